@@ -1,7 +1,11 @@
 use siertrichain::mining::config::{MiningConfig, HardwareSelection};
 use siertrichain::mining::miner::Miner;
 use siertrichain::core::blockchain::Blockchain;
+use siertrichain::core::sqlite_storage::SqliteStorage;
+use siertrichain::wallet::keypair;
 use clap::{Parser, ValueEnum};
+use ed25519_dalek::Keypair;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about = "SierpinskiChain Miner CLI")]
@@ -11,7 +15,7 @@ struct Cli {
     difficulty: u64,
 
     /// Target triangle depth
-    #[clap(short, long, default_value = "10")]
+    #[clap(short = 'p', long, default_value = "10")]
     depth: u32,
 
     /// Mining reward
@@ -19,8 +23,16 @@ struct Cli {
     reward: u64,
 
     /// Hardware selection (cpu/gpu)
-    #[clap(short, long, value_enum, default_value = "cpu")]
+    #[clap(short = 'w', long, value_enum, default_value = "cpu")]
     hardware: HardwareArg,
+
+    /// Directory holding the chain database
+    #[clap(long, default_value = "data")]
+    data_dir: PathBuf,
+
+    /// Number of blocks to mine before exiting
+    #[clap(short, long, default_value = "1")]
+    blocks: u64,
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -38,6 +50,16 @@ impl From<HardwareArg> for HardwareSelection {
     }
 }
 
+/// Loads the key block rewards are paid to, creating it on first use.
+fn load_or_create_keypair(path: &Path) -> Keypair {
+    if let Ok(bytes) = std::fs::read(path) {
+        return keypair::from_bytes(&bytes).expect("invalid miner key file");
+    }
+    let new_keypair = Keypair::generate(&mut rand::thread_rng());
+    std::fs::write(path, keypair::to_bytes(&new_keypair)).expect("failed to write miner key file");
+    new_keypair
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -48,13 +70,28 @@ fn main() {
         hardware: cli.hardware.into(),
     };
 
-    let blockchain = Blockchain::new();
-    let mut miner = Miner::new(config, blockchain);
+    std::fs::create_dir_all(&cli.data_dir).expect("failed to create data directory");
+    let storage = SqliteStorage::open(cli.data_dir.join("chain.sqlite")).expect("failed to open chain database");
+    let blockchain = Blockchain::open(storage, config.difficulty_target).expect("failed to load chain");
 
     println!(
-        "Starting miner with difficulty {}, depth {}, reward {}, hardware {:?}...",
-        config.difficulty_target, config.target_triangle_depth, config.mining_reward, config.hardware
+        "Starting miner with difficulty {}, depth {}, reward {}, hardware {:?} at height {}...",
+        config.difficulty_target,
+        config.target_triangle_depth,
+        config.mining_reward,
+        config.hardware,
+        blockchain.latest_header().height,
     );
 
-    let block = miner.mine();
-    println!("Mined new block: {:?}",
\ No newline at end of file
+    let reward_keypair = load_or_create_keypair(&cli.data_dir.join("miner.key"));
+    println!("Paying block rewards to {}", hex::encode(reward_keypair.public.as_bytes()));
+
+    let mut miner = Miner::new(config, blockchain, reward_keypair);
+    for _ in 0..cli.blocks {
+        let block = miner.mine().expect("failed to mine block");
+        let hash = block.hash();
+        let height = block.header.height;
+        miner.blockchain_mut().add_block(block).expect("mined block was rejected");
+        println!("Mined new block {:?} at height {}", hash, height);
+    }
+}
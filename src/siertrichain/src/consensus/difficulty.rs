@@ -1,27 +1,255 @@
-use crate::core::blockchain::Blockchain;
+//! Proof-of-work targets and per-block retargeting.
+//!
+//! A block is valid when its hash, read as a big-endian 256-bit number, is
+//! at most the target encoded in the header's compact `bits`. The target of
+//! every block is derived from its ancestors with a linearly weighted moving
+//! average (LWMA) of their solve times, so the chain reacts to hash-rate
+//! changes within a few blocks instead of once per interval.
 
-const TARGET_BLOCK_TIME: u64 = 60; // 1 minute
-const DIFFICULTY_ADJUSTMENT_INTERVAL: u64 = 10; // 10 blocks
+use crate::core::block::BlockHeader;
+use crate::core::hash::H256;
 
-pub fn adjust_difficulty(blockchain: &Blockchain) -> u64 {
-    let latest_block = blockchain.latest_block();
-    if latest_block.header.height % DIFFICULTY_ADJUSTMENT_INTERVAL != 0 {
-        return blockchain.get_difficulty();
+#[allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+mod uint256 {
+    uint::construct_uint! {
+        /// Unsigned 256-bit integer for targets and chain work.
+        pub struct U256(4);
     }
+}
 
-    let previous_adjustment_block = blockchain.get_block_by_height(latest_block.header.height - DIFFICULTY_ADJUSTMENT_INTERVAL).unwrap();
+pub use uint256::U256;
 
-    let time_taken = latest_block.header.timestamp - previous_adjustment_block.header.timestamp;
-    let expected_time = (DIFFICULTY_ADJUSTMENT_INTERVAL * TARGET_BLOCK_TIME) as i64;
+/// Seconds the retargeting aims for between blocks.
+pub const TARGET_BLOCK_TIME: i64 = 60;
 
-    let new_difficulty = if time_taken < expected_time / 2 {
-        blockchain.get_difficulty() * 2
-    } else if time_taken > expected_time * 2 {
-        blockchain.get_difficulty() / 2
+/// Number of past blocks the moving average looks at.
+pub const LWMA_WINDOW: usize = 45;
+
+/// Compact form of the easiest allowed target, which is also the target of
+/// difficulty 1.
+pub const POW_LIMIT_BITS: u32 = 0x2100_ffff;
+
+/// The easiest allowed target.
+pub fn pow_limit() -> U256 {
+    target_from_compact(POW_LIMIT_BITS).expect("the limit is a valid compact target")
+}
+
+/// Decodes Bitcoin-style compact bits: the top byte is the length of the
+/// target in bytes and the low 23 bits are its leading digits. Negative,
+/// zero and overflowing encodings are rejected.
+pub fn target_from_compact(bits: u32) -> Option<U256> {
+    let size = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 || mantissa == 0 {
+        return None;
+    }
+    let target = if size <= 3 {
+        U256::from(mantissa >> (8 * (3 - size)))
     } else {
-        blockchain.get_difficulty()
+        let shift = 8 * (size - 3) as usize;
+        if shift + (32 - mantissa.leading_zeros() as usize) > 256 {
+            return None;
+        }
+        U256::from(mantissa) << shift
     };
+    if target.is_zero() {
+        None
+    } else {
+        Some(target)
+    }
+}
+
+/// Encodes `target` in compact form, truncating it to its three leading
+/// bytes.
+pub fn target_to_compact(target: U256) -> u32 {
+    let mut size = (target.bits() as u32).div_ceil(8);
+    let mut mantissa = if size <= 3 {
+        target.low_u64() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3) as usize)).low_u64()
+    } as u32;
+    // The sign bit must stay clear, so move one byte into the exponent.
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    (size << 24) | mantissa
+}
+
+/// Target at which a block takes `difficulty` times as many hashes as at the
+/// limit.
+pub fn difficulty_to_target(difficulty: u64) -> U256 {
+    pow_limit() / U256::from(difficulty.max(1))
+}
+
+/// Expected number of hashes needed to meet the target in `bits`, or zero if
+/// the bits are invalid. Summed along a branch this is its chain work.
+pub fn block_work(bits: u32) -> U256 {
+    match target_from_compact(bits) {
+        // 2^256 / (target + 1), without leaving 256 bits.
+        Some(target) => (!target / (target + 1)) + 1,
+        None => U256::zero(),
+    }
+}
+
+/// Whether `hash` meets the target encoded in `bits`.
+pub fn meets_target(hash: &H256, bits: u32) -> bool {
+    match target_from_compact(bits) {
+        Some(target) => U256::from_big_endian(&hash.to_bytes()) <= target,
+        None => false,
+    }
+}
+
+/// Compact target of the block that follows `window`, the last ancestors of
+/// that block in ascending height order, ending with its parent. At most
+/// `LWMA_WINDOW + 1` of them are used.
+///
+/// Each solve time is weighted by its position so recent blocks count most.
+/// Timestamps are clamped so that every solve time is between one second
+/// and six target block times, which keeps a single out-of-order or forged
+/// timestamp from swinging the target.
+pub fn next_bits(window: &[&BlockHeader]) -> u32 {
+    let window = &window[window.len().saturating_sub(LWMA_WINDOW + 1)..];
+    let parent = match window.last() {
+        Some(parent) => parent,
+        None => return POW_LIMIT_BITS,
+    };
+    let blocks = window.len() - 1;
+    if blocks == 0 {
+        return parent.bits;
+    }
+
+    let limit = pow_limit();
+    let mut previous_timestamp = window[0].timestamp;
+    let mut weighted_solve_times: u64 = 0;
+    // Summed as fractions of the average, as the sum of targets near the
+    // limit does not fit in 256 bits.
+    let mut average = U256::zero();
+    for (weight, header) in window[1..].iter().enumerate() {
+        let timestamp = header.timestamp.max(previous_timestamp + 1);
+        let solve_time = (timestamp - previous_timestamp).min(6 * TARGET_BLOCK_TIME);
+        previous_timestamp = timestamp;
+        weighted_solve_times += (weight as u64 + 1) * solve_time as u64;
+        average += target_from_compact(header.bits).unwrap_or(limit) / U256::from(blocks);
+    }
+
+    // next = average target * weighted solve times / (weights * block time)
+    let expected = U256::from(blocks * (blocks + 1) / 2) * U256::from(TARGET_BLOCK_TIME);
+    let scaled = U256::from(weighted_solve_times);
+    let next = (average / expected)
+        .checked_mul(scaled)
+        .and_then(|whole| whole.checked_add(average % expected * scaled / expected));
+    match next {
+        Some(next) if next <= limit && !next.is_zero() => target_to_compact(next),
+        Some(next) if next.is_zero() => target_to_compact(U256::one()),
+        _ => POW_LIMIT_BITS,
+    }
+}
 
-    // Ensure difficulty doesn't go to zero or become excessively large.
-    new_difficulty.max(1).min(u64::MAX / 1000)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::TriangleAddress;
+
+    fn header(timestamp: i64, bits: u32) -> BlockHeader {
+        BlockHeader {
+            version: crate::core::block::BLOCK_VERSION,
+            previous_hash: H256::default(),
+            merkle_root: H256::default(),
+            timestamp,
+            nonce: 0,
+            bits,
+            base_fee: crate::consensus::fees::INITIAL_BASE_FEE,
+            transaction_count: 0,
+            height: 0,
+            geometric_proof: TriangleAddress::root(),
+        }
+    }
+
+    /// Mines `blocks` blocks with a deterministic solve time of
+    /// `work / hash_rate` seconds and returns the chain.
+    fn simulate(mut chain: Vec<BlockHeader>, hash_rate: u64, blocks: usize) -> Vec<BlockHeader> {
+        for _ in 0..blocks {
+            let window: Vec<&BlockHeader> = chain.iter().collect();
+            let bits = next_bits(&window);
+            let solve_time = (block_work(bits) / U256::from(hash_rate)).low_u64() as i64;
+            chain.push(header(chain.last().unwrap().timestamp + solve_time, bits));
+        }
+        chain
+    }
+
+    fn average_solve_time(chain: &[BlockHeader], blocks: usize) -> i64 {
+        let recent = &chain[chain.len() - blocks - 1..];
+        (recent.last().unwrap().timestamp - recent[0].timestamp) / blocks as i64
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        assert_eq!(pow_limit(), U256::from(0xffff) << 240);
+        assert_eq!(target_to_compact(pow_limit()), POW_LIMIT_BITS);
+        assert_eq!(target_from_compact(0x1d00_ffff), Some(U256::from(0xffff) << 208));
+        assert_eq!(target_to_compact(U256::from(0x80)), 0x0200_8000);
+        assert_eq!(target_from_compact(0x0200_8000), Some(U256::from(0x80)));
+
+        assert_eq!(target_from_compact(0x0480_0000), None);
+        assert_eq!(target_from_compact(0x2200_ffff), None);
+        assert_eq!(target_from_compact(0x2100_0000), None);
+    }
+
+    #[test]
+    fn test_work_grows_with_difficulty() {
+        assert_eq!(block_work(POW_LIMIT_BITS), U256::one());
+        let work = block_work(target_to_compact(difficulty_to_target(1_000_000)));
+        assert!(work >= U256::from(1_000_000) && work < U256::from(1_001_000));
+        assert_eq!(block_work(0), U256::zero());
+    }
+
+    #[test]
+    fn test_hash_is_compared_as_big_endian_number() {
+        let bits = target_to_compact(U256::from(1) << 248);
+        let mut bytes = [0u8; 32];
+        bytes[0] = 1;
+        assert!(meets_target(&H256::from(bytes), bits));
+        bytes[31] = 1;
+        assert!(!meets_target(&H256::from(bytes), bits));
+    }
+
+    #[test]
+    fn test_steady_hash_rate_keeps_target() {
+        let bits = target_to_compact(difficulty_to_target(6_000));
+        let chain = simulate(vec![header(0, bits)], 100, 100);
+        let target = target_from_compact(chain.last().unwrap().bits).unwrap();
+        let initial = target_from_compact(bits).unwrap();
+        assert!(target > initial / 100 * 99 && target < initial / 100 * 101);
+        assert_eq!(average_solve_time(&chain, 50), TARGET_BLOCK_TIME);
+    }
+
+    #[test]
+    fn test_follows_hash_rate_swings() {
+        let bits = target_to_compact(difficulty_to_target(6_000));
+        let steady = simulate(vec![header(0, bits)], 100, 50);
+
+        // Ten times the hash rate: blocks come fast until the target drops.
+        let surge = simulate(steady, 1_000, 150);
+        assert!(average_solve_time(&surge[..60], 5) < TARGET_BLOCK_TIME / 2);
+        assert!((average_solve_time(&surge, 50) - TARGET_BLOCK_TIME).abs() <= 3);
+
+        // Back to a tenth: blocks are slow until the target recovers.
+        let drop = simulate(surge, 100, 150);
+        assert!(average_solve_time(&drop[..210], 5) > TARGET_BLOCK_TIME * 2);
+        assert!((average_solve_time(&drop, 50) - TARGET_BLOCK_TIME).abs() <= 3);
+    }
+
+    #[test]
+    fn test_out_of_order_timestamps_are_clamped() {
+        let bits = target_to_compact(difficulty_to_target(6_000));
+        let mut chain = simulate(vec![header(0, bits)], 100, 20);
+        let last = chain.last().unwrap().timestamp;
+        chain.push(header(last - 10_000, bits));
+        chain.push(header(last + 10_000, bits));
+        let window: Vec<&BlockHeader> = chain.iter().collect();
+        let next = target_from_compact(next_bits(&window)).unwrap();
+        let initial = target_from_compact(bits).unwrap();
+        assert!(next < initial * 2 && next > initial / 2);
+    }
+}
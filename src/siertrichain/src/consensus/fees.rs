@@ -0,0 +1,80 @@
+//! Base fee of each block.
+//!
+//! Every transaction in a block must pay at least the base fee recorded in
+//! the block header; that part of its fee is burned and only the rest goes
+//! to the miner. The base fee follows demand: it rises after blocks with more
+//! than `TARGET_BLOCK_TRANSACTIONS` transactions and falls after emptier
+//! ones, by at most an eighth per block.
+
+use crate::core::block::BlockHeader;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// Base fee of the genesis block.
+pub const INITIAL_BASE_FEE: Decimal = dec!(0.001);
+
+/// The base fee never drops below this, so it can always rise again.
+pub const MIN_BASE_FEE: Decimal = dec!(0.0001);
+
+/// Number of transactions per block, besides the coinbase, at which the base
+/// fee stays put.
+pub const TARGET_BLOCK_TRANSACTIONS: u32 = 100;
+
+/// Bounds the change of the base fee per block to `1 / 8`.
+pub const BASE_FEE_CHANGE_DENOMINATOR: u32 = 8;
+
+/// Decimal places the base fee is rounded to.
+const BASE_FEE_SCALE: u32 = 12;
+
+/// Base fee of the block that follows `parent`. Blocks beyond twice the
+/// target count as twice the target.
+pub fn next_base_fee(parent: &BlockHeader) -> Decimal {
+    let target = Decimal::from(TARGET_BLOCK_TRANSACTIONS);
+    let used = Decimal::from(parent.transaction_count.min(2 * TARGET_BLOCK_TRANSACTIONS));
+    let change = parent.base_fee * (used - target) / target / Decimal::from(BASE_FEE_CHANGE_DENOMINATOR);
+    (parent.base_fee + change).round_dp(BASE_FEE_SCALE).max(MIN_BASE_FEE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::TriangleAddress;
+    use crate::core::block::BLOCK_VERSION;
+    use crate::core::hash::H256;
+
+    fn header(base_fee: Decimal, transaction_count: u32) -> BlockHeader {
+        BlockHeader {
+            version: BLOCK_VERSION,
+            previous_hash: H256::default(),
+            merkle_root: H256::default(),
+            timestamp: 0,
+            nonce: 0,
+            bits: 0,
+            base_fee,
+            transaction_count,
+            height: 0,
+            geometric_proof: TriangleAddress::root(),
+        }
+    }
+
+    #[test]
+    fn test_base_fee_follows_fullness() {
+        assert_eq!(next_base_fee(&header(dec!(1), TARGET_BLOCK_TRANSACTIONS)), dec!(1));
+        assert_eq!(next_base_fee(&header(dec!(1), 2 * TARGET_BLOCK_TRANSACTIONS)), dec!(1.125));
+        assert_eq!(next_base_fee(&header(dec!(1), 10 * TARGET_BLOCK_TRANSACTIONS)), dec!(1.125));
+        assert_eq!(next_base_fee(&header(dec!(1), 0)), dec!(0.875));
+        assert_eq!(next_base_fee(&header(dec!(1), 150)), dec!(1.0625));
+    }
+
+    #[test]
+    fn test_base_fee_stays_above_minimum() {
+        let mut parent = header(INITIAL_BASE_FEE, 0);
+        for _ in 0..100 {
+            parent.base_fee = next_base_fee(&parent);
+        }
+        assert_eq!(parent.base_fee, MIN_BASE_FEE);
+
+        parent.transaction_count = 2 * TARGET_BLOCK_TRANSACTIONS;
+        assert!(next_base_fee(&parent) > MIN_BASE_FEE);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod rules;
 pub mod difficulty;
+pub mod fees;
+pub mod signatures;
+pub mod weight;
@@ -1,45 +1,159 @@
-use crate::core::block::Block;
+use crate::consensus::difficulty::{meets_target, TARGET_BLOCK_TIME};
+use crate::core::address::TriangleAddress;
+use crate::core::block::{Block, BlockHeader};
 use crate::core::blockchain::Blockchain;
+use crate::core::errors::ValidationError;
+use crate::core::state::{TriangleRecord, WorldState};
+use crate::core::storage::{BlockStorage, TransactionStorage};
+use crate::core::merkle::MerkleTree;
+use crate::core::tokenomics::BlockReward;
+use crate::core::transaction::{TriangleOperation, CHAIN_ID};
 use crate::core::triangle::Triangle;
-use rust_decimal::Decimal;
+use crate::core::subdivision::subdivide_triangle;
+use crate::consensus::signatures::verify_signatures;
+use crate::consensus::weight::{block_weight, MAX_BLOCK_WEIGHT};
+use crate::mining::verification::fast_verify;
+use std::collections::HashSet;
+
+/// Number of ancestors whose median timestamp a block must exceed.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far ahead of the local clock a block may be stamped, in seconds.
+/// Kept to a few block times so that forged timestamps cannot skew the
+/// retargeting for long.
+pub const MAX_FUTURE_DRIFT: i64 = 10 * TARGET_BLOCK_TIME;
+
+pub fn validate_block<S: BlockStorage + TransactionStorage>(
+    block: &Block,
+    blockchain: &Blockchain<S>,
+) -> Result<(), ValidationError> {
+    let header = &block.header;
 
-pub fn validate_block(block: &Block, blockchain: &Blockchain) -> bool {
     // 1. Check if the previous block exists
-    let prev_block = match blockchain.get_block(&block.header.previous_hash) {
-        Some(b) => b,
-        None => return false, // Previous block not found
-    };
+    let prev_header = blockchain
+        .get_header(&header.previous_hash)
+        .ok_or(ValidationError::UnknownParent(header.previous_hash))?;
+    if header.height != prev_header.height + 1 {
+        return Err(ValidationError::BadHeight { expected: prev_header.height + 1, found: header.height });
+    }
 
-    // 2. Validate block timestamp
-    if block.header.timestamp <= prev_block.header.timestamp {
-        return false;
+    // 2. Validate block timestamp against the past and the local clock
+    let median_time_past = blockchain.median_time_past(&header.previous_hash);
+    if header.timestamp <= median_time_past {
+        return Err(ValidationError::TimestampTooEarly { timestamp: header.timestamp, median_time_past });
+    }
+    let now = blockchain.clock().now();
+    if header.timestamp > now + MAX_FUTURE_DRIFT {
+        return Err(ValidationError::TimestampTooFarInFuture { timestamp: header.timestamp, now });
     }
 
-    // 3. Check proof-of-work
-    if !is_valid_proof_of_work(block, blockchain.get_difficulty()) {
-        return false;
+    // 3. Check proof-of-work against the target required after the parent
+    let expected_bits = blockchain.next_bits(&header.previous_hash);
+    if header.bits != expected_bits {
+        return Err(ValidationError::WrongBits { expected: expected_bits, found: header.bits });
+    }
+    fast_verify(block)?;
+    // Only the ledger of the active tip is at hand. Blocks on other branches
+    // are checked against their parent's ledger when they are connected.
+    if header.previous_hash == blockchain.tip() {
+        validate_geometric_proof(&header.geometric_proof, blockchain.state())?;
     }
 
     // 4. Validate Merkle root
+    let merkle_root = MerkleTree::new(&block.triangle_transactions).get_root();
+    if merkle_root != header.merkle_root {
+        return Err(ValidationError::MerkleRootMismatch { expected: merkle_root, found: header.merkle_root });
+    }
+
+    // 5. Bound the work the block asks of every node
+    let weight = block_weight(block);
+    if weight > MAX_BLOCK_WEIGHT {
+        return Err(ValidationError::BlockTooHeavy { weight, max: MAX_BLOCK_WEIGHT });
+    }
 
-    // 5. Validate all transactions in the block
-    for tx in &block.triangle_transactions {
-        if !tx.validate() {
-            return false;
+    // 6. No transaction may appear twice. The Merkle tree pairs the last node
+    // of an odd level with itself, so repeating the last transactions keeps
+    // the root.
+    let mut seen = HashSet::with_capacity(block.triangle_transactions.len());
+    if let Some(index) = block.triangle_transactions.iter().position(|tx| !seen.insert(*tx.hash())) {
+        return Err(ValidationError::DuplicateTransaction(index));
+    }
+
+    // 7. Check the base fee and the transaction count the next one follows from
+    let expected_base_fee = blockchain.next_base_fee(&header.previous_hash);
+    if header.base_fee != expected_base_fee {
+        return Err(ValidationError::WrongBaseFee { expected: expected_base_fee, found: header.base_fee });
+    }
+    let transaction_count = block.transaction_count();
+    if header.transaction_count != transaction_count {
+        return Err(ValidationError::BadTransactionCount { expected: transaction_count, found: header.transaction_count });
+    }
+
+    // 8. The first transaction, and only that one, pays the exact block reward
+    let expected_amount = BlockReward::for_block(block).coinbase_amount();
+    match block.triangle_transactions.first().map(|tx| &tx.operation) {
+        Some(TriangleOperation::Coinbase { amount, height, .. }) => {
+            if *amount != expected_amount || *height != header.height {
+                return Err(ValidationError::BadCoinbase { expected_amount, expected_height: header.height });
+            }
+        }
+        _ => return Err(ValidationError::MissingCoinbase),
+    }
+    if let Some(index) = block.triangle_transactions.iter().skip(1).position(|tx| tx.is_coinbase()) {
+        return Err(ValidationError::UnexpectedCoinbase(index + 1));
+    }
+
+    // 9. Validate all transactions in the block
+    for (index, tx) in block.triangle_transactions.iter().enumerate() {
+        if tx.chain_id != CHAIN_ID {
+            return Err(ValidationError::WrongChain { index, chain_id: tx.chain_id });
+        }
+        if let Some(expiry) = tx.expiry.filter(|expiry| header.height > *expiry) {
+            return Err(ValidationError::Expired { index, expiry });
+        }
+        if !tx.is_coinbase() && tx.fee < header.base_fee {
+            return Err(ValidationError::FeeBelowBaseFee { index, fee: tx.fee, base_fee: header.base_fee });
         }
     }
+    verify_signatures(&block.triangle_transactions).map_err(ValidationError::InvalidSignature)?;
 
-    true
+    Ok(())
 }
 
-fn is_valid_proof_of_work(block: &Block, difficulty: u64) -> bool {
-    let target = u64::MAX / difficulty;
-    let hash_value = u64::from_le_bytes(block.hash().to_bytes()[..8].try_into().unwrap());
-    hash_value < target
+/// Checks that `proof` is a triangle miners can claim in `state`, the ledger
+/// as of the block's parent: one that lies below an active triangle, or the
+/// root once no active triangle can be subdivided any further, and that no
+/// earlier block mined. The reward halves with every level, so miners claim
+/// the shallowest triangles left.
+pub fn validate_geometric_proof(proof: &TriangleAddress, state: &WorldState) -> Result<(), ValidationError> {
+    if state.is_claimed(proof) {
+        return Err(ValidationError::ProofAlreadyClaimed(proof.clone()));
+    }
+    let claimable = match proof.parent() {
+        // The nearest ancestor in the ledger must be an active leaf: below a
+        // subdivided triangle the ledger records every child and the void.
+        Some(parent) => std::iter::successors(Some(parent), TriangleAddress::parent)
+            .find_map(|ancestor| state.get(&ancestor))
+            .is_some_and(TriangleRecord::is_spendable),
+        None => state.active_leaves().all(|leaf| leaf.child(0).is_err()),
+    };
+    if !claimable {
+        return Err(ValidationError::UnclaimableProof(proof.clone()));
+    }
+    Ok(())
 }
 
-pub fn validate_triangle_subdivision(parent: &Triangle, child: &Triangle) -> bool {
-    // For now, a simple area check will suffice.
-    // A more robust solution would check the coordinates of the child triangle vertices.
-    (parent.area() / Decimal::new(4, 0) - child.area()).abs() < Decimal::new(1, 9)
+/// Checks the header hash against the target in its own bits.
+pub fn is_valid_proof_of_work(header: &BlockHeader) -> bool {
+    meets_target(&header.hash(), header.bits)
+}
+
+/// Checks that `child` is exactly one of the three triangles obtained by
+/// subdividing `parent`. Block validation goes further and checks all three
+/// children in order against the parent recorded in the ledger.
+pub fn validate_triangle_subdivision(parent: &Triangle, child: &Triangle) -> Result<(), ValidationError> {
+    match subdivide_triangle(parent) {
+        Ok((c1, c2, c3)) if [&c1, &c2, &c3].contains(&child) => Ok(()),
+        _ => Err(ValidationError::GeometryMismatch),
+    }
 }
@@ -0,0 +1,210 @@
+//! Signature checks for whole blocks.
+//!
+//! A block is valid only if `Transaction::validate`, which uses
+//! `verify_strict`, accepts every transaction. Signatures are verified in
+//! ed25519 batches spread over all cores, and a batch that fails is checked
+//! again one signature at a time to name the offender.
+//!
+//! A batch tests a random combination of the signature equations, so it may
+//! only see signatures for which that combination holds exactly when every
+//! equation does. Each transaction is screened first: a non-canonical `s`,
+//! an `R` that does not decode and a small-order `R` or key are rejected, as
+//! `verify_strict` rejects them. Points with a torsion component could cancel
+//! each other out in the combination, so a transaction whose `R` or key has
+//! one is verified on its own.
+
+use crate::core::transaction::Transaction;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::{PublicKey, Signature};
+use rayon::prelude::*;
+
+/// Signatures verified together in one batch.
+const BATCH_SIZE: usize = 64;
+
+/// The outcome of screening one transaction.
+enum Screened {
+    Rejected,
+    Accepted,
+    /// Safe to verify in a batch, with the bytes its signature covers.
+    Batch(Vec<u8>),
+}
+
+/// Checks every signature and cached hash in `transactions`, as
+/// `Transaction::validate` would, and returns the index of the first
+/// transaction that fails.
+pub fn verify_signatures(transactions: &[Transaction]) -> Result<(), usize> {
+    let screened: Vec<Screened> = transactions.par_iter().map(screen).collect();
+    let rejected = screened.iter().position(|s| matches!(s, Screened::Rejected));
+    let batchable: Vec<(usize, &[u8])> = screened
+        .iter()
+        .enumerate()
+        .filter_map(|(index, s)| match s {
+            Screened::Batch(payload) => Some((index, payload.as_slice())),
+            _ => None,
+        })
+        .collect();
+    let failed = batchable
+        .par_chunks(BATCH_SIZE)
+        .filter_map(|chunk| first_failure(transactions, chunk))
+        .min();
+    match rejected.into_iter().chain(failed).min() {
+        Some(index) => Err(index),
+        None => Ok(()),
+    }
+}
+
+/// Rejects what `verify_strict` rejects before the equation is checked, and
+/// decides whether the rest can go into a batch.
+fn screen(tx: &Transaction) -> Screened {
+    let Some(payload) = tx.signed_payload() else {
+        return Screened::Rejected;
+    };
+    let signature = tx.signature.to_bytes();
+    let mut s = [0u8; 32];
+    s.copy_from_slice(&signature[32..]);
+    let r = CompressedEdwardsY::from_slice(&signature[..32]).decompress();
+    let key = CompressedEdwardsY(tx.public_key.to_bytes()).decompress();
+    let (Some(r), Some(key)) = (r, key) else {
+        return Screened::Rejected;
+    };
+    if Scalar::from_canonical_bytes(s).is_none() || r.is_small_order() || key.is_small_order() {
+        return Screened::Rejected;
+    }
+    if r.is_torsion_free() && key.is_torsion_free() {
+        return Screened::Batch(payload);
+    }
+    match tx.public_key.verify_strict(&payload, &tx.signature) {
+        Ok(()) => Screened::Accepted,
+        Err(_) => Screened::Rejected,
+    }
+}
+
+/// Verifies `chunk` as one batch and, only if that fails, each signature on
+/// its own. Returns the index of the first bad one.
+fn first_failure(transactions: &[Transaction], chunk: &[(usize, &[u8])]) -> Option<usize> {
+    let messages: Vec<&[u8]> = chunk.iter().map(|(_, payload)| *payload).collect();
+    let signatures: Vec<Signature> = chunk.iter().map(|(index, _)| transactions[*index].signature).collect();
+    let keys: Vec<PublicKey> = chunk.iter().map(|(index, _)| transactions[*index].public_key).collect();
+    if ed25519_dalek::verify_batch(&messages, &signatures, &keys).is_ok() {
+        return None;
+    }
+    chunk
+        .iter()
+        .find(|(index, payload)| {
+            let tx = &transactions[*index];
+            tx.public_key.verify_strict(payload, &tx.signature).is_err()
+        })
+        .map(|(index, _)| *index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::TriangleAddress;
+    use crate::core::encoding::{Decode, Encode};
+    use crate::core::transaction::TriangleOperation;
+    use curve25519_dalek::constants::EIGHT_TORSION;
+    use ed25519_dalek::{ExpandedSecretKey, Keypair, Verifier};
+
+    fn transactions(count: usize) -> Vec<Transaction> {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        (0..count).map(|nonce| transfer(&keypair, nonce as u64)).collect()
+    }
+
+    fn transfer(keypair: &Keypair, nonce: u64) -> Transaction {
+        let operation = TriangleOperation::Transfer { from: TriangleAddress::root(), to: keypair.public };
+        Transaction::new(operation, nonce, keypair)
+    }
+
+    /// The single-signature rule the batches must agree with.
+    fn verify_one_by_one(transactions: &[Transaction]) -> Result<(), usize> {
+        match transactions.iter().position(|tx| !tx.validate()) {
+            Some(index) => Err(index),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the key and signature at the end of an encoded transaction.
+    fn with_key_and_signature(tx: &Transaction, key: &[u8; 32], signature: &[u8; 64]) -> Transaction {
+        let mut bytes = tx.encode();
+        let len = bytes.len();
+        bytes[len - 96..len - 64].copy_from_slice(key);
+        bytes[len - 64..].copy_from_slice(signature);
+        Transaction::decode(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_valid_transactions_pass() {
+        assert_eq!(verify_signatures(&[]), Ok(()));
+        assert_eq!(verify_signatures(&transactions(3)), Ok(()));
+        assert_eq!(verify_signatures(&transactions(517)), Ok(()));
+    }
+
+    #[test]
+    fn test_names_first_offender() {
+        let mut txs = transactions(517);
+        let other = transactions(1).remove(0);
+        txs[263].signature = other.signature;
+        txs[513].signature = other.signature;
+        assert_eq!(verify_signatures(&txs), Err(263));
+
+        // A swapped key with a matching signature still fails on the hash.
+        let mut forged = transactions(8);
+        forged[3].public_key = other.public_key;
+        forged[3].signature = other.signature;
+        assert_eq!(verify_signatures(&forged), Err(3));
+    }
+
+    #[test]
+    fn test_small_order_key_agrees_with_single_verification() {
+        // The identity point as the key, and as R with s = 0, satisfies the
+        // plain verification equation for any message.
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&identity);
+        let forged = with_key_and_signature(&transactions(1)[0], &identity, &signature);
+        let payload = forged.signed_payload().unwrap();
+        assert!(forged.public_key.verify(&payload, &forged.signature).is_ok());
+
+        let alone = std::slice::from_ref(&forged);
+        assert_eq!(verify_signatures(alone), Err(0));
+        assert_eq!(verify_signatures(alone), verify_one_by_one(alone));
+        let mut block = transactions(300);
+        block[290] = forged;
+        assert_eq!(verify_signatures(&block), Err(290));
+        assert_eq!(verify_signatures(&block), verify_one_by_one(&block));
+    }
+
+    #[test]
+    fn test_mixed_order_key_agrees_with_single_verification() {
+        // A key with an order-8 component: a signature made with the real
+        // secret passes `verify_strict` only when the challenge clears the
+        // torsion, about one time in eight.
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let point = CompressedEdwardsY(keypair.public.to_bytes()).decompress().unwrap();
+        let mixed = (point + EIGHT_TORSION[1]).compress().to_bytes();
+        let mixed_key = PublicKey::from_bytes(&mixed).unwrap();
+        let secret = ExpandedSecretKey::from(&keypair.secret);
+        let signed = |nonce| {
+            let unsigned = with_key_and_signature(&transfer(&keypair, nonce), &mixed, &[0; 64]);
+            let payload = unsigned.signed_payload().unwrap();
+            with_key_and_signature(&unsigned, &mixed, &secret.sign(&payload, &mixed_key).to_bytes())
+        };
+        let (accepted, rejected): (Vec<Transaction>, Vec<Transaction>) =
+            (0..200).map(signed).partition(Transaction::validate);
+        assert!(!accepted.is_empty() && !rejected.is_empty());
+
+        for forged in [&accepted[0], &rejected[0]] {
+            let mut block = transactions(300);
+            block[150] = forged.clone();
+            assert_eq!(verify_signatures(&block), verify_one_by_one(&block));
+        }
+        let mut block = transactions(100);
+        block.extend(accepted.iter().cloned());
+        assert_eq!(verify_signatures(&block), Ok(()));
+        block.push(rejected[0].clone());
+        assert_eq!(verify_signatures(&block), Err(block.len() - 1));
+    }
+}
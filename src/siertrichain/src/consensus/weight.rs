@@ -0,0 +1,69 @@
+//! Block weight.
+//!
+//! A transaction weighs its encoded size in bytes plus a fixed cost for its
+//! operation, standing for the ledger work it causes: a subdivision checks
+//! the exact geometry of three children and writes four records, while a
+//! transfer rewrites a single one and a DeFi operation updates its module's
+//! books and a few balances. A block weighs the sum of its
+//! transactions, coinbase included, and may weigh at most
+//! `MAX_BLOCK_WEIGHT`. The header is small and bounded, so it is not
+//! counted.
+
+use crate::core::block::Block;
+use crate::core::encoding::Encode;
+use crate::core::transaction::{Transaction, TriangleOperation};
+
+/// Consensus limit on the weight of a block. Room for a few thousand
+/// transfers, well above `fees::TARGET_BLOCK_TRANSACTIONS`.
+pub const MAX_BLOCK_WEIGHT: u64 = 1_000_000;
+
+/// Fixed cost of each kind of operation, on top of its size.
+pub fn operation_cost(operation: &TriangleOperation) -> u64 {
+    match operation {
+        TriangleOperation::Coinbase { .. } => 100,
+        TriangleOperation::TokenTransfer { .. } => 100,
+        TriangleOperation::Transfer { .. } => 200,
+        TriangleOperation::Defi(_) => 300,
+        TriangleOperation::Create { .. } => 500,
+        TriangleOperation::Merge { .. } => 800,
+        TriangleOperation::Subdivide { .. } => 1_000,
+    }
+}
+
+pub fn transaction_weight(tx: &Transaction) -> u64 {
+    tx.encode().len() as u64 + operation_cost(&tx.operation)
+}
+
+/// Saturates instead of overflowing, so any oversized block stays over the
+/// limit.
+pub fn block_weight(block: &Block) -> u64 {
+    block
+        .triangle_transactions
+        .iter()
+        .fold(0u64, |weight, tx| weight.saturating_add(transaction_weight(tx)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::TriangleAddress;
+    use crate::core::genesis::{genesis_keypair, GenesisTriangle};
+    use crate::core::hash::H256;
+    use crate::core::subdivision::subdivide_triangle;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_subdivide_weighs_more_than_transfer() {
+        let keypair = genesis_keypair();
+        let root = TriangleAddress::root();
+        let transfer = Transaction::new(TriangleOperation::Transfer { from: root.clone(), to: keypair.public }, 0, &keypair);
+        let (a, b, c) = subdivide_triangle(&GenesisTriangle::new()).unwrap();
+        let subdivide = Transaction::new(TriangleOperation::Subdivide { parent: root, children: [a, b, c] }, 0, &keypair);
+        assert_eq!(transaction_weight(&transfer), transfer.encode().len() as u64 + 200);
+        assert!(transaction_weight(&subdivide) > transaction_weight(&transfer) + 800);
+
+        let coinbase = Transaction::new_coinbase(Decimal::ONE, 1, &keypair);
+        let block = Block::new(H256::default(), H256::default(), 0, 0, Decimal::ZERO, 1, vec![coinbase.clone(), transfer.clone()]);
+        assert_eq!(block_weight(&block), transaction_weight(&coinbase) + transaction_weight(&transfer));
+    }
+}
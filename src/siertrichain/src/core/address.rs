@@ -1,46 +1,266 @@
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::str::FromStr;
 
+use crate::core::barycentric::MAX_DEPTH;
 use crate::core::errors::TriangleError;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Position of a triangle in the Sierpinski tree: the child index (0, 1 or 2)
+/// taken at each level below the root. Index 3 (`VOID_INDEX`) names the
+/// central triangle removed by a subdivision; it may only be the last index.
+///
+/// The path is packed two bits per level, first level in the highest bits.
+/// Comparing the packed bits and then the depth orders addresses depth-first:
+/// a triangle sorts right before its descendants, and siblings by index.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TriangleAddress {
-    path: Vec<u8>,
+    bits: u128,
+    depth: u8,
 }
 
+/// Child index of the void left in the middle of a subdivided triangle.
+pub const VOID_INDEX: u8 = 3;
+
 impl TriangleAddress {
-    pub fn new(path: Vec<u8>) -> Self {
-        Self { path }
+    pub fn new(path: Vec<u8>) -> Result<Self, TriangleError> {
+        path.into_iter().try_fold(Self::root(), |address, index| address.child(index))
     }
 
     pub fn root() -> Self {
-        Self { path: vec![] }
+        Self { bits: 0, depth: 0 }
     }
 
+    /// The child at `index`, or an error if the index is not 0, 1, 2 or
+    /// `VOID_INDEX`, this address is a void, or the child would be deeper
+    /// than `MAX_DEPTH`.
+    pub fn child(&self, index: u8) -> Result<Self, TriangleError> {
+        if index > VOID_INDEX || self.is_void() {
+            return Err(TriangleError::InvalidAddressFormat);
+        }
+        if self.depth() >= MAX_DEPTH {
+            return Err(TriangleError::MaxDepthExceeded(self.depth() + 1));
+        }
+        Ok(self.push(index))
+    }
+
+    /// Like `child`, for an index and depth the caller has already checked,
+    /// such as the children of a triangle the ledger records as subdivided.
+    /// The checks only run in debug builds, so addresses taken from
+    /// transactions or other untrusted input must go through `child`.
     pub fn append(&self, index: u8) -> Self {
-        let mut new_path = self.path.clone();
-        new_path.push(index);
-        Self { path: new_path }
+        debug_assert!(self.child(index).is_ok(), "invalid child {} of triangle address {}", index, self);
+        self.push(index)
+    }
+
+    fn push(&self, index: u8) -> Self {
+        Self {
+            bits: self.bits | (index as u128) << Self::shift(self.depth as u32),
+            depth: self.depth + 1,
+        }
+    }
+
+    /// Child indices from the root down to this triangle.
+    pub fn indices(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.depth as u32).map(move |level| ((self.bits >> Self::shift(level)) & 0b11) as u8)
+    }
+
+    /// The void in the middle of this triangle.
+    pub fn void(&self) -> Result<Self, TriangleError> {
+        self.child(VOID_INDEX)
+    }
+
+    /// Whether this is the central void of its parent rather than a triangle
+    /// of the fractal.
+    pub fn is_void(&self) -> bool {
+        self.depth > 0 && self.last_index() == VOID_INDEX
+    }
+
+    /// Number of subdivisions between the root and this triangle.
+    pub fn depth(&self) -> u32 {
+        self.depth as u32
     }
 
     pub fn parent(&self) -> Option<Self> {
-        if self.path.is_empty() {
+        if self.depth == 0 {
             None
         } else {
-            let mut parent_path = self.path.clone();
-            parent_path.pop();
-            Some(Self { path: parent_path })
+            let level = self.depth as u32 - 1;
+            Some(Self {
+                bits: self.bits & !(0b11 << Self::shift(level)),
+                depth: self.depth - 1,
+            })
+        }
+    }
+
+    /// Packed binary form: the depth, followed by two bits per level
+    /// (first level in the highest bits) padded with zeros to whole bytes.
+    pub fn to_packed(&self) -> Vec<u8> {
+        let len = (self.depth as usize).div_ceil(4);
+        let mut packed = Vec::with_capacity(1 + len);
+        packed.push(self.depth);
+        packed.extend_from_slice(&self.bits.to_be_bytes()[..len]);
+        packed
+    }
+
+    /// Parses the packed form, rejecting invalid indices and any encoding
+    /// other than the one `to_packed` produces.
+    pub fn from_packed(packed: &[u8]) -> Result<Self, TriangleError> {
+        let (&depth, body) = packed.split_first().ok_or(TriangleError::InvalidAddressFormat)?;
+        if depth as u32 > MAX_DEPTH {
+            return Err(TriangleError::MaxDepthExceeded(depth as u32));
+        }
+        if body.len() != (depth as usize).div_ceil(4) {
+            return Err(TriangleError::InvalidAddressFormat);
+        }
+        let mut bytes = [0u8; 16];
+        bytes[..body.len()].copy_from_slice(body);
+        let address = Self { bits: u128::from_be_bytes(bytes), depth };
+
+        let used_bits = 2 * depth as u32;
+        let padding = if used_bits == 0 { address.bits } else { address.bits << used_bits };
+        let inner_void = address.indices().take(depth.saturating_sub(1) as usize).any(|index| index == VOID_INDEX);
+        if padding != 0 || inner_void {
+            return Err(TriangleError::InvalidAddressFormat);
+        }
+        Ok(address)
+    }
+
+    /// Compact form: one digit per level, `0` to `2` for a child and `3`
+    /// for a void, which can only come last. For example `"012"`, or
+    /// `"0123"` for its void. The root is the empty string.
+    pub fn to_compact_string(&self) -> String {
+        self.indices().map(|index| char::from(b'0' + index)).collect()
+    }
+
+    /// Parses the compact form, accepting the same addresses as `FromStr`.
+    pub fn from_compact_str(s: &str) -> Result<Self, TriangleError> {
+        s.chars().try_fold(Self::root(), |address, digit| match digit {
+            '0'..='3' => address.child(digit as u8 - b'0'),
+            _ => Err(TriangleError::InvalidAddressFormat),
+        })
+    }
+
+    /// The ancestor at `depth`, or `None` if this address is shallower.
+    pub fn ancestor_at(&self, depth: u32) -> Option<Self> {
+        if depth > self.depth() {
+            return None;
+        }
+        Some(Self { bits: self.bits & Self::prefix_mask(depth), depth: depth as u8 })
+    }
+
+    /// Whether `other` lies strictly below this triangle.
+    pub fn is_ancestor_of(&self, other: &Self) -> bool {
+        self.depth < other.depth && other.bits & Self::prefix_mask(self.depth()) == self.bits
+    }
+
+    /// The deepest triangle containing both addresses.
+    pub fn lowest_common_ancestor(&self, other: &Self) -> Self {
+        let common = ((self.bits ^ other.bits).leading_zeros() / 2).min(self.depth().min(other.depth()));
+        Self { bits: self.bits & Self::prefix_mask(common), depth: common as u8 }
+    }
+
+    /// Triangles at the same depth that touch this one.
+    ///
+    /// Triangles of one level never share a full side; they meet at corners,
+    /// which makes them the vertices of the Sierpinski graph. Siblings touch
+    /// at the midpoints of their parent, and `w i j..j` touches `w j i..i`
+    /// across subtrees. Every triangle has three neighbors, except the two
+    /// or three at the corners of the genesis triangle. The root has none.
+    ///
+    /// A void shares a full side with each of its three siblings, and those
+    /// are its neighbors. Voids are not listed as neighbors of triangles.
+    pub fn neighbors(&self) -> Vec<Self> {
+        if self.is_void() {
+            let parent = self.parent().expect("a void has a parent");
+            return (0..3).map(|i| parent.append(i)).collect();
+        }
+        let path: Vec<u8> = self.indices().collect();
+        let Some((&last, prefix)) = path.split_last() else {
+            return Vec::new();
+        };
+        let parent = self.parent().expect("non-root address has a parent");
+        let mut neighbors: Vec<Self> = (0..3).filter(|&i| i != last).map(|i| parent.append(i)).collect();
+
+        let run = path.iter().rev().take_while(|&&index| index == last).count();
+        if run < path.len() {
+            let split = path.len() - run - 1;
+            let other = prefix[split];
+            let mut across = self.ancestor_at(split as u32).expect("split is above this address");
+            across = across.append(last);
+            for _ in 0..run {
+                across = across.append(other);
+            }
+            neighbors.push(across);
+        }
+        neighbors
+    }
+
+    /// Every descendant down to `max_depth`, in depth-first order. Voids are
+    /// not included; use `void` on the yielded addresses to reach them.
+    pub fn descendants(&self, max_depth: u32) -> Descendants {
+        let mut stack = Vec::new();
+        if !self.is_void() && self.depth() < max_depth.min(MAX_DEPTH) {
+            stack.extend((0..3).rev().map(|i| self.append(i)));
+        }
+        Descendants { stack, max_depth: max_depth.min(MAX_DEPTH) }
+    }
+
+    fn last_index(&self) -> u8 {
+        ((self.bits >> Self::shift(self.depth as u32 - 1)) & 0b11) as u8
+    }
+
+    /// Bit offset of the index at `level`.
+    fn shift(level: u32) -> u32 {
+        126 - 2 * level
+    }
+
+    /// Mask selecting the indices of the first `depth` levels.
+    fn prefix_mask(depth: u32) -> u128 {
+        if depth == 0 {
+            0
+        } else {
+            !0u128 << (128 - 2 * depth)
+        }
+    }
+}
+
+/// Depth-first iterator returned by `TriangleAddress::descendants`.
+pub struct Descendants {
+    stack: Vec<TriangleAddress>,
+    max_depth: u32,
+}
+
+impl Iterator for Descendants {
+    type Item = TriangleAddress;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let address = self.stack.pop()?;
+        if address.depth() < self.max_depth {
+            self.stack.extend((0..3).rev().map(|i| address.append(i)));
         }
+        Some(address)
+    }
+}
+
+impl Serialize for TriangleAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_packed())
+    }
+}
+
+impl<'de> Deserialize<'de> for TriangleAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let packed = Vec::<u8>::deserialize(deserializer)?;
+        Self::from_packed(&packed).map_err(D::Error::custom)
     }
 }
 
 impl fmt::Display for TriangleAddress {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let path_str = self
-            .path
-            .iter()
-            .map(|&num| num.to_string())
+            .indices()
+            .map(|num| num.to_string())
             .collect::<Vec<String>>()
             .join(".");
         write!(f, "{}", path_str)
@@ -58,7 +278,7 @@ impl FromStr for TriangleAddress {
         let path: Result<Vec<u8>, _> = s.split('.').map(|part| part.parse::<u8>()).collect();
 
         match path {
-            Ok(path) => Ok(TriangleAddress::new(path)),
+            Ok(path) => TriangleAddress::new(path),
             Err(_) => Err(TriangleError::InvalidAddressFormat),
         }
     }
@@ -70,7 +290,7 @@ mod tests {
 
     #[test]
     fn test_address_creation_and_display() {
-        let address = TriangleAddress::new(vec![0, 1, 2]);
+        let address = TriangleAddress::new(vec![0, 1, 2]).unwrap();
         assert_eq!(address.to_string(), "0.1.2");
     }
 
@@ -78,7 +298,7 @@ mod tests {
     fn test_address_from_str() {
         let address_str = "0.1.2";
         let address = TriangleAddress::from_str(address_str).unwrap();
-        assert_eq!(address, TriangleAddress::new(vec![0, 1, 2]));
+        assert_eq!(address, TriangleAddress::new(vec![0, 1, 2]).unwrap());
     }
 
     #[test]
@@ -97,4 +317,149 @@ mod tests {
         assert_eq!(root, TriangleAddress::root());
         assert!(root.parent().is_none());
     }
+
+    #[test]
+    fn test_reject_invalid_indices() {
+        assert!(TriangleAddress::from_str("0.7.255").is_err());
+        assert!(TriangleAddress::from_str("0.3.1").is_err());
+        assert!(TriangleAddress::new(vec![1, 3, 0]).is_err());
+        assert!(TriangleAddress::root().child(4).is_err());
+        assert!(TriangleAddress::from_compact_str("014").is_err());
+    }
+
+    #[test]
+    fn test_reject_addresses_beyond_max_depth() {
+        let deepest = TriangleAddress::new(vec![1; MAX_DEPTH as usize]).unwrap();
+        assert!(matches!(deepest.child(0), Err(TriangleError::MaxDepthExceeded(_))));
+    }
+
+    #[test]
+    fn test_packed_round_trip() {
+        for s in ["", "0", "2", "0.1.2", "2.2.2.2", "1.0.2.2.1"] {
+            let address = TriangleAddress::from_str(s).unwrap();
+            let packed = address.to_packed();
+            assert_eq!(packed.len(), 1 + (address.depth() as usize).div_ceil(4));
+            assert_eq!(TriangleAddress::from_packed(&packed).unwrap(), address);
+        }
+        let deepest = TriangleAddress::new(vec![2; MAX_DEPTH as usize]).unwrap();
+        assert_eq!(TriangleAddress::from_packed(&deepest.to_packed()).unwrap(), deepest);
+
+        let bytes = bincode::serialize(&TriangleAddress::from_str("0.1.2").unwrap()).unwrap();
+        let decoded: TriangleAddress = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.to_string(), "0.1.2");
+    }
+
+    #[test]
+    fn test_reject_non_canonical_packed_form() {
+        // A void with a child below it.
+        assert!(TriangleAddress::from_packed(&[2, 0b1100_0000]).is_err());
+        // Non-zero padding after the last level.
+        assert!(TriangleAddress::from_packed(&[1, 0b0000_0001]).is_err());
+        // Body longer than the depth requires.
+        assert!(TriangleAddress::from_packed(&[1, 0, 0]).is_err());
+        assert!(TriangleAddress::from_packed(&[]).is_err());
+    }
+
+    #[test]
+    fn test_compact_string() {
+        let address = TriangleAddress::from_str("0.1.2.2").unwrap();
+        assert_eq!(address.to_compact_string(), "0122");
+        assert_eq!(TriangleAddress::from_compact_str("0122").unwrap(), address);
+        assert_eq!(TriangleAddress::from_compact_str("").unwrap(), TriangleAddress::root());
+    }
+
+    #[test]
+    fn test_void_round_trips_through_both_string_forms() {
+        let void = TriangleAddress::from_str("2.0.3").unwrap();
+        assert!(void.is_void());
+        assert_eq!(void.to_compact_string(), "203");
+        assert_eq!(TriangleAddress::from_compact_str(&void.to_compact_string()).unwrap(), void);
+        assert_eq!(TriangleAddress::from_str(&void.to_string()).unwrap(), void);
+        assert!(TriangleAddress::from_compact_str("2030").is_err());
+        assert!(TriangleAddress::from_compact_str("24").is_err());
+    }
+
+    #[test]
+    fn test_ord_is_depth_first() {
+        let mut addresses: Vec<TriangleAddress> = ["1", "0.2", "", "0", "2.0", "0.0.1", "1.0", "0.0"]
+            .iter()
+            .map(|s| TriangleAddress::from_str(s).unwrap())
+            .collect();
+        addresses.sort();
+        let sorted: Vec<String> = addresses.iter().map(|a| a.to_compact_string()).collect();
+        assert_eq!(sorted, vec!["", "0", "00", "001", "02", "1", "10", "20"]);
+    }
+
+    fn addr(s: &str) -> TriangleAddress {
+        TriangleAddress::from_compact_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_ancestors() {
+        assert!(addr("01").is_ancestor_of(&addr("012")));
+        assert!(TriangleAddress::root().is_ancestor_of(&addr("2")));
+        assert!(!addr("012").is_ancestor_of(&addr("012")));
+        assert!(!addr("02").is_ancestor_of(&addr("012")));
+        assert_eq!(addr("0122").lowest_common_ancestor(&addr("0102")), addr("01"));
+        assert_eq!(addr("01").lowest_common_ancestor(&addr("0122")), addr("01"));
+        assert_eq!(addr("1").lowest_common_ancestor(&addr("2")), TriangleAddress::root());
+        assert_eq!(addr("0122").ancestor_at(2), Some(addr("01")));
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let mut neighbors = addr("012").neighbors();
+        neighbors.sort();
+        assert_eq!(neighbors, vec![addr("010"), addr("011"), addr("021")]);
+
+        let mut neighbors = addr("0211").neighbors();
+        neighbors.sort();
+        assert_eq!(neighbors, vec![addr("0122"), addr("0210"), addr("0212")]);
+
+        // Corners of the genesis triangle only touch their siblings.
+        assert_eq!(addr("000").neighbors().len(), 2);
+        assert!(TriangleAddress::root().neighbors().is_empty());
+    }
+
+    #[test]
+    fn test_neighbors_share_a_corner() {
+        use crate::core::barycentric::ExactTriangle;
+        for address in TriangleAddress::root().descendants(4).filter(|a| a.depth() == 4) {
+            let triangle = ExactTriangle::from_address(&address).unwrap();
+            for neighbor in address.neighbors() {
+                let other = ExactTriangle::from_address(&neighbor).unwrap();
+                let shared = [triangle.a, triangle.b, triangle.c]
+                    .iter()
+                    .filter(|p| [other.a, other.b, other.c].contains(p))
+                    .count();
+                assert_eq!(shared, 1, "{} and {}", address, neighbor);
+            }
+        }
+    }
+
+    #[test]
+    fn test_descendants_depth_first() {
+        let all: Vec<String> = addr("1").descendants(3).map(|a| a.to_compact_string()).collect();
+        assert_eq!(all.len(), 3 + 9);
+        assert_eq!(&all[..5], &["10", "100", "101", "102", "11"]);
+        assert!(addr("1").descendants(1).next().is_none());
+    }
+
+    #[test]
+    fn test_void_is_terminal() {
+        let void = addr("01").void().unwrap();
+        assert!(void.is_void());
+        assert_eq!(void.to_compact_string(), "013");
+        assert_eq!(void.parent(), Some(addr("01")));
+        assert!(void.child(0).is_err());
+        assert!(void.void().is_err());
+        assert!(TriangleAddress::from_compact_str("0130").is_err());
+        assert_eq!(void.descendants(3).count(), 0);
+        assert_eq!(TriangleAddress::from_packed(&void.to_packed()).unwrap(), void);
+        assert!(!TriangleAddress::root().is_void());
+
+        let mut neighbors = void.neighbors();
+        neighbors.sort();
+        assert_eq!(neighbors, vec![addr("010"), addr("011"), addr("012")]);
+    }
 }
@@ -0,0 +1,160 @@
+//! Balances of the native token.
+//!
+//! Tokens only enter the ledger through `mint` and only leave it through
+//! `burn`; `transfer` moves them between accounts. The ledger keeps the total
+//! supply alongside the balances, so the sum of all balances always equals
+//! it. Amounts must be positive, balances never go negative, and arithmetic
+//! that would overflow a `Decimal` is rejected instead of wrapping or
+//! panicking. A failed operation leaves the ledger untouched.
+
+use crate::core::errors::BalanceError;
+use crate::wallet::address::Address;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default)]
+pub struct BalanceLedger {
+    /// Non-zero balances only.
+    balances: HashMap<Address, Decimal>,
+    supply: Decimal,
+}
+
+impl BalanceLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn balance(&self, address: &Address) -> Decimal {
+        self.balances.get(address).copied().unwrap_or(Decimal::ZERO)
+    }
+
+    /// Tokens in existence, which is the sum of all balances.
+    pub fn total_supply(&self) -> Decimal {
+        self.supply
+    }
+
+    /// Accounts holding tokens, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Address, &Decimal)> {
+        self.balances.iter()
+    }
+
+    /// Issues `amount` new tokens to `to`.
+    pub fn mint(&mut self, to: &Address, amount: Decimal) -> Result<(), BalanceError> {
+        check_amount(amount)?;
+        let supply = self.supply.checked_add(amount).ok_or(BalanceError::Overflow)?;
+        let balance = self.balance(to).checked_add(amount).ok_or(BalanceError::Overflow)?;
+        self.supply = supply;
+        self.set(to, balance);
+        Ok(())
+    }
+
+    /// Destroys `amount` tokens held by `from`.
+    pub fn burn(&mut self, from: &Address, amount: Decimal) -> Result<(), BalanceError> {
+        check_amount(amount)?;
+        let balance = self.debited(from, amount)?;
+        self.supply -= amount;
+        self.set(from, balance);
+        Ok(())
+    }
+
+    /// Moves `amount` tokens from `from` to `to`.
+    pub fn transfer(&mut self, from: &Address, to: &Address, amount: Decimal) -> Result<(), BalanceError> {
+        check_amount(amount)?;
+        let from_balance = self.debited(from, amount)?;
+        if from == to {
+            return Ok(());
+        }
+        let to_balance = self.balance(to).checked_add(amount).ok_or(BalanceError::Overflow)?;
+        self.set(from, from_balance);
+        self.set(to, to_balance);
+        Ok(())
+    }
+
+    /// Checks that `from` could pay `amount` without changing anything.
+    pub fn check_debit(&self, from: &Address, amount: Decimal) -> Result<(), BalanceError> {
+        check_amount(amount)?;
+        self.debited(from, amount).map(|_| ())
+    }
+
+    /// Puts back a balance and the supply recorded before a change, when
+    /// undoing a block.
+    pub(crate) fn restore(&mut self, address: &Address, balance: Decimal) {
+        let current = self.balance(address);
+        self.supply = self.supply - current + balance;
+        self.set(address, balance);
+    }
+
+    fn debited(&self, from: &Address, amount: Decimal) -> Result<Decimal, BalanceError> {
+        let balance = self.balance(from);
+        if balance < amount {
+            return Err(BalanceError::InsufficientBalance { address: *from, balance, amount });
+        }
+        Ok(balance - amount)
+    }
+
+    fn set(&mut self, address: &Address, balance: Decimal) {
+        if balance.is_zero() {
+            self.balances.remove(address);
+        } else {
+            self.balances.insert(*address, balance);
+        }
+    }
+}
+
+fn check_amount(amount: Decimal) -> Result<(), BalanceError> {
+    if amount <= Decimal::ZERO {
+        return Err(BalanceError::InvalidAmount(amount));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn account() -> Address {
+        Address::from_pubkey(&ed25519_dalek::Keypair::generate(&mut rand::thread_rng()).public)
+    }
+
+    fn sum_of_balances(ledger: &BalanceLedger) -> Decimal {
+        ledger.iter().map(|(_, balance)| *balance).sum()
+    }
+
+    #[test]
+    fn test_supply_matches_balances() {
+        let (alice, bob) = (account(), account());
+        let mut ledger = BalanceLedger::new();
+
+        ledger.mint(&alice, dec!(100)).unwrap();
+        ledger.transfer(&alice, &bob, dec!(30.5)).unwrap();
+        ledger.burn(&bob, dec!(0.5)).unwrap();
+        assert_eq!(ledger.balance(&alice), dec!(69.5));
+        assert_eq!(ledger.balance(&bob), dec!(30));
+        assert_eq!(ledger.total_supply(), dec!(99.5));
+        assert_eq!(sum_of_balances(&ledger), ledger.total_supply());
+
+        ledger.restore(&bob, dec!(30.5));
+        assert_eq!(ledger.total_supply(), dec!(100));
+        assert_eq!(sum_of_balances(&ledger), ledger.total_supply());
+    }
+
+    #[test]
+    fn test_reject_overdraft_bad_amounts_and_overflow() {
+        let (alice, bob) = (account(), account());
+        let mut ledger = BalanceLedger::new();
+        ledger.mint(&alice, dec!(10)).unwrap();
+
+        assert_eq!(
+            ledger.transfer(&alice, &bob, dec!(10.01)),
+            Err(BalanceError::InsufficientBalance { address: alice, balance: dec!(10), amount: dec!(10.01) })
+        );
+        assert_eq!(ledger.transfer(&alice, &bob, dec!(-1)), Err(BalanceError::InvalidAmount(dec!(-1))));
+        assert_eq!(ledger.mint(&bob, Decimal::ZERO), Err(BalanceError::InvalidAmount(Decimal::ZERO)));
+
+        ledger.mint(&bob, Decimal::MAX - dec!(10)).unwrap();
+        assert_eq!(ledger.mint(&bob, dec!(1)), Err(BalanceError::Overflow));
+        assert_eq!(ledger.balance(&alice), dec!(10));
+        assert_eq!(ledger.total_supply(), Decimal::MAX);
+    }
+}
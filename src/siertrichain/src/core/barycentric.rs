@@ -0,0 +1,249 @@
+//! Exact geometry for triangles of the Sierpinski tree.
+//!
+//! Every vertex of every triangle in the tree is a dyadic combination of the
+//! three genesis vertices, so it can be stored as integer barycentric weights
+//! scaled by `2^MAX_DEPTH`. Subdividing only halves sums of weights, which is
+//! exact down to `MAX_DEPTH`. Cartesian coordinates are derived from these
+//! weights for display and for the `Triangle` values carried in transactions.
+
+use crate::core::address::{TriangleAddress, VOID_INDEX};
+use crate::core::errors::TriangleError;
+use crate::core::geometry::Point;
+use crate::core::hash::H256;
+use crate::core::triangle::Triangle;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+/// Deepest level at which triangles still have exact integer vertices.
+pub const MAX_DEPTH: u32 = 62;
+
+/// The sum of the barycentric weights of every point.
+pub const SCALE: u64 = 1 << MAX_DEPTH;
+
+/// sqrt(3)/2, the height of the genesis triangle, rounded to the precision of
+/// `Decimal`. A constant so that no node depends on a square root routine.
+pub const SQRT3_2: Decimal = dec!(0.8660254037844386467637231708);
+
+/// A point given by its weights on the genesis vertices A, B and C. The
+/// weights always add up to `SCALE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BarycentricPoint {
+    pub a: u64,
+    pub b: u64,
+    pub c: u64,
+}
+
+impl BarycentricPoint {
+    pub const A: Self = Self { a: SCALE, b: 0, c: 0 };
+    pub const B: Self = Self { a: 0, b: SCALE, c: 0 };
+    pub const C: Self = Self { a: 0, b: 0, c: SCALE };
+
+    /// Exact as long as both points lie on the grid of depth `MAX_DEPTH - 1`
+    /// or coarser, which holds for every vertex of a subdividable triangle.
+    pub fn midpoint(&self, other: &Self) -> Self {
+        Self {
+            a: (self.a + other.a) / 2,
+            b: (self.b + other.b) / 2,
+            c: (self.c + other.c) / 2,
+        }
+    }
+
+    /// Cartesian position with A = (0, 0), B = (1, 0), C = (1/2, sqrt(3)/2).
+    pub fn to_cartesian(&self) -> Point {
+        let scale = Decimal::from(SCALE);
+        let x = (Decimal::from(self.b) + Decimal::from(self.c) / dec!(2)) / scale;
+        let y = SQRT3_2 * (Decimal::from(self.c) / scale);
+        Point::new(x.normalize(), y.normalize())
+    }
+}
+
+/// A triangle of the Sierpinski tree with exact vertices, in the same vertex
+/// order as `subdivision::subdivide_triangle` produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ExactTriangle {
+    pub a: BarycentricPoint,
+    pub b: BarycentricPoint,
+    pub c: BarycentricPoint,
+    pub depth: u32,
+}
+
+impl ExactTriangle {
+    pub fn genesis() -> Self {
+        Self {
+            a: BarycentricPoint::A,
+            b: BarycentricPoint::B,
+            c: BarycentricPoint::C,
+            depth: 0,
+        }
+    }
+
+    /// Walks the address down from genesis.
+    pub fn from_address(address: &TriangleAddress) -> Result<Self, TriangleError> {
+        if address.depth() > MAX_DEPTH {
+            return Err(TriangleError::MaxDepthExceeded(address.depth()));
+        }
+        let mut triangle = Self::genesis();
+        for index in address.indices() {
+            triangle = triangle.child(index)?;
+        }
+        Ok(triangle)
+    }
+
+    /// The child at `index`: a corner triangle for 0, 1 or 2, or the central
+    /// void, spanned by the three edge midpoints, for `VOID_INDEX`.
+    pub fn child(&self, index: u8) -> Result<Self, TriangleError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(TriangleError::MaxDepthExceeded(self.depth + 1));
+        }
+        let depth = self.depth + 1;
+        let mid_ab = self.a.midpoint(&self.b);
+        let mid_bc = self.b.midpoint(&self.c);
+        let mid_ca = self.c.midpoint(&self.a);
+        match index {
+            0 => Ok(Self { a: self.a, b: mid_ab, c: mid_ca, depth }),
+            1 => Ok(Self { a: mid_ab, b: self.b, c: mid_bc, depth }),
+            2 => Ok(Self { a: mid_ca, b: mid_bc, c: self.c, depth }),
+            VOID_INDEX => Ok(Self { a: mid_ab, b: mid_bc, c: mid_ca, depth }),
+            _ => Err(TriangleError::InvalidAddressFormat),
+        }
+    }
+
+    pub fn subdivide(&self) -> Result<[Self; 3], TriangleError> {
+        Ok([self.child(0)?, self.child(1)?, self.child(2)?])
+    }
+
+    /// Cartesian coordinates, for display and for transaction payloads.
+    pub fn to_cartesian(&self) -> Triangle {
+        Triangle::new(self.a.to_cartesian(), self.b.to_cartesian(), self.c.to_cartesian())
+    }
+}
+
+/// The three children of the triangle at `parent` in the Cartesian form a
+/// `Subdivide` operation must carry.
+pub fn cartesian_children(parent: &TriangleAddress) -> Result<[Triangle; 3], TriangleError> {
+    Ok(ExactTriangle::from_address(parent)?.subdivide()?.map(|child| child.to_cartesian()))
+}
+
+/// The central void left by subdividing the triangle at `parent`.
+pub fn cartesian_void(parent: &TriangleAddress) -> Result<Triangle, TriangleError> {
+    Ok(ExactTriangle::from_address(&parent.void()?)?.to_cartesian())
+}
+
+/// Id of the triangle at `address`; equal to the hash of its Cartesian form,
+/// so records keyed by either agree.
+pub fn triangle_id(address: &TriangleAddress) -> Result<H256, TriangleError> {
+    Ok(ExactTriangle::from_address(address)?.to_cartesian().hash())
+}
+
+/// The triangle at `depth` that contains `point`, given in the Cartesian
+/// frame of the genesis triangle. Returns `None` for points outside the
+/// genesis triangle, points inside one of the removed middle triangles, and
+/// depths beyond `MAX_DEPTH`. Points on a shared corner go to the child with
+/// the lowest index.
+pub fn locate(point: &Point, depth: u32) -> Option<TriangleAddress> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let half = dec!(0.5);
+    let mut c = point.y / SQRT3_2;
+    let mut b = point.x - c * half;
+    let mut a = Decimal::ONE - b - c;
+    if a < Decimal::ZERO || b < Decimal::ZERO || c < Decimal::ZERO {
+        return None;
+    }
+
+    let mut address = TriangleAddress::root();
+    for _ in 0..depth {
+        let index = if a >= half {
+            0
+        } else if b >= half {
+            1
+        } else if c >= half {
+            2
+        } else {
+            return None;
+        };
+        a *= dec!(2);
+        b *= dec!(2);
+        c *= dec!(2);
+        match index {
+            0 => a -= Decimal::ONE,
+            1 => b -= Decimal::ONE,
+            _ => c -= Decimal::ONE,
+        }
+        address = address.append(index);
+    }
+    Some(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::genesis::GenesisTriangle;
+
+    #[test]
+    fn test_genesis_matches_cartesian_genesis() {
+        assert_eq!(ExactTriangle::genesis().to_cartesian(), GenesisTriangle::new());
+    }
+
+    #[test]
+    fn test_subdivision_is_exact_at_max_depth() {
+        let deepest = TriangleAddress::new(vec![2; MAX_DEPTH as usize]).unwrap();
+        let triangle = ExactTriangle::from_address(&deepest).unwrap();
+        // Corner C's child keeps C and shrinks towards it by exactly 2^-62.
+        assert_eq!(triangle.c, BarycentricPoint::C);
+        assert_eq!(triangle.a, BarycentricPoint { a: 1, b: 0, c: SCALE - 1 });
+        assert_eq!(triangle.b, BarycentricPoint { a: 0, b: 1, c: SCALE - 1 });
+        for point in [triangle.a, triangle.b, triangle.c] {
+            assert_eq!(point.a + point.b + point.c, SCALE);
+        }
+
+        assert!(matches!(triangle.child(0), Err(TriangleError::MaxDepthExceeded(_))));
+    }
+
+    #[test]
+    fn test_children_follow_address_order() {
+        let root = ExactTriangle::genesis();
+        let children = root.subdivide().unwrap();
+        let addressed = ExactTriangle::from_address(&TriangleAddress::root().append(1)).unwrap();
+        assert_eq!(children[1], addressed);
+        assert_eq!(children[1].to_cartesian().b, Point::new(dec!(1), dec!(0)));
+        assert!(matches!(root.child(4), Err(TriangleError::InvalidAddressFormat)));
+    }
+
+    #[test]
+    fn test_locate_finds_the_triangle_around_a_centroid() {
+        for address in TriangleAddress::root().descendants(5).filter(|a| a.depth() == 5) {
+            let centroid = ExactTriangle::from_address(&address).unwrap().to_cartesian().centroid();
+            assert_eq!(locate(&centroid, 5), Some(address.clone()));
+            assert_eq!(locate(&centroid, 2), address.ancestor_at(2));
+        }
+    }
+
+    #[test]
+    fn test_locate_rejects_holes_and_outside_points() {
+        let middle = Point::new(dec!(0.5), dec!(0.3));
+        assert_eq!(locate(&middle, 0), Some(TriangleAddress::root()));
+        assert_eq!(locate(&middle, 1), None);
+        assert_eq!(locate(&Point::new(dec!(2), dec!(0)), 1), None);
+        assert_eq!(locate(&Point::new(dec!(0), dec!(0)), 3).unwrap().to_compact_string(), "000");
+    }
+
+    #[test]
+    fn test_void_and_children_cover_the_parent() {
+        let parent = TriangleAddress::root().append(1);
+        let area = ExactTriangle::from_address(&parent).unwrap().to_cartesian().area();
+        let children: Decimal = cartesian_children(&parent).unwrap().iter().map(|t| t.area()).sum();
+        let void = cartesian_void(&parent).unwrap();
+        assert!((children + void.area() - area).abs() < dec!(1e-20));
+        assert!((void.area() * dec!(4) - area).abs() < dec!(1e-20));
+    }
+
+    #[test]
+    fn test_triangle_id_matches_cartesian_hash() {
+        let address = TriangleAddress::root().append(0).append(2);
+        let [_, _, child] = cartesian_children(&TriangleAddress::root().append(0)).unwrap();
+        assert_eq!(triangle_id(&address).unwrap(), child.hash());
+    }
+}
@@ -1,20 +1,38 @@
 use serde::{Deserialize, Serialize};
 use crate::core::transaction::Transaction;
 use crate::core::hash::H256;
-use chrono::prelude::*;
 use crate::core::address::TriangleAddress;
+use crate::core::encoding::{Decode, Encode, Reader};
+use crate::core::errors::DecodeError;
+use rust_decimal::Decimal;
+
+/// Version of the block header encoding, see `core::encoding`.
+pub const BLOCK_VERSION: u32 = 2;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockHeader {
+    pub version: u32,
     pub previous_hash: H256,
     pub merkle_root: H256,
     pub timestamp: i64,
     pub nonce: u64,
-    pub difficulty: u64,
+    /// Compact proof-of-work target, see `consensus::difficulty`.
+    pub bits: u32,
+    /// Fee every transaction must at least pay, see `consensus::fees`.
+    pub base_fee: Decimal,
+    /// Number of transactions besides the coinbase. The base fee of the
+    /// next block follows from it.
+    pub transaction_count: u32,
     pub height: u64,
     pub geometric_proof: TriangleAddress,
 }
 
+impl BlockHeader {
+    pub fn hash(&self) -> H256 {
+        blake3::hash(&self.encode()).into()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     pub header: BlockHeader,
@@ -22,21 +40,27 @@ pub struct Block {
 }
 
 impl Block {
+    /// Builds an unmined block. `timestamp` is taken from the caller's
+    /// `Clock` rather than read here, so blocks can be built reproducibly.
     pub fn new(
         previous_hash: H256,
         merkle_root: H256,
-        difficulty: u64,
+        timestamp: i64,
+        bits: u32,
+        base_fee: Decimal,
         height: u64,
         triangle_transactions: Vec<Transaction>,
     ) -> Self {
-        let timestamp = Utc::now().timestamp();
         Self {
             header: BlockHeader {
+                version: BLOCK_VERSION,
                 previous_hash,
                 merkle_root,
                 timestamp,
                 nonce: 0,
-                difficulty,
+                bits,
+                base_fee,
+                transaction_count: count_transactions(&triangle_transactions),
                 height,
                 geometric_proof: TriangleAddress::root(), // Default value
             },
@@ -44,8 +68,123 @@ impl Block {
         }
     }
 
+    /// The `transaction_count` the header must carry for the block's
+    /// current transactions.
+    pub fn transaction_count(&self) -> u32 {
+        count_transactions(&self.triangle_transactions)
+    }
+
     pub fn hash(&self) -> H256 {
-        let header_bytes = bincode::serialize(&self.header).unwrap();
-        blake3::hash(&header_bytes).into()
+        self.header.hash()
+    }
+}
+
+fn count_transactions(transactions: &[Transaction]) -> u32 {
+    transactions.iter().filter(|tx| !tx.is_coinbase()).count() as u32
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.version.encode_to(out);
+        self.previous_hash.encode_to(out);
+        self.merkle_root.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.nonce.encode_to(out);
+        self.bits.encode_to(out);
+        self.base_fee.encode_to(out);
+        self.transaction_count.encode_to(out);
+        self.height.encode_to(out);
+        self.geometric_proof.encode_to(out);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let version = u32::decode_from(reader)?;
+        if version != BLOCK_VERSION {
+            return Err(DecodeError::UnsupportedVersion { kind: "block", version });
+        }
+        Ok(Self {
+            version,
+            previous_hash: H256::decode_from(reader)?,
+            merkle_root: H256::decode_from(reader)?,
+            timestamp: i64::decode_from(reader)?,
+            nonce: u64::decode_from(reader)?,
+            bits: u32::decode_from(reader)?,
+            base_fee: Decimal::decode_from(reader)?,
+            transaction_count: u32::decode_from(reader)?,
+            height: u64::decode_from(reader)?,
+            geometric_proof: TriangleAddress::decode_from(reader)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.triangle_transactions.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            header: BlockHeader::decode_from(reader)?,
+            triangle_transactions: Vec::decode_from(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::genesis::GenesisTriangle;
+
+    #[test]
+    fn test_header_golden_vector() {
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            previous_hash: H256::from([0x11; 32]),
+            merkle_root: H256::from([0x22; 32]),
+            timestamp: 0x0102_0304_0506_0708,
+            nonce: 42,
+            bits: 0x2100_ffff,
+            base_fee: Decimal::new(15, 4),
+            transaction_count: 2,
+            height: 3,
+            geometric_proof: TriangleAddress::root().append(2).append(1),
+        };
+        let expected = [
+            "02000000",
+            &"11".repeat(32),
+            &"22".repeat(32),
+            "0807060504030201",
+            "2a00000000000000",
+            "ffff0021",
+            "00000400",
+            "0f0000000000000000000000",
+            "02000000",
+            "0300000000000000",
+            "0290",
+        ]
+        .concat();
+        assert_eq!(hex::encode(header.encode()), expected);
+        assert_eq!(format!("{:?}", header.hash()), "0x8b645a3a8ef3fb81ea2e3002ffc8ade9cb618752615ef47cfcc18fbbb67e0b39");
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let txs = vec![Transaction::new_genesis(GenesisTriangle::new())];
+        let block = Block::new(H256::from([7; 32]), H256::default(), 1_735_689_600, 0x2100_ffff, Decimal::ONE, 0, txs);
+        let bytes = block.encode();
+        let decoded = Block::decode(&bytes).unwrap();
+        assert_eq!(decoded.hash(), block.hash());
+        assert_eq!(decoded.triangle_transactions[0].hash(), block.triangle_transactions[0].hash());
+        assert_eq!(decoded.encode(), bytes);
+
+        let mut unknown = bytes.clone();
+        unknown[0] = 0;
+        assert_eq!(Block::decode(&unknown).unwrap_err(), DecodeError::UnsupportedVersion { kind: "block", version: 0 });
+        assert_eq!(Block::decode(&bytes[..bytes.len() - 1]).unwrap_err(), DecodeError::UnexpectedEnd);
     }
 }
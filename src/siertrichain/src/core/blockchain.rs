@@ -1,27 +1,186 @@
-use crate::core::block::{Block};
+use crate::consensus::difficulty::{block_work, difficulty_to_target, next_bits, target_to_compact, U256, LWMA_WINDOW};
+use crate::consensus::fees::{next_base_fee, INITIAL_BASE_FEE};
+use crate::consensus::rules::{validate_block, validate_geometric_proof, MEDIAN_TIME_SPAN};
+use crate::core::block::{Block, BlockHeader};
+use crate::core::clock::{Clock, SystemClock};
+use crate::core::errors::BlockchainError;
 use crate::core::hash::H256;
-use crate::core::genesis::GenesisTriangle;
+use crate::core::genesis::{genesis_owner, GenesisTriangle};
 use crate::core::merkle::MerkleTree;
+use crate::core::state::{BlockUndo, WorldState};
+use crate::core::storage::{BlockStorage, InMemoryStorage, TransactionStorage};
+use crate::core::tokenomics::{BlockReward, Supply};
 use crate::core::transaction::Transaction;
+use ed25519_dalek::PublicKey;
+use rust_decimal::Decimal;
 use std::collections::HashMap;
+use std::sync::Arc;
 use crate::core::fractal::FractalTriangle;
 
-pub struct Blockchain {
-    blocks: Vec<Block>,
-    block_map: HashMap<H256, usize>,
-    difficulty: u64,
+const INITIAL_DIFFICULTY: u64 = 1_000_000;
+/// Fixed so that every node derives the same genesis block.
+const GENESIS_TIMESTAMP: i64 = 1_735_689_600; // 2025-01-01T00:00:00Z
+
+/// Describes how the active chain changed after a block was accepted.
+///
+/// A block that simply extends the tip shows up as a single connected block,
+/// a block that lands on a side branch leaves both lists empty, and a
+/// reorganization lists every block that left and joined the active chain.
+#[derive(Debug, Clone, Default)]
+pub struct ChainUpdate {
+    /// Blocks removed from the active chain, tip first.
+    pub disconnected: Vec<Block>,
+    /// Blocks added to the active chain, in ascending height order.
+    pub connected: Vec<Block>,
+}
+
+impl ChainUpdate {
+    pub fn is_reorg(&self) -> bool {
+        !self.disconnected.is_empty()
+    }
+
+    /// Replays the update against a listener, disconnects first.
+    pub fn notify<L: ChainListener + ?Sized>(&self, listener: &mut L) {
+        for block in &self.disconnected {
+            listener.block_disconnected(block);
+        }
+        for block in &self.connected {
+            listener.block_connected(block);
+        }
+    }
+}
+
+/// Implemented by components that keep state derived from the active chain
+/// (mempool, NFT and DeFi managers) so they can follow reorganizations.
+pub trait ChainListener {
+    fn block_connected(&mut self, block: &Block);
+    fn block_disconnected(&mut self, block: &Block);
+}
+
+/// In-memory index entry for a block held by the backing storage.
+struct BlockIndexEntry {
+    header: BlockHeader,
+    chain_work: U256,
 }
 
-impl Blockchain {
+/// A tree of blocks rooted at genesis. Every valid block is kept in the
+/// backing storage, and the active chain is the branch with the most
+/// cumulative work. Only headers are held in memory.
+pub struct Blockchain<S = InMemoryStorage> {
+    storage: S,
+    index: HashMap<H256, BlockIndexEntry>,
+    /// Hashes of the active chain, indexed by height.
+    main_chain: Vec<H256>,
+    /// Triangle ownership as of the tip of the active chain.
+    state: WorldState,
+    /// Ledger changes of every connected block, used to disconnect it.
+    undo: HashMap<H256, BlockUndo>,
+    supply: Supply,
+    /// Target of the genesis block.
+    initial_bits: u32,
+    /// Judges how far in the future a block may be stamped.
+    clock: Arc<dyn Clock>,
+}
+
+impl Blockchain<InMemoryStorage> {
     pub fn new() -> Self {
+        Self::with_difficulty(INITIAL_DIFFICULTY)
+    }
+
+    pub fn with_difficulty(initial_difficulty: u64) -> Self {
+        Self::open(InMemoryStorage::new(), initial_difficulty)
+            .expect("in-memory storage does not fail")
+    }
+
+    /// An in-memory chain whose genesis triangle belongs to `owner` instead
+    /// of the owner of the public chain.
+    pub fn with_genesis_owner(initial_difficulty: u64, owner: PublicKey) -> Self {
+        Self::open_with_genesis_owner(InMemoryStorage::new(), initial_difficulty, owner)
+            .expect("in-memory storage does not fail")
+    }
+}
+
+impl<S: BlockStorage + TransactionStorage> Blockchain<S> {
+    /// Opens a chain on top of `storage`. An empty store is initialised with
+    /// the genesis block; otherwise the block index is rebuilt from the stored
+    /// headers and the chain resumes at the stored tip.
+    pub fn open(storage: S, initial_difficulty: u64) -> Result<Self, BlockchainError> {
+        Self::open_with_genesis_owner(storage, initial_difficulty, genesis_owner())
+    }
+
+    /// Like `open`, assigning the genesis triangle to `owner`. The genesis
+    /// block itself does not depend on it, but every node of a network must
+    /// agree on it.
+    pub fn open_with_genesis_owner(
+        storage: S,
+        initial_difficulty: u64,
+        owner: PublicKey,
+    ) -> Result<Self, BlockchainError> {
         let mut blockchain = Self {
-            blocks: Vec::new(),
-            block_map: HashMap::new(),
-            difficulty: 1_000_000, // Initial difficulty
+            storage,
+            index: HashMap::new(),
+            main_chain: Vec::new(),
+            state: WorldState::with_genesis_owner(owner),
+            undo: HashMap::new(),
+            supply: Supply::default(),
+            initial_bits: target_to_compact(difficulty_to_target(initial_difficulty)),
+            clock: Arc::new(SystemClock),
         };
         let genesis_block = blockchain.create_genesis_block();
-        blockchain.add_block(genesis_block).unwrap();
-        blockchain
+        let genesis_hash = genesis_block.hash();
+
+        match blockchain.storage.get_tip()? {
+            None => {
+                blockchain.storage.put_block(&genesis_block)?;
+                blockchain.insert_index(genesis_block.header, U256::zero());
+                blockchain.connect_block(genesis_hash)?;
+                blockchain.storage.put_tip(&genesis_hash)?;
+            }
+            Some(tip) => {
+                if blockchain.storage.get_hash_at_height(0)? != Some(genesis_hash) {
+                    return Err(BlockchainError::GenesisMismatch);
+                }
+                // Headers come back in ascending height order, so parents are
+                // always indexed before their children.
+                for header in blockchain.storage.load_headers()? {
+                    let parent_work = if header.height == 0 {
+                        U256::zero()
+                    } else {
+                        blockchain
+                            .index
+                            .get(&header.previous_hash)
+                            .map(|entry| entry.chain_work)
+                            .ok_or(BlockchainError::UnknownParent)?
+                    };
+                    blockchain.insert_index(header, parent_work);
+                }
+                let mut cursor = tip;
+                loop {
+                    let header = blockchain.get_header(&cursor).ok_or(BlockchainError::MissingBlock(cursor))?;
+                    let (height, previous_hash) = (header.height, header.previous_hash);
+                    blockchain.main_chain.push(cursor);
+                    if height == 0 {
+                        break;
+                    }
+                    cursor = previous_hash;
+                }
+                blockchain.main_chain.reverse();
+                for hash in blockchain.main_chain.clone() {
+                    blockchain.apply_to_state(hash)?;
+                }
+            }
+        }
+        Ok(blockchain)
+    }
+
+    /// Replaces the system clock, e.g. with a `MockClock` in tests.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
     }
 
     fn create_genesis_block(&self) -> Block {
@@ -30,92 +189,856 @@ impl Blockchain {
         let genesis_tx = Transaction::new_genesis(genesis_triangle);
         let transactions = vec![genesis_tx];
         let merkle_root = MerkleTree::new(&transactions).get_root();
-        let mut genesis_block = Block::new(H256::default(), merkle_root, self.difficulty, 0, transactions);
-        self.find_nonce(&mut genesis_block);
-        genesis_block
+        Block::new(H256::default(), merkle_root, GENESIS_TIMESTAMP, self.initial_bits, INITIAL_BASE_FEE, 0, transactions)
     }
 
-    pub fn add_block(&mut self, mut block: Block) -> Result<(), &'static str> {
-        if !self.is_valid_block(&block) {
-            return Err("Invalid block");
-        }
+    fn insert_index(&mut self, header: BlockHeader, parent_work: U256) -> U256 {
+        let chain_work = parent_work + block_work(header.bits);
+        self.index.insert(header.hash(), BlockIndexEntry { header, chain_work });
+        chain_work
+    }
 
-        self.find_nonce(&mut block);
+    /// Verifies a block and adds it to the tree unchanged, switching the
+    /// active chain to it if its branch now carries the most cumulative work.
+    /// Blocks must already carry a valid proof-of-work.
+    pub fn add_block(&mut self, block: Block) -> Result<ChainUpdate, BlockchainError> {
         let block_hash = block.hash();
-        self.block_map.insert(block_hash, self.blocks.len());
-        self.blocks.push(block);
+        if self.index.contains_key(&block_hash) {
+            return Err(BlockchainError::DuplicateBlock);
+        }
+        let parent_work = self
+            .index
+            .get(&block.header.previous_hash)
+            .ok_or(BlockchainError::UnknownParent)?
+            .chain_work;
+
+        validate_block(&block, self)?;
 
-        // Adjust difficulty
-        if self.blocks.len() % 10 == 0 { // Adjust every 10 blocks
-            self.adjust_difficulty();
+        self.storage.put_block(&block)?;
+        let work = self.insert_index(block.header, parent_work);
+
+        if work > self.get_chain_work(&self.tip()) {
+            self.reorganize(block_hash)
+        } else {
+            Ok(ChainUpdate::default())
+        }
+    }
+
+    /// Moves the active chain onto the branch ending at `new_tip`. If a block
+    /// on the new branch violates the ledger rules, the previous active chain
+    /// is restored and the offending block and its descendants are dropped
+    /// from the index and the storage.
+    fn reorganize(&mut self, new_tip: H256) -> Result<ChainUpdate, BlockchainError> {
+        let mut branch = Vec::new();
+        let mut cursor = new_tip;
+        while !self.is_on_main_chain(&cursor) {
+            branch.push(cursor);
+            cursor = self.index[&cursor].header.previous_hash;
         }
+        branch.reverse();
+        let fork_point = cursor;
 
+        let mut update = ChainUpdate::default();
+        while self.tip() != fork_point {
+            update.disconnected.push(self.disconnect_tip()?);
+        }
+        for (i, hash) in branch.iter().enumerate() {
+            match self.connect_block(*hash) {
+                Ok(block) => update.connected.push(block),
+                Err(err) => {
+                    for _ in 0..update.connected.len() {
+                        self.disconnect_tip()?;
+                    }
+                    for block in update.disconnected.iter().rev() {
+                        self.connect_block(block.hash())?;
+                    }
+                    self.discard(branch[i])?;
+                    return Err(err);
+                }
+            }
+        }
+        self.storage.put_tip(&new_tip)?;
+        Ok(update)
+    }
+
+    /// Forgets an invalid block and every block built on it, on any branch.
+    fn discard(&mut self, invalid: H256) -> Result<(), BlockchainError> {
+        let height = self.index[&invalid].header.height;
+        let mut descendants: Vec<(u64, H256, H256)> = self
+            .index
+            .iter()
+            .filter(|(_, entry)| entry.header.height > height)
+            .map(|(hash, entry)| (entry.header.height, *hash, entry.header.previous_hash))
+            .collect();
+        descendants.sort_unstable_by_key(|(height, ..)| *height);
+
+        let mut discarded = vec![invalid];
+        for (_, hash, previous_hash) in descendants {
+            if discarded.contains(&previous_hash) {
+                discarded.push(hash);
+            }
+        }
+        for hash in discarded {
+            self.index.remove(&hash);
+            self.storage.remove_block(&hash)?;
+        }
         Ok(())
     }
 
-    fn is_valid_block(&self, block: &Block) -> bool {
-        let last_block = self.latest_block();
-        if block.header.previous_hash != last_block.hash() {
-            return false; // Previous hash doesn't match
+    fn connect_block(&mut self, hash: H256) -> Result<Block, BlockchainError> {
+        let block = self.apply_to_state(hash)?;
+        self.storage.put_hash_at_height(self.main_chain.len() as u64, &hash)?;
+        self.main_chain.push(hash);
+        Ok(block)
+    }
+
+    fn disconnect_tip(&mut self) -> Result<Block, BlockchainError> {
+        assert!(self.main_chain.len() > 1, "cannot disconnect the genesis block");
+        let hash = self.main_chain.pop().unwrap();
+        self.storage.remove_hash_at_height(self.main_chain.len() as u64)?;
+        if let Some(undo) = self.undo.remove(&hash) {
+            self.state.undo_block(undo);
+        }
+        let block = self.load_block(&hash)?;
+        self.supply.disconnect(&BlockReward::for_block(&block));
+        Ok(block)
+    }
+
+    fn apply_to_state(&mut self, hash: H256) -> Result<Block, BlockchainError> {
+        let block = self.load_block(&hash)?;
+        if block.header.height > 0 {
+            validate_geometric_proof(&block.header.geometric_proof, &self.state)?;
+        }
+        let undo = self.state.apply_block(&block)?;
+        self.undo.insert(hash, undo);
+        // Genesis pays no reward.
+        if block.header.height > 0 {
+            self.supply.connect(&BlockReward::for_block(&block));
         }
+        Ok(block)
+    }
 
-        // Add more validation logic here (e.g. timestamp, difficulty)
+    fn is_on_main_chain(&self, hash: &H256) -> bool {
+        match self.index.get(hash) {
+            Some(entry) => self.main_chain.get(entry.header.height as usize) == Some(hash),
+            None => false,
+        }
+    }
 
-        true
+    fn load_block(&self, hash: &H256) -> Result<Block, BlockchainError> {
+        self.storage.get_block(hash)?.ok_or(BlockchainError::MissingBlock(*hash))
     }
 
-    fn find_nonce(&self, block: &mut Block) {
-        while !self.is_valid_proof_of_work(block) {
-            block.header.nonce += 1;
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// The triangle ownership ledger as of the tip of the active chain.
+    pub fn state(&self) -> &WorldState {
+        &self.state
+    }
+
+    /// Tokens issued and burned along the active chain.
+    pub fn supply(&self) -> &Supply {
+        &self.supply
+    }
+
+    pub fn get_block(&self, hash: &H256) -> Result<Option<Block>, BlockchainError> {
+        if !self.index.contains_key(hash) {
+            return Ok(None);
         }
+        self.load_block(hash).map(Some)
     }
 
-    fn is_valid_proof_of_work(&self, block: &Block) -> bool {
-        let target = u64::MAX / self.difficulty;
-        let hash_value = u64::from_le_bytes(block.hash().to_bytes()[..8].try_into().unwrap());
-        hash_value < target
+    pub fn get_header(&self, hash: &H256) -> Option<&BlockHeader> {
+        self.index.get(hash).map(|entry| &entry.header)
     }
 
-    pub fn get_block(&self, hash: &H256) -> Option<&Block> {
-        self.block_map.get(hash).map(|&index| &self.blocks[index])
+    /// The shallowest triangle that no block from genesis to `parent`
+    /// mined, for tests that leave the root unsubdivided.
+    #[cfg(test)]
+    pub(crate) fn unclaimed_proof(&self, parent: &H256) -> crate::core::address::TriangleAddress {
+        let mut claimed = std::collections::HashSet::new();
+        let mut header = self.get_header(parent);
+        while let Some(current) = header.filter(|current| current.height > 0) {
+            claimed.insert(current.geometric_proof.clone());
+            header = self.get_header(&current.previous_hash);
+        }
+        let root = crate::core::address::TriangleAddress::root();
+        (1..)
+            .flat_map(|depth| root.descendants(depth).filter(move |proof| proof.depth() == depth))
+            .find(|proof| !claimed.contains(proof))
+            .expect("some triangle is left")
     }
 
-    pub fn get_difficulty(&self) -> u64 {
-        self.difficulty
+    /// Looks up a stored transaction by hash.
+    pub fn get_transaction(&self, hash: &H256) -> Result<Option<Transaction>, BlockchainError> {
+        Ok(self.storage.get_transaction(hash)?)
     }
 
-    fn adjust_difficulty(&mut self) {
-        // Simplified difficulty adjustment algorithm
-        let last_adjustment_block = &self.blocks[self.blocks.len() - 10];
-        let time_taken = self.latest_block().header.timestamp - last_adjustment_block.header.timestamp;
-        let expected_time = 10 * 60; // 10 minutes
+    /// Hash of the block of the active chain that includes the transaction,
+    /// if any. Side branches may include it too; they are ignored.
+    pub fn get_transaction_block(&self, hash: &H256) -> Result<Option<H256>, BlockchainError> {
+        let blocks = self.storage.get_transaction_blocks(hash)?;
+        Ok(blocks.into_iter().find(|block_hash| self.is_on_main_chain(block_hash)))
+    }
+
+    /// Cumulative work of the branch ending at `hash`, or zero if the block
+    /// is unknown.
+    pub fn get_chain_work(&self, hash: &H256) -> U256 {
+        self.index.get(hash).map(|entry| entry.chain_work).unwrap_or_default()
+    }
 
-        if time_taken < expected_time / 2 {
-            self.difficulty *= 2;
-        } else if time_taken > expected_time * 2 {
-            self.difficulty /= 2;
+    /// Median timestamp of `parent_hash` and up to `MEDIAN_TIME_SPAN - 1` of
+    /// its ancestors. A child block must be stamped later than this.
+    pub fn median_time_past(&self, parent_hash: &H256) -> i64 {
+        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut cursor = self.get_header(parent_hash);
+        while let Some(header) = cursor {
+            timestamps.push(header.timestamp);
+            if timestamps.len() == MEDIAN_TIME_SPAN || header.height == 0 {
+                break;
+            }
+            cursor = self.get_header(&header.previous_hash);
         }
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(GENESIS_TIMESTAMP)
+    }
 
-        if self.difficulty == 0 { // Ensure difficulty is never zero
-            self.difficulty = 1;
+    /// Compact target required of the next block on the active chain.
+    pub fn get_bits(&self) -> u32 {
+        self.next_bits(&self.tip())
+    }
+
+    /// Compact target required of a block built on top of `parent_hash`,
+    /// which may be on any branch.
+    pub fn next_bits(&self, parent_hash: &H256) -> u32 {
+        let mut window = Vec::with_capacity(LWMA_WINDOW + 1);
+        let mut cursor = self.get_header(parent_hash);
+        while let Some(header) = cursor {
+            window.push(header);
+            if window.len() > LWMA_WINDOW || header.height == 0 {
+                break;
+            }
+            cursor = self.get_header(&header.previous_hash);
         }
+        if window.is_empty() {
+            return self.initial_bits;
+        }
+        window.reverse();
+        next_bits(&window)
+    }
+
+    /// Base fee required of a block built on top of `parent_hash`, which
+    /// may be on any branch.
+    pub fn next_base_fee(&self, parent_hash: &H256) -> Decimal {
+        self.get_header(parent_hash).map(next_base_fee).unwrap_or(INITIAL_BASE_FEE)
+    }
+
+    /// Hash of the tip of the active chain.
+    pub fn tip(&self) -> H256 {
+        *self.main_chain.last().unwrap()
+    }
+
+    pub fn latest_header(&self) -> &BlockHeader {
+        &self.index[&self.tip()].header
+    }
+
+    pub fn latest_block(&self) -> Result<Block, BlockchainError> {
+        self.load_block(&self.tip())
     }
 
-    pub fn latest_block(&self) -> &Block {
-        self.blocks.last().unwrap()
+    pub fn get_block_by_height(&self, height: u64) -> Result<Option<Block>, BlockchainError> {
+        match self.main_chain.get(height as usize) {
+            Some(hash) => self.load_block(hash).map(Some),
+            None => Ok(None),
+        }
     }
 
-    pub fn get_block_by_height(&self, height: u64) -> Option<&Block> {
-        self.blocks.get(height as usize)
+    pub fn get_header_by_height(&self, height: u64) -> Option<&BlockHeader> {
+        self.main_chain.get(height as usize).and_then(|hash| self.get_header(hash))
     }
 
+    /// Every triangle that can still be transferred or subdivided, ordered
+    /// by address.
     pub fn get_active_triangles(&self) -> Vec<FractalTriangle> {
-        let mut active_triangles = Vec::new();
-        for block in &self.blocks {
-            for tx in &block.triangle_transactions {
-                active_triangles.extend(tx.get_fractal_triangles());
+        self.state
+            .active_leaves()
+            .filter_map(|address| {
+                let record = self.state.get(address)?;
+                Some(FractalTriangle::new(record.triangle.clone(), record.state.clone(), address.clone()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::rules::MAX_FUTURE_DRIFT;
+    use crate::consensus::weight::{transaction_weight, MAX_BLOCK_WEIGHT};
+    use crate::core::address::TriangleAddress;
+    use crate::core::clock::MockClock;
+    use crate::core::errors::{BalanceError, StateError, ValidationError};
+    use crate::core::genesis::test_genesis_owner;
+    use crate::core::transaction::{TriangleOperation, CHAIN_ID};
+    use crate::wallet::address::Address;
+    use ed25519_dalek::Keypair;
+    use rust_decimal_macros::dec;
+
+    /// A chain at the lowest difficulty whose genesis triangle belongs to
+    /// `test_genesis_owner`.
+    fn test_chain() -> Blockchain {
+        Blockchain::with_genesis_owner(1, test_genesis_owner().public)
+    }
+
+    fn child_of(chain: &Blockchain, parent: &H256, seconds: i64) -> Block {
+        child_with(chain, parent, seconds, Vec::new())
+    }
+
+    /// Comfortably above the base fee of the first blocks.
+    const FEE: Decimal = dec!(0.01);
+
+    /// A block on `parent` carrying a valid coinbase followed by `transactions`.
+    fn child_with(chain: &Blockchain, parent: &H256, seconds: i64, transactions: Vec<Transaction>) -> Block {
+        let miner = Keypair::generate(&mut rand::thread_rng());
+        child_paying(chain, parent, seconds, &miner, transactions)
+    }
+
+    /// Like `child_with`, with the coinbase paid to `miner`. The reward is
+    /// minted before the transactions run, so they can spend it.
+    fn child_paying(
+        chain: &Blockchain,
+        parent: &H256,
+        seconds: i64,
+        miner: &Keypair,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let proof = chain.unclaimed_proof(parent);
+        child_claiming(chain, parent, seconds, proof, miner, transactions)
+    }
+
+    /// Like `child_paying`, mining `proof` whether or not it can be claimed.
+    fn child_claiming(
+        chain: &Blockchain,
+        parent: &H256,
+        seconds: i64,
+        proof: TriangleAddress,
+        miner: &Keypair,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let parent_header = chain.get_header(parent).unwrap();
+        let mut block = Block::new(
+            *parent,
+            H256::default(),
+            parent_header.timestamp + seconds,
+            chain.next_bits(parent),
+            chain.next_base_fee(parent),
+            parent_header.height + 1,
+            transactions,
+        );
+        block.header.geometric_proof = proof;
+        let amount = BlockReward::for_block(&block).coinbase_amount();
+        block.triangle_transactions.insert(0, Transaction::new_coinbase(amount, block.header.height, miner));
+        block.header.merkle_root = MerkleTree::new(&block.triangle_transactions).get_root();
+        mine(&mut block);
+        block
+    }
+
+    /// Grinds the nonce for a while. At difficulty 1 this virtually always
+    /// succeeds; at high difficulty the block is left without a valid proof.
+    fn mine(block: &mut Block) {
+        for nonce in 0..64 {
+            block.header.nonce = nonce;
+            if crate::consensus::rules::is_valid_proof_of_work(&block.header) {
+                return;
             }
         }
-        active_triangles
+    }
+
+    #[test]
+    fn test_extend_tip() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let block = child_of(&chain, &genesis, 60);
+        let hash = block.hash();
+
+        let update = chain.add_block(block).unwrap();
+        assert!(!update.is_reorg());
+        assert_eq!(update.connected.len(), 1);
+        assert_eq!(chain.tip(), hash);
+        assert_eq!(chain.get_header_by_height(1).unwrap().hash(), hash);
+    }
+
+    #[test]
+    fn test_side_branch_is_kept() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let a1 = child_of(&chain, &genesis, 60);
+        let a1_hash = a1.hash();
+        chain.add_block(a1).unwrap();
+
+        let b1 = child_of(&chain, &genesis, 61);
+        let b1_hash = b1.hash();
+        let update = chain.add_block(b1).unwrap();
+
+        assert!(update.connected.is_empty() && update.disconnected.is_empty());
+        assert_eq!(chain.tip(), a1_hash);
+        assert!(chain.get_block(&b1_hash).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_reorg_to_heavier_branch() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let a1 = child_of(&chain, &genesis, 60);
+        let a1_hash = a1.hash();
+        chain.add_block(a1).unwrap();
+
+        let b1 = child_of(&chain, &genesis, 61);
+        let b1_hash = b1.hash();
+        chain.add_block(b1).unwrap();
+        let b2 = child_of(&chain, &b1_hash, 60);
+        let b2_hash = b2.hash();
+        let update = chain.add_block(b2).unwrap();
+
+        assert!(update.is_reorg());
+        let disconnected: Vec<H256> = update.disconnected.iter().map(Block::hash).collect();
+        let connected: Vec<H256> = update.connected.iter().map(Block::hash).collect();
+        assert_eq!(disconnected, vec![a1_hash]);
+        assert_eq!(connected, vec![b1_hash, b2_hash]);
+        assert_eq!(chain.tip(), b2_hash);
+        assert_eq!(chain.get_header_by_height(1).unwrap().hash(), b1_hash);
+        assert_eq!(chain.get_chain_work(&b2_hash), U256::from(3));
+    }
+
+    #[test]
+    fn test_reject_unknown_parent_and_duplicates() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let block = child_of(&chain, &genesis, 60);
+        chain.add_block(block.clone()).unwrap();
+        assert!(matches!(chain.add_block(block), Err(BlockchainError::DuplicateBlock)));
+
+        let mut orphan = child_of(&chain, &genesis, 60);
+        orphan.header.previous_hash = H256::from([7u8; 32]);
+        assert!(matches!(chain.add_block(orphan), Err(BlockchainError::UnknownParent)));
+    }
+
+    #[test]
+    fn test_listener_sees_disconnects_before_connects() {
+        struct Recorder(Vec<(bool, u64)>);
+        impl ChainListener for Recorder {
+            fn block_connected(&mut self, block: &Block) {
+                self.0.push((true, block.header.height));
+            }
+            fn block_disconnected(&mut self, block: &Block) {
+                self.0.push((false, block.header.height));
+            }
+        }
+
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        chain.add_block(child_of(&chain, &genesis, 60)).unwrap();
+        let b1 = child_of(&chain, &genesis, 61);
+        let b1_hash = b1.hash();
+        chain.add_block(b1).unwrap();
+        let update = chain.add_block(child_of(&chain, &b1_hash, 60)).unwrap();
+
+        let mut recorder = Recorder(Vec::new());
+        update.notify(&mut recorder);
+        assert_eq!(recorder.0, vec![(false, 1), (true, 1), (true, 2)]);
+    }
+
+    #[test]
+    fn test_add_block_verifies_instead_of_mining() {
+        let mut chain = Blockchain::with_difficulty(u64::MAX);
+        let genesis = chain.tip();
+        let block = child_of(&chain, &genesis, 60);
+        // With the maximum difficulty virtually no nonce is valid, and the
+        // chain must not try to find one itself.
+        assert!(matches!(
+            chain.add_block(block),
+            Err(BlockchainError::Invalid(ValidationError::InsufficientProofOfWork))
+        ));
+        assert_eq!(chain.tip(), genesis);
+    }
+
+    #[test]
+    fn test_reject_bad_merkle_root_and_timestamp() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+
+        let mut bad_root = child_of(&chain, &genesis, 60);
+        bad_root.header.merkle_root = H256::from([1u8; 32]);
+        mine(&mut bad_root);
+        assert!(matches!(
+            chain.add_block(bad_root),
+            Err(BlockchainError::Invalid(ValidationError::MerkleRootMismatch { .. }))
+        ));
+
+        let stale = child_of(&chain, &genesis, 0);
+        assert!(matches!(
+            chain.add_block(stale),
+            Err(BlockchainError::Invalid(ValidationError::TimestampTooEarly { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_reject_repeated_transaction_with_same_merkle_root() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let transfers: Vec<Transaction> = (0..2)
+            .map(|_| {
+                let signer = Keypair::generate(&mut rand::thread_rng());
+                let operation = TriangleOperation::Transfer { from: TriangleAddress::root(), to: signer.public };
+                Transaction::with_fee(operation, 0, FEE, &signer)
+            })
+            .collect();
+        let block = child_with(&chain, &genesis, 60, transfers);
+
+        // Three leaves hash like four with the last one repeated.
+        let mut repeated = block.clone();
+        repeated.triangle_transactions.push(repeated.triangle_transactions[2].clone());
+        repeated.header.transaction_count += 1;
+        assert_eq!(MerkleTree::new(&repeated.triangle_transactions).get_root(), block.header.merkle_root);
+        mine(&mut repeated);
+        assert!(matches!(
+            chain.add_block(repeated),
+            Err(BlockchainError::Invalid(ValidationError::DuplicateTransaction(3)))
+        ));
+    }
+
+    #[test]
+    fn test_reject_block_too_far_in_future() {
+        let clock = MockClock::new(GENESIS_TIMESTAMP + 60);
+        let mut chain = test_chain().with_clock(clock.clone());
+        let genesis = chain.tip();
+
+        let early = child_of(&chain, &genesis, 60 + MAX_FUTURE_DRIFT + 1);
+        assert!(matches!(
+            chain.add_block(early.clone()),
+            Err(BlockchainError::Invalid(ValidationError::TimestampTooFarInFuture { .. }))
+        ));
+        clock.advance(1);
+        chain.add_block(early).unwrap();
+    }
+
+    #[test]
+    fn test_timestamp_must_follow_median_time_past() {
+        let mut chain = test_chain();
+        for _ in 0..MEDIAN_TIME_SPAN {
+            let tip = chain.tip();
+            chain.add_block(child_of(&chain, &tip, 60)).unwrap();
+        }
+        let tip = chain.tip();
+        let median = chain.median_time_past(&tip);
+        assert_eq!(median, chain.latest_header().timestamp - 5 * 60);
+
+        let at_median = child_of(&chain, &tip, median - chain.latest_header().timestamp);
+        assert!(matches!(
+            chain.add_block(at_median),
+            Err(BlockchainError::Invalid(ValidationError::TimestampTooEarly { .. }))
+        ));
+        // Earlier than the parent is fine as long as it is after the median.
+        chain.add_block(child_of(&chain, &tip, -60)).unwrap();
+    }
+
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let a = test_chain();
+        let b = test_chain();
+        assert_eq!(a.tip(), b.tip());
+    }
+
+    #[test]
+    fn test_reject_block_spending_foreign_triangle() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let thief = Keypair::generate(&mut rand::thread_rng());
+        let tx = Transaction::with_fee(
+            TriangleOperation::Transfer { from: TriangleAddress::root(), to: thief.public },
+            0,
+            FEE,
+            &thief,
+        );
+        let block = child_with(&chain, &genesis, 60, vec![tx]);
+
+        assert!(matches!(
+            chain.add_block(block),
+            Err(BlockchainError::Invalid(ValidationError::Transaction { index: 1, error: StateError::NotOwner(_) }))
+        ));
+        assert_eq!(chain.tip(), genesis);
+        assert_eq!(chain.state().owner_of(&TriangleAddress::root()), Some(&test_genesis_owner().public));
+    }
+
+    #[test]
+    fn test_reject_foreign_chain_and_expired_transactions() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let owner = test_genesis_owner();
+        let operation = TriangleOperation::Transfer { from: TriangleAddress::root(), to: owner.public };
+
+        let foreign = Transaction::signed(CHAIN_ID + 1, operation.clone(), 0, None, FEE, &owner);
+        assert!(matches!(
+            chain.add_block(child_with(&chain, &genesis, 60, vec![foreign])),
+            Err(BlockchainError::Invalid(ValidationError::WrongChain { index: 1, .. }))
+        ));
+
+        let expired = Transaction::signed(CHAIN_ID, operation.clone(), 0, Some(0), FEE, &owner);
+        assert!(matches!(
+            chain.add_block(child_with(&chain, &genesis, 60, vec![expired])),
+            Err(BlockchainError::Invalid(ValidationError::Expired { index: 1, expiry: 0 }))
+        ));
+
+        let current = Transaction::signed(CHAIN_ID, operation, 0, Some(1), FEE, &owner);
+        chain.add_block(child_paying(&chain, &genesis, 60, &owner, vec![current])).unwrap();
+    }
+
+    #[test]
+    fn test_reorg_reverts_ledger_and_rejects_invalid_branch() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let owner = test_genesis_owner();
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+
+        let transfer = Transaction::with_fee(TriangleOperation::Transfer { from: root.clone(), to: other.public }, 0, FEE, &owner);
+        let a1 = child_paying(&chain, &genesis, 60, &owner, vec![transfer]);
+        let a1_hash = a1.hash();
+        chain.add_block(a1).unwrap();
+        assert_eq!(chain.state().owner_of(&root), Some(&other.public));
+
+        // A heavier branch that spends the root as the new owner would is
+        // invalid on its own fork, so the chain must stay on `a1`.
+        let spend = Transaction::with_fee(TriangleOperation::Transfer { from: root.clone(), to: owner.public }, 0, FEE, &other);
+        let b1 = child_of(&chain, &genesis, 61);
+        let b1_hash = b1.hash();
+        chain.add_block(b1).unwrap();
+        let b2 = child_with(&chain, &b1_hash, 60, vec![spend]);
+        assert!(matches!(chain.add_block(b2), Err(BlockchainError::Invalid(ValidationError::Transaction { .. }))));
+        assert_eq!(chain.tip(), a1_hash);
+        assert_eq!(chain.state().owner_of(&root), Some(&other.public));
+
+        // A valid heavier branch disconnects `a1` and its transfer.
+        let c1 = child_of(&chain, &genesis, 62);
+        let c1_hash = c1.hash();
+        chain.add_block(c1).unwrap();
+        let c2 = child_of(&chain, &c1_hash, 60);
+        let c2_hash = c2.hash();
+        chain.add_block(c2).unwrap();
+        assert_eq!(chain.tip(), c2_hash);
+        assert_eq!(chain.state().owner_of(&root), Some(&owner.public));
+        assert_eq!(chain.state().balances().balance(&Address::from_pubkey(&owner.public)), Decimal::ZERO);
+        assert_eq!(chain.state().balances().total_supply(), chain.supply().circulating());
+    }
+
+    #[test]
+    fn test_reject_wrong_or_missing_coinbase() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let miner = Keypair::generate(&mut rand::thread_rng());
+
+        let mut overpaid = child_of(&chain, &genesis, 60);
+        overpaid.triangle_transactions[0] = Transaction::new_coinbase(dec!(1000), 1, &miner);
+        overpaid.header.merkle_root = MerkleTree::new(&overpaid.triangle_transactions).get_root();
+        mine(&mut overpaid);
+        assert!(matches!(
+            chain.add_block(overpaid),
+            Err(BlockchainError::Invalid(ValidationError::BadCoinbase { .. }))
+        ));
+
+        let mut missing = child_of(&chain, &genesis, 60);
+        missing.triangle_transactions.clear();
+        missing.header.merkle_root = H256::default();
+        mine(&mut missing);
+        assert!(matches!(chain.add_block(missing), Err(BlockchainError::Invalid(ValidationError::MissingCoinbase))));
+
+        let second = Transaction::new_coinbase(dec!(100), 1, &miner);
+        let twice = child_with(&chain, &genesis, 60, vec![second]);
+        assert!(matches!(
+            chain.add_block(twice),
+            Err(BlockchainError::Invalid(ValidationError::UnexpectedCoinbase(1)))
+        ));
+    }
+
+    #[test]
+    fn test_fees_burn_base_fee_and_pay_tip() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let owner = test_genesis_owner();
+        let root = TriangleAddress::root();
+        let base_fee = chain.next_base_fee(&genesis);
+        assert!(base_fee < INITIAL_BASE_FEE);
+
+        let unpaid = Transaction::new(TriangleOperation::Transfer { from: root.clone(), to: owner.public }, 0, &owner);
+        assert!(matches!(
+            chain.add_block(child_with(&chain, &genesis, 60, vec![unpaid])),
+            Err(BlockchainError::Invalid(ValidationError::FeeBelowBaseFee { index: 1, fee, base_fee: required }))
+                if fee.is_zero() && required == base_fee
+        ));
+
+        let mut wrong_fee = child_of(&chain, &genesis, 60);
+        wrong_fee.header.base_fee = INITIAL_BASE_FEE;
+        mine(&mut wrong_fee);
+        assert!(matches!(
+            chain.add_block(wrong_fee),
+            Err(BlockchainError::Invalid(ValidationError::WrongBaseFee { .. }))
+        ));
+        let mut miscounted = child_of(&chain, &genesis, 60);
+        miscounted.header.transaction_count = 1;
+        mine(&mut miscounted);
+        assert!(matches!(
+            chain.add_block(miscounted),
+            Err(BlockchainError::Invalid(ValidationError::BadTransactionCount { expected: 0, found: 1 }))
+        ));
+
+        let paid = Transaction::with_fee(TriangleOperation::Transfer { from: root, to: owner.public }, 0, FEE, &owner);
+        let block = child_paying(&chain, &genesis, 60, &owner, vec![paid]);
+        match &block.triangle_transactions[0].operation {
+            TriangleOperation::Coinbase { amount, .. } => assert_eq!(*amount, dec!(50) + FEE - base_fee),
+            other => panic!("expected a coinbase, got {:?}", other),
+        }
+        chain.add_block(block).unwrap();
+        assert_eq!(chain.supply().burned, base_fee);
+        assert_eq!(chain.supply().circulating(), dec!(50) - base_fee);
+        let balances = chain.state().balances();
+        assert_eq!(balances.balance(&Address::from_pubkey(&owner.public)), dec!(50) - base_fee);
+        assert_eq!(balances.total_supply(), chain.supply().circulating());
+    }
+
+    #[test]
+    fn test_token_transfers_need_funds() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let owner = test_genesis_owner();
+        let owner_account = Address::from_pubkey(&owner.public);
+        let recipient = Address::from_pubkey(&Keypair::generate(&mut rand::thread_rng()).public);
+        let pay = |amount, nonce| Transaction::with_fee(TriangleOperation::TokenTransfer { to: recipient, amount }, nonce, FEE, &owner);
+
+        // The fee has to be covered on top of the amount.
+        let overdraft = child_paying(&chain, &genesis, 60, &owner, vec![pay(dec!(50), 0)]);
+        assert!(matches!(
+            chain.add_block(overdraft),
+            Err(BlockchainError::Invalid(ValidationError::Transaction {
+                index: 1,
+                error: StateError::Balance(BalanceError::InsufficientBalance { .. })
+            }))
+        ));
+
+        let block = child_paying(&chain, &genesis, 60, &owner, vec![pay(dec!(40), 0)]);
+        let hash = block.hash();
+        chain.add_block(block).unwrap();
+        let balances = chain.state().balances();
+        assert_eq!(balances.balance(&recipient), dec!(40));
+        assert_eq!(balances.balance(&owner_account), dec!(50) - dec!(40) - chain.get_header(&hash).unwrap().base_fee);
+        assert_eq!(balances.total_supply(), chain.supply().circulating());
+        assert!(chain.state().check_transaction(&pay(dec!(10), 1), 2).is_err());
+        chain.state().check_transaction(&pay(dec!(5), 1), 2).unwrap();
+    }
+
+    #[test]
+    fn test_reject_block_over_max_weight() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let owner = test_genesis_owner();
+        let tx = Transaction::with_fee(TriangleOperation::Transfer { from: TriangleAddress::root(), to: owner.public }, 0, FEE, &owner);
+        // Rejected on weight alone, before the repeated spend is looked at.
+        let count = (MAX_BLOCK_WEIGHT / transaction_weight(&tx)) as usize + 1;
+        let block = child_with(&chain, &genesis, 60, vec![tx; count]);
+        assert!(matches!(
+            chain.add_block(block),
+            Err(BlockchainError::Invalid(ValidationError::BlockTooHeavy { max: MAX_BLOCK_WEIGHT, .. }))
+        ));
+        assert_eq!(chain.tip(), genesis);
+    }
+
+    #[test]
+    fn test_reject_void_as_geometric_proof() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let miner = Keypair::generate(&mut rand::thread_rng());
+        let void = TriangleAddress::root().void().unwrap();
+        let block = child_claiming(&chain, &genesis, 60, void, &miner, Vec::new());
+        assert!(matches!(chain.add_block(block), Err(BlockchainError::Invalid(ValidationError::VoidProof(_)))));
+    }
+
+    #[test]
+    fn test_reject_geometric_proof_missing_from_ledger() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let miner = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+
+        // The root is still active, so only triangles below it can be
+        // claimed, not the root itself, which would pay the full reward.
+        let block = child_claiming(&chain, &genesis, 60, root.clone(), &miner, Vec::new());
+        assert!(matches!(
+            chain.add_block(block),
+            Err(BlockchainError::Invalid(ValidationError::UnclaimableProof(found))) if found == root
+        ));
+
+        // Off the active chain the proof is checked when the branch connects.
+        let a1 = child_of(&chain, &genesis, 60);
+        let a1_hash = a1.hash();
+        chain.add_block(a1).unwrap();
+        let b1 = child_claiming(&chain, &genesis, 61, root.clone(), &miner, Vec::new());
+        let b1_hash = b1.hash();
+        chain.add_block(b1).unwrap();
+        let b2 = child_of(&chain, &b1_hash, 60);
+        assert!(matches!(
+            chain.add_block(b2),
+            Err(BlockchainError::Invalid(ValidationError::UnclaimableProof(found))) if found == root
+        ));
+        assert_eq!(chain.tip(), a1_hash);
+        assert!(chain.get_header(&b1_hash).is_none());
+    }
+
+    #[test]
+    fn test_reject_reused_geometric_proof() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        let miner = Keypair::generate(&mut rand::thread_rng());
+        let proof = TriangleAddress::root().append(1).append(2);
+
+        let a1 = child_claiming(&chain, &genesis, 60, proof.clone(), &miner, Vec::new());
+        let a1_hash = a1.hash();
+        chain.add_block(a1).unwrap();
+        assert!(chain.state().is_claimed(&proof));
+        let a2 = child_claiming(&chain, &a1_hash, 60, proof.clone(), &miner, Vec::new());
+        assert!(matches!(
+            chain.add_block(a2),
+            Err(BlockchainError::Invalid(ValidationError::ProofAlreadyClaimed(found))) if found == proof
+        ));
+
+        // Disconnecting the block gives its proof back.
+        let other = TriangleAddress::root().append(2);
+        let b1 = child_claiming(&chain, &genesis, 61, other.clone(), &miner, Vec::new());
+        let b1_hash = b1.hash();
+        chain.add_block(b1).unwrap();
+        let b2 = child_of(&chain, &b1_hash, 60);
+        assert!(chain.add_block(b2).unwrap().is_reorg());
+        assert!(!chain.state().is_claimed(&proof));
+        assert!(chain.state().is_claimed(&other));
+    }
+
+    #[test]
+    fn test_supply_follows_active_chain() {
+        let mut chain = test_chain();
+        let genesis = chain.tip();
+        assert_eq!(chain.supply().issued, Decimal::ZERO);
+
+        let a1 = child_of(&chain, &genesis, 60);
+        chain.add_block(a1).unwrap();
+        assert_eq!(chain.supply().issued, dec!(50));
+
+        let b1 = child_of(&chain, &genesis, 61);
+        let b1_hash = b1.hash();
+        chain.add_block(b1).unwrap();
+        let b2 = child_of(&chain, &b1_hash, 60);
+        chain.add_block(b2).unwrap();
+
+        assert_eq!(chain.supply().issued, dec!(100));
+        assert_eq!(chain.supply().burned, Decimal::ZERO);
     }
 }
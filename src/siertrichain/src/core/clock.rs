@@ -0,0 +1,47 @@
+use chrono::Utc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+/// Source of the current time in Unix seconds. Everything that stamps or
+/// checks block timestamps reads the time through this trait, so tests can
+/// control it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// The system wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a
+/// test can keep one handle and advance the clock of a chain or miner.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    now: Arc<AtomicI64>,
+}
+
+impl MockClock {
+    pub fn new(now: i64) -> Self {
+        Self { now: Arc::new(AtomicI64::new(now)) }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
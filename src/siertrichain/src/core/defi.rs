@@ -0,0 +1,723 @@
+//! Decentralized finance on top of the triangle and token ledgers.
+//!
+//! Staking, insurance, lending, rentals, the exchange, liquidity pools and
+//! atomic swaps are driven by `DefiOperation`s inside transactions, so every
+//! node applies them in block order and `WorldState` undoes them together
+//! with the rest of a block. Tokens a module holds for its users sit in the
+//! module's account on the token ledger, which no key controls: only the
+//! module's own operations pay out of it, and plain token transfers into it
+//! are refused.
+//!
+//! `DefiState::check` decides whether an operation may run, without
+//! changing anything. `DefiState::apply` then updates the module's books and
+//! returns the payments and triangle handovers for `WorldState` to carry out.
+//! Stakes, loan collateral and rentals also encumber their triangle in
+//! `WorldState`, which refuses to transfer, subdivide or merge it until they
+//! end.
+
+use crate::core::address::TriangleAddress;
+use crate::core::encoding::{Decode, Encode, Reader};
+use crate::core::errors::{BalanceError, DecodeError, DefiError, StateError};
+use crate::core::exchange::{Exchange, Order, OrderType};
+use crate::core::fractal::TriangleState;
+use crate::core::hash::H256;
+use crate::core::insurance::InsuranceManager;
+use crate::core::lending::LendingPlatform;
+use crate::core::liquidity_pools::LiquidityPoolManager;
+use crate::core::rental::{RentalManager, RentalOffer};
+use crate::core::staking::{Stake, StakingManager};
+use crate::core::state::{Encumbrance, TriangleRecord, WorldState};
+use crate::core::swaps::{hash_secret, AtomicSwap, SwapManager, SwapState};
+use crate::wallet::address::Address;
+use ed25519_dalek::PublicKey;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DefiOperation {
+    /// Locks `amount` of the signer's tokens on a triangle the signer owns.
+    Stake { triangle: TriangleAddress, amount: Decimal },
+    /// Returns everything the signer staked on `triangle`.
+    Unstake { triangle: TriangleAddress },
+    /// Pays `amount` to the stakers of `triangle`, in proportion to their
+    /// stakes.
+    RewardStakers { triangle: TriangleAddress, amount: Decimal },
+    /// Insures a triangle of the signer for `insured_value` until
+    /// `expiration_block`, paying `premium` into the insurance account.
+    Insure { triangle: TriangleAddress, insured_value: Decimal, premium: Decimal, expiration_block: u64 },
+    /// Pays out the signer's running policies on `triangle` once it has been
+    /// subdivided.
+    Claim { triangle: TriangleAddress },
+    /// Asks for a loan of `amount` against a triangle of the signer, to be
+    /// repaid with `interest_rate` on top within `term` blocks of funding.
+    RequestLoan { collateral: TriangleAddress, amount: Decimal, interest_rate: Decimal, term: u64 },
+    /// Lends `amount`, the requested amount, to the borrower.
+    FundLoan { collateral: TriangleAddress, amount: Decimal },
+    /// Pays the lender `amount`, the loan with interest, and frees the
+    /// collateral. Withdraws a request nobody funded with an amount of zero.
+    RepayLoan { collateral: TriangleAddress, amount: Decimal },
+    /// Gives the lender the collateral of a loan that was not repaid in time.
+    Liquidate { collateral: TriangleAddress },
+    /// Offers a triangle of the signer for rent at `price` per `duration`
+    /// blocks.
+    OfferRental { triangle: TriangleAddress, price: Decimal, duration: u64 },
+    /// Takes the signer's triangle off the rental market.
+    WithdrawRental { triangle: TriangleAddress },
+    /// Rents `triangle` at its offered `price`, paid to the owner.
+    Rent { triangle: TriangleAddress, price: Decimal },
+    /// Places an order for `triangle`. A bid holds its price until it is
+    /// filled or cancelled, an ask needs the signer to own the triangle. An
+    /// order that meets the best one on the other side is filled at once, at
+    /// the price of the order already in the book.
+    PlaceOrder { triangle: TriangleAddress, order_type: OrderType, price: Decimal },
+    /// Withdraws the signer's orders on `triangle` and refunds their bids.
+    CancelOrders { triangle: TriangleAddress },
+    /// Puts `amount` of the signer's tokens into the pool of `triangle`.
+    AddLiquidity { triangle: TriangleAddress, amount: Decimal },
+    /// Takes back everything the signer put into the pool of `triangle`.
+    RemoveLiquidity { triangle: TriangleAddress },
+    /// Locks `amount` for `participant` until block `timeout`, to be released
+    /// by the secret that hashes to `secret_hash`.
+    InitiateSwap { participant: Address, amount: Decimal, secret_hash: H256, timeout: u64 },
+    /// Pays the swap locked by the hash of `secret` to its participant.
+    RedeemSwap { secret: H256 },
+    /// Returns the tokens of a swap that timed out to its initiator.
+    RefundSwap { secret_hash: H256 },
+}
+
+impl DefiOperation {
+    /// Tokens the signer pays, on top of the fee.
+    pub fn payment(&self) -> Decimal {
+        match self {
+            DefiOperation::Stake { amount, .. }
+            | DefiOperation::RewardStakers { amount, .. }
+            | DefiOperation::FundLoan { amount, .. }
+            | DefiOperation::RepayLoan { amount, .. }
+            | DefiOperation::AddLiquidity { amount, .. }
+            | DefiOperation::InitiateSwap { amount, .. } => *amount,
+            DefiOperation::Insure { premium, .. } => *premium,
+            DefiOperation::Rent { price, .. } | DefiOperation::PlaceOrder { order_type: OrderType::Buy, price, .. } => *price,
+            _ => Decimal::ZERO,
+        }
+    }
+
+    /// The triangle the operation concerns, if any.
+    pub fn triangle(&self) -> Option<&TriangleAddress> {
+        match self {
+            DefiOperation::Stake { triangle, .. }
+            | DefiOperation::Unstake { triangle }
+            | DefiOperation::RewardStakers { triangle, .. }
+            | DefiOperation::Insure { triangle, .. }
+            | DefiOperation::Claim { triangle }
+            | DefiOperation::OfferRental { triangle, .. }
+            | DefiOperation::WithdrawRental { triangle }
+            | DefiOperation::Rent { triangle, .. }
+            | DefiOperation::PlaceOrder { triangle, .. }
+            | DefiOperation::CancelOrders { triangle }
+            | DefiOperation::AddLiquidity { triangle, .. }
+            | DefiOperation::RemoveLiquidity { triangle } => Some(triangle),
+            DefiOperation::RequestLoan { collateral, .. }
+            | DefiOperation::FundLoan { collateral, .. }
+            | DefiOperation::RepayLoan { collateral, .. }
+            | DefiOperation::Liquidate { collateral } => Some(collateral),
+            DefiOperation::InitiateSwap { .. } | DefiOperation::RedeemSwap { .. } | DefiOperation::RefundSwap { .. } => None,
+        }
+    }
+
+    /// The triangle the signer must own and be able to spend.
+    pub fn owned(&self) -> Option<&TriangleAddress> {
+        match self {
+            DefiOperation::Stake { triangle, .. }
+            | DefiOperation::Insure { triangle, .. }
+            | DefiOperation::OfferRental { triangle, .. }
+            | DefiOperation::PlaceOrder { triangle, order_type: OrderType::Sell, .. }
+            | DefiOperation::RequestLoan { collateral: triangle, .. } => Some(triangle),
+            _ => None,
+        }
+    }
+
+    /// The triangle the operation consumes in its block: the one the signer
+    /// must own, or the collateral a liquidation hands over.
+    pub fn spends(&self) -> Option<&TriangleAddress> {
+        match self {
+            DefiOperation::Liquidate { collateral } => Some(collateral),
+            _ => self.owned(),
+        }
+    }
+}
+
+/// A change a DeFi operation makes outside the books of its module.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// Moves tokens on the token ledger.
+    Pay { from: Address, to: Address, amount: Decimal },
+    /// Gives a triangle, which `from` must still own, to `to`.
+    HandOver { triangle: TriangleAddress, from: Address, to: Address },
+}
+
+/// The books of every DeFi module, as of the tip of the active chain.
+#[derive(Debug, Clone, Default)]
+pub struct DefiState {
+    pub staking: StakingManager,
+    pub insurance: InsuranceManager,
+    pub lending: LendingPlatform,
+    pub rental: RentalManager,
+    pub exchange: Exchange,
+    pub liquidity: LiquidityPoolManager,
+    pub swaps: SwapManager,
+}
+
+impl DefiState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks that `signer` may run `operation` in the block at `height`.
+    /// Ownership of `DefiOperation::owned` and the signer's balance are
+    /// checked by `WorldState`.
+    pub fn check(&self, operation: &DefiOperation, signer: &PublicKey, height: u64, state: &WorldState) -> Result<(), StateError> {
+        let account = Address::from_pubkey(signer);
+        let record = |address: &TriangleAddress| state.get(address).ok_or_else(|| StateError::UnknownTriangle(address.clone()));
+        let id = |address: &TriangleAddress| record(address).map(|record| record.triangle.hash());
+        // Collateral excludes every other encumbrance, so nothing can keep
+        // a lender from liquidating.
+        let unless = |address: &TriangleAddress, allowed: fn(Encumbrance) -> bool| match state.encumbrance(address, height) {
+            Some(encumbrance) if !allowed(encumbrance) => Err(StateError::Encumbered { triangle: address.clone(), encumbrance }),
+            _ => Ok(()),
+        };
+        match operation {
+            DefiOperation::Stake { triangle, amount } => {
+                positive(*amount)?;
+                unless(triangle, |encumbrance| encumbrance != Encumbrance::Collateral)?;
+            }
+            DefiOperation::Unstake { triangle } => {
+                if self.staking.staked_by(&id(triangle)?, &account).is_zero() {
+                    return Err(DefiError::NothingStaked(triangle.clone()).into());
+                }
+            }
+            DefiOperation::RewardStakers { triangle, amount } => {
+                positive(*amount)?;
+                if !self.staking.is_staked(&id(triangle)?) {
+                    return Err(DefiError::NoStakers(triangle.clone()).into());
+                }
+            }
+            DefiOperation::Insure { insured_value, premium, expiration_block, .. } => {
+                positive(*insured_value)?;
+                positive(*premium)?;
+                if *expiration_block < height {
+                    return Err(DefiError::Expired(*expiration_block).into());
+                }
+                // The account must be able to pay every running policy at once.
+                let needed = self
+                    .insurance
+                    .outstanding_cover(height)
+                    .and_then(|cover| cover.checked_add(*insured_value))
+                    .ok_or(BalanceError::Overflow)?;
+                let available = state
+                    .balances()
+                    .balance(&InsuranceManager::account())
+                    .checked_add(*premium)
+                    .ok_or(BalanceError::Overflow)?;
+                if available < needed {
+                    return Err(DefiError::InsufficientCover { needed, available }.into());
+                }
+            }
+            DefiOperation::Claim { triangle } => {
+                let record = record(triangle)?;
+                if record.state != TriangleState::Subdivided {
+                    return Err(StateError::NotSubdivided(triangle.clone()));
+                }
+                let payout = self.insurance.claimable(&record.triangle.hash(), &account, height);
+                if payout.is_zero() {
+                    return Err(DefiError::NoPolicy(triangle.clone()).into());
+                }
+                state.balances().check_debit(&InsuranceManager::account(), payout)?;
+            }
+            DefiOperation::RequestLoan { collateral, amount, interest_rate, .. } => {
+                positive(*amount)?;
+                if *interest_rate < Decimal::ZERO {
+                    return Err(DefiError::NegativeRate(*interest_rate).into());
+                }
+                amount
+                    .checked_mul(*interest_rate)
+                    .and_then(|interest| amount.checked_add(interest))
+                    .ok_or(BalanceError::Overflow)?;
+                if self.lending.loan(&id(collateral)?).is_some() {
+                    return Err(DefiError::LoanExists(collateral.clone()).into());
+                }
+                unless(collateral, |_| false)?;
+            }
+            DefiOperation::FundLoan { collateral, amount } => {
+                let loan = self.lending.loan(&id(collateral)?).ok_or_else(|| DefiError::NoLoan(collateral.clone()))?;
+                if loan.lender.is_some() {
+                    return Err(DefiError::AlreadyFunded(collateral.clone()).into());
+                }
+                expect_payment(loan.loan_amount, *amount)?;
+            }
+            DefiOperation::RepayLoan { collateral, amount } => {
+                let loan = self.lending.loan(&id(collateral)?).ok_or_else(|| DefiError::NoLoan(collateral.clone()))?;
+                if loan.borrower != account {
+                    return Err(DefiError::NotParty("loan").into());
+                }
+                expect_payment(loan.amount_due().ok_or(BalanceError::Overflow)?, *amount)?;
+            }
+            DefiOperation::Liquidate { collateral } => {
+                let loan = self.lending.loan(&id(collateral)?).ok_or_else(|| DefiError::NoLoan(collateral.clone()))?;
+                if loan.lender != Some(account) {
+                    return Err(DefiError::NotParty("loan").into());
+                }
+                if height <= loan.due_block() {
+                    return Err(DefiError::NotDue { triangle: collateral.clone(), due: loan.due_block() }.into());
+                }
+            }
+            DefiOperation::OfferRental { price, duration, .. } => {
+                positive(*price)?;
+                if *duration == 0 {
+                    return Err(DefiError::ZeroDuration.into());
+                }
+            }
+            DefiOperation::WithdrawRental { triangle } => match self.rental.offer_of(&id(triangle)?) {
+                Some(offer) if offer.owner == account => {}
+                Some(_) => return Err(DefiError::NotParty("rental offer").into()),
+                None => return Err(DefiError::NotForRent(triangle.clone()).into()),
+            },
+            DefiOperation::Rent { triangle, price } => {
+                let record = record(triangle)?;
+                let triangle_id = record.triangle.hash();
+                let offer = self
+                    .rental
+                    .offer_of(&triangle_id)
+                    .filter(|offer| offer.owner == Address::from_pubkey(&record.owner))
+                    .ok_or_else(|| DefiError::NotForRent(triangle.clone()))?;
+                expect_payment(offer.price, *price)?;
+                if let Some(rental) = self.rental.current_rental(&triangle_id, height) {
+                    return Err(DefiError::Rented { triangle: triangle.clone(), until: rental.expiration_block }.into());
+                }
+                unless(triangle, |encumbrance| encumbrance != Encumbrance::Collateral)?;
+            }
+            DefiOperation::PlaceOrder { triangle, price, .. } => {
+                positive(*price)?;
+                record(triangle)?;
+                unless(triangle, |_| false)?;
+            }
+            DefiOperation::CancelOrders { triangle } => {
+                let has_orders = self
+                    .exchange
+                    .order_book(&id(triangle)?)
+                    .is_some_and(|book| book.buy_orders.iter().chain(&book.sell_orders).any(|order| order.trader == account));
+                if !has_orders {
+                    return Err(DefiError::NoOrders(triangle.clone()).into());
+                }
+            }
+            DefiOperation::AddLiquidity { triangle, amount } => {
+                positive(*amount)?;
+                record(triangle)?;
+            }
+            DefiOperation::RemoveLiquidity { triangle } => {
+                let provides = self.liquidity.pool(&id(triangle)?).is_some_and(|pool| pool.providers.contains_key(&account));
+                if !provides {
+                    return Err(DefiError::NoLiquidity(triangle.clone()).into());
+                }
+            }
+            DefiOperation::InitiateSwap { participant, amount, secret_hash, timeout } => {
+                positive(*amount)?;
+                if participant.is_module() {
+                    return Err(StateError::ModuleAccount(*participant));
+                }
+                if *timeout < height {
+                    return Err(DefiError::Expired(*timeout).into());
+                }
+                if self.swaps.swap(secret_hash).is_some() {
+                    return Err(DefiError::SwapExists(*secret_hash).into());
+                }
+            }
+            DefiOperation::RedeemSwap { secret } => {
+                let secret_hash = hash_secret(secret);
+                let swap = self.open_swap(&secret_hash)?;
+                if swap.participant != account {
+                    return Err(DefiError::NotParty("swap").into());
+                }
+                if height > swap.timeout {
+                    return Err(DefiError::Expired(swap.timeout).into());
+                }
+            }
+            DefiOperation::RefundSwap { secret_hash } => {
+                let swap = self.open_swap(secret_hash)?;
+                if swap.initiator != account {
+                    return Err(DefiError::NotParty("swap").into());
+                }
+                if height <= swap.timeout {
+                    return Err(DefiError::SwapRunning { secret_hash: *secret_hash, timeout: swap.timeout }.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the books for `operation`, which `check` accepted, and returns
+    /// what `WorldState` has to pay and hand over, in order. `record` is the
+    /// ledger's record of `DefiOperation::triangle`.
+    pub(crate) fn apply(
+        &mut self,
+        operation: &DefiOperation,
+        signer: &PublicKey,
+        height: u64,
+        record: Option<&TriangleRecord>,
+    ) -> Result<Vec<Effect>, StateError> {
+        let account = Address::from_pubkey(signer);
+        let known = |address: &TriangleAddress| {
+            record.map(|record| (record.triangle.clone(), record.triangle.hash())).ok_or_else(|| StateError::UnknownTriangle(address.clone()))
+        };
+        let mut effects = Vec::new();
+        match operation {
+            DefiOperation::Stake { triangle, amount } => {
+                let (triangle, _) = known(triangle)?;
+                self.staking.add_stake(Stake { staker: account, triangle, amount: *amount });
+                pay(&mut effects, account, StakingManager::account(), *amount);
+            }
+            DefiOperation::Unstake { triangle } => {
+                let (_, id) = known(triangle)?;
+                let staked = self.staking.remove_stakes(&id, &account);
+                pay(&mut effects, StakingManager::account(), account, staked);
+            }
+            DefiOperation::RewardStakers { triangle, amount } => {
+                let (_, id) = known(triangle)?;
+                for (staker, reward) in self.staking.distribute_rewards(&id, *amount) {
+                    pay(&mut effects, account, staker, reward);
+                }
+            }
+            DefiOperation::Insure { triangle, insured_value, premium, expiration_block } => {
+                let (triangle, _) = known(triangle)?;
+                self.insurance.create_policy(account, triangle, *insured_value, *premium, *expiration_block);
+                pay(&mut effects, account, InsuranceManager::account(), *premium);
+            }
+            DefiOperation::Claim { triangle } => {
+                let (_, id) = known(triangle)?;
+                let payout = self.insurance.process_claim(&id, &account, height);
+                pay(&mut effects, InsuranceManager::account(), account, payout);
+            }
+            DefiOperation::RequestLoan { collateral, amount, interest_rate, term } => {
+                let (triangle, _) = known(collateral)?;
+                self.lending.create_loan(account, triangle, *amount, *interest_rate, *term);
+            }
+            DefiOperation::FundLoan { collateral, amount } => {
+                let (_, id) = known(collateral)?;
+                self.lending.fund_loan(&id, account, height);
+                if let Some(loan) = self.lending.loan(&id) {
+                    pay(&mut effects, account, loan.borrower, *amount);
+                }
+            }
+            DefiOperation::RepayLoan { collateral, amount } => {
+                let (_, id) = known(collateral)?;
+                if let Some(lender) = self.lending.close_loan(&id).and_then(|loan| loan.lender) {
+                    pay(&mut effects, account, lender, *amount);
+                }
+            }
+            DefiOperation::Liquidate { collateral } => {
+                let (_, id) = known(collateral)?;
+                if let Some(loan) = self.lending.close_loan(&id) {
+                    effects.push(Effect::HandOver { triangle: collateral.clone(), from: loan.borrower, to: account });
+                }
+            }
+            DefiOperation::OfferRental { triangle, price, duration } => {
+                let (triangle, _) = known(triangle)?;
+                self.rental.offer(RentalOffer { owner: account, triangle, price: *price, duration: *duration });
+            }
+            DefiOperation::WithdrawRental { triangle } => {
+                let (_, id) = known(triangle)?;
+                self.rental.withdraw_offer(&id);
+            }
+            DefiOperation::Rent { triangle, price } => {
+                let (triangle, id) = known(triangle)?;
+                if let Some(offer) = self.rental.offer_of(&id).cloned() {
+                    self.rental.rent_triangle(account, triangle, *price, offer.duration, height);
+                    pay(&mut effects, account, offer.owner, *price);
+                }
+            }
+            DefiOperation::PlaceOrder { triangle: address, order_type: OrderType::Sell, price } => {
+                let (triangle, id) = known(address)?;
+                // An owner has a single ask per triangle.
+                self.exchange.cancel_orders(&id, &account, Some(OrderType::Sell));
+                match self.exchange.take_best_if(&id, OrderType::Buy, |bid| bid.price >= *price) {
+                    Some(bid) => {
+                        pay(&mut effects, Exchange::account(), account, bid.price);
+                        effects.push(Effect::HandOver { triangle: address.clone(), from: account, to: bid.trader });
+                    }
+                    None => self.exchange.place_order(Order { trader: account, order_type: OrderType::Sell, triangle, price: *price }),
+                }
+            }
+            DefiOperation::PlaceOrder { triangle: address, order_type: OrderType::Buy, price } => {
+                let (triangle, id) = known(address)?;
+                pay(&mut effects, account, Exchange::account(), *price);
+                // Asks left behind by a former owner are not filled.
+                let owner = record.map(|record| Address::from_pubkey(&record.owner));
+                match self.exchange.take_best_if(&id, OrderType::Sell, |ask| ask.price <= *price && Some(ask.trader) == owner) {
+                    Some(ask) => {
+                        pay(&mut effects, Exchange::account(), ask.trader, ask.price);
+                        pay(&mut effects, Exchange::account(), account, *price - ask.price);
+                        effects.push(Effect::HandOver { triangle: address.clone(), from: ask.trader, to: account });
+                    }
+                    None => self.exchange.place_order(Order { trader: account, order_type: OrderType::Buy, triangle, price: *price }),
+                }
+            }
+            DefiOperation::CancelOrders { triangle } => {
+                let (_, id) = known(triangle)?;
+                let refund: Decimal = self
+                    .exchange
+                    .cancel_orders(&id, &account, None)
+                    .iter()
+                    .filter(|order| order.order_type == OrderType::Buy)
+                    .map(|order| order.price)
+                    .sum();
+                pay(&mut effects, Exchange::account(), account, refund);
+            }
+            DefiOperation::AddLiquidity { triangle, amount } => {
+                let (triangle, _) = known(triangle)?;
+                self.liquidity.add_liquidity(&triangle, account, *amount);
+                pay(&mut effects, account, LiquidityPoolManager::account(), *amount);
+            }
+            DefiOperation::RemoveLiquidity { triangle } => {
+                let (_, id) = known(triangle)?;
+                let amount = self.liquidity.remove_liquidity(&id, &account);
+                pay(&mut effects, LiquidityPoolManager::account(), account, amount);
+            }
+            DefiOperation::InitiateSwap { participant, amount, secret_hash, timeout } => {
+                self.swaps.initiate_swap(account, *participant, *amount, *secret_hash, *timeout);
+                pay(&mut effects, account, SwapManager::account(), *amount);
+            }
+            DefiOperation::RedeemSwap { secret } => {
+                let secret_hash = hash_secret(secret);
+                let swap = self.open_swap(&secret_hash)?.clone();
+                self.swaps.confirm_swap(&secret_hash);
+                pay(&mut effects, SwapManager::account(), swap.participant, swap.amount);
+            }
+            DefiOperation::RefundSwap { secret_hash } => {
+                let swap = self.open_swap(secret_hash)?.clone();
+                self.swaps.refund_swap(secret_hash);
+                pay(&mut effects, SwapManager::account(), swap.initiator, swap.amount);
+            }
+        }
+        Ok(effects)
+    }
+
+    fn open_swap(&self, secret_hash: &H256) -> Result<&AtomicSwap, DefiError> {
+        self.swaps
+            .swap(secret_hash)
+            .filter(|swap| swap.state == SwapState::Initiated)
+            .ok_or(DefiError::UnknownSwap(*secret_hash))
+    }
+}
+
+fn positive(amount: Decimal) -> Result<(), BalanceError> {
+    if amount <= Decimal::ZERO {
+        return Err(BalanceError::InvalidAmount(amount));
+    }
+    Ok(())
+}
+
+fn expect_payment(expected: Decimal, found: Decimal) -> Result<(), DefiError> {
+    if found != expected {
+        return Err(DefiError::WrongPayment { expected, found });
+    }
+    Ok(())
+}
+
+/// Adds a payment, unless there is nothing to pay.
+fn pay(effects: &mut Vec<Effect>, from: Address, to: Address, amount: Decimal) {
+    if amount > Decimal::ZERO {
+        effects.push(Effect::Pay { from, to, amount });
+    }
+}
+
+const TAG_STAKE: u8 = 0;
+const TAG_UNSTAKE: u8 = 1;
+const TAG_REWARD_STAKERS: u8 = 2;
+const TAG_INSURE: u8 = 3;
+const TAG_CLAIM: u8 = 4;
+const TAG_REQUEST_LOAN: u8 = 5;
+const TAG_FUND_LOAN: u8 = 6;
+const TAG_REPAY_LOAN: u8 = 7;
+const TAG_LIQUIDATE: u8 = 8;
+const TAG_OFFER_RENTAL: u8 = 9;
+const TAG_WITHDRAW_RENTAL: u8 = 10;
+const TAG_RENT: u8 = 11;
+const TAG_PLACE_ORDER: u8 = 12;
+const TAG_CANCEL_ORDERS: u8 = 13;
+const TAG_ADD_LIQUIDITY: u8 = 14;
+const TAG_REMOVE_LIQUIDITY: u8 = 15;
+const TAG_INITIATE_SWAP: u8 = 16;
+const TAG_REDEEM_SWAP: u8 = 17;
+const TAG_REFUND_SWAP: u8 = 18;
+
+impl Encode for OrderType {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            OrderType::Buy => 0,
+            OrderType::Sell => 1,
+        });
+    }
+}
+
+impl Decode for OrderType {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match u8::decode_from(reader)? {
+            0 => Ok(OrderType::Buy),
+            1 => Ok(OrderType::Sell),
+            tag => Err(DecodeError::UnknownTag { kind: "order type", tag }),
+        }
+    }
+}
+
+impl Encode for DefiOperation {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            DefiOperation::Stake { triangle, amount } => {
+                out.push(TAG_STAKE);
+                triangle.encode_to(out);
+                amount.encode_to(out);
+            }
+            DefiOperation::Unstake { triangle } => {
+                out.push(TAG_UNSTAKE);
+                triangle.encode_to(out);
+            }
+            DefiOperation::RewardStakers { triangle, amount } => {
+                out.push(TAG_REWARD_STAKERS);
+                triangle.encode_to(out);
+                amount.encode_to(out);
+            }
+            DefiOperation::Insure { triangle, insured_value, premium, expiration_block } => {
+                out.push(TAG_INSURE);
+                triangle.encode_to(out);
+                insured_value.encode_to(out);
+                premium.encode_to(out);
+                expiration_block.encode_to(out);
+            }
+            DefiOperation::Claim { triangle } => {
+                out.push(TAG_CLAIM);
+                triangle.encode_to(out);
+            }
+            DefiOperation::RequestLoan { collateral, amount, interest_rate, term } => {
+                out.push(TAG_REQUEST_LOAN);
+                collateral.encode_to(out);
+                amount.encode_to(out);
+                interest_rate.encode_to(out);
+                term.encode_to(out);
+            }
+            DefiOperation::FundLoan { collateral, amount } => {
+                out.push(TAG_FUND_LOAN);
+                collateral.encode_to(out);
+                amount.encode_to(out);
+            }
+            DefiOperation::RepayLoan { collateral, amount } => {
+                out.push(TAG_REPAY_LOAN);
+                collateral.encode_to(out);
+                amount.encode_to(out);
+            }
+            DefiOperation::Liquidate { collateral } => {
+                out.push(TAG_LIQUIDATE);
+                collateral.encode_to(out);
+            }
+            DefiOperation::OfferRental { triangle, price, duration } => {
+                out.push(TAG_OFFER_RENTAL);
+                triangle.encode_to(out);
+                price.encode_to(out);
+                duration.encode_to(out);
+            }
+            DefiOperation::WithdrawRental { triangle } => {
+                out.push(TAG_WITHDRAW_RENTAL);
+                triangle.encode_to(out);
+            }
+            DefiOperation::Rent { triangle, price } => {
+                out.push(TAG_RENT);
+                triangle.encode_to(out);
+                price.encode_to(out);
+            }
+            DefiOperation::PlaceOrder { triangle, order_type, price } => {
+                out.push(TAG_PLACE_ORDER);
+                triangle.encode_to(out);
+                order_type.encode_to(out);
+                price.encode_to(out);
+            }
+            DefiOperation::CancelOrders { triangle } => {
+                out.push(TAG_CANCEL_ORDERS);
+                triangle.encode_to(out);
+            }
+            DefiOperation::AddLiquidity { triangle, amount } => {
+                out.push(TAG_ADD_LIQUIDITY);
+                triangle.encode_to(out);
+                amount.encode_to(out);
+            }
+            DefiOperation::RemoveLiquidity { triangle } => {
+                out.push(TAG_REMOVE_LIQUIDITY);
+                triangle.encode_to(out);
+            }
+            DefiOperation::InitiateSwap { participant, amount, secret_hash, timeout } => {
+                out.push(TAG_INITIATE_SWAP);
+                participant.encode_to(out);
+                amount.encode_to(out);
+                secret_hash.encode_to(out);
+                timeout.encode_to(out);
+            }
+            DefiOperation::RedeemSwap { secret } => {
+                out.push(TAG_REDEEM_SWAP);
+                secret.encode_to(out);
+            }
+            DefiOperation::RefundSwap { secret_hash } => {
+                out.push(TAG_REFUND_SWAP);
+                secret_hash.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for DefiOperation {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let triangle = |reader: &mut Reader<'_>| TriangleAddress::decode_from(reader);
+        let amount = |reader: &mut Reader<'_>| Decimal::decode_from(reader);
+        match u8::decode_from(reader)? {
+            TAG_STAKE => Ok(DefiOperation::Stake { triangle: triangle(reader)?, amount: amount(reader)? }),
+            TAG_UNSTAKE => Ok(DefiOperation::Unstake { triangle: triangle(reader)? }),
+            TAG_REWARD_STAKERS => Ok(DefiOperation::RewardStakers { triangle: triangle(reader)?, amount: amount(reader)? }),
+            TAG_INSURE => Ok(DefiOperation::Insure {
+                triangle: triangle(reader)?,
+                insured_value: amount(reader)?,
+                premium: amount(reader)?,
+                expiration_block: u64::decode_from(reader)?,
+            }),
+            TAG_CLAIM => Ok(DefiOperation::Claim { triangle: triangle(reader)? }),
+            TAG_REQUEST_LOAN => Ok(DefiOperation::RequestLoan {
+                collateral: triangle(reader)?,
+                amount: amount(reader)?,
+                interest_rate: amount(reader)?,
+                term: u64::decode_from(reader)?,
+            }),
+            TAG_FUND_LOAN => Ok(DefiOperation::FundLoan { collateral: triangle(reader)?, amount: amount(reader)? }),
+            TAG_REPAY_LOAN => Ok(DefiOperation::RepayLoan { collateral: triangle(reader)?, amount: amount(reader)? }),
+            TAG_LIQUIDATE => Ok(DefiOperation::Liquidate { collateral: triangle(reader)? }),
+            TAG_OFFER_RENTAL => Ok(DefiOperation::OfferRental {
+                triangle: triangle(reader)?,
+                price: amount(reader)?,
+                duration: u64::decode_from(reader)?,
+            }),
+            TAG_WITHDRAW_RENTAL => Ok(DefiOperation::WithdrawRental { triangle: triangle(reader)? }),
+            TAG_RENT => Ok(DefiOperation::Rent { triangle: triangle(reader)?, price: amount(reader)? }),
+            TAG_PLACE_ORDER => Ok(DefiOperation::PlaceOrder {
+                triangle: triangle(reader)?,
+                order_type: OrderType::decode_from(reader)?,
+                price: amount(reader)?,
+            }),
+            TAG_CANCEL_ORDERS => Ok(DefiOperation::CancelOrders { triangle: triangle(reader)? }),
+            TAG_ADD_LIQUIDITY => Ok(DefiOperation::AddLiquidity { triangle: triangle(reader)?, amount: amount(reader)? }),
+            TAG_REMOVE_LIQUIDITY => Ok(DefiOperation::RemoveLiquidity { triangle: triangle(reader)? }),
+            TAG_INITIATE_SWAP => Ok(DefiOperation::InitiateSwap {
+                participant: Address::decode_from(reader)?,
+                amount: amount(reader)?,
+                secret_hash: H256::decode_from(reader)?,
+                timeout: u64::decode_from(reader)?,
+            }),
+            TAG_REDEEM_SWAP => Ok(DefiOperation::RedeemSwap { secret: H256::decode_from(reader)? }),
+            TAG_REFUND_SWAP => Ok(DefiOperation::RefundSwap { secret_hash: H256::decode_from(reader)? }),
+            tag => Err(DecodeError::UnknownTag { kind: "DeFi operation", tag }),
+        }
+    }
+}
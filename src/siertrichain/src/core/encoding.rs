@@ -0,0 +1,314 @@
+//! Canonical binary encoding of chain data.
+//!
+//! Block and transaction hashes, signatures and the block store all use this
+//! encoding instead of whatever a serialization library makes of the Rust
+//! structs, so the bytes only change when the format below is changed on
+//! purpose, together with a version number.
+//!
+//! Format, version 2:
+//!
+//! * Integers are fixed width and little-endian.
+//! * `H256`, public keys (32 bytes), account addresses (33 bytes) and
+//!   signatures (64 bytes) are raw bytes.
+//! * `Decimal` is its 16-byte `Decimal::serialize` form.
+//! * A point is `x` then `y`; a triangle is its vertices `a`, `b`, `c`.
+//! * A `TriangleAddress` is its packed form: the depth as one byte, then two
+//!   bits per level, first level in the high bits.
+//! * `Option<T>` is a `u8` tag, 0 for `None` and 1 for `Some`, then `T`.
+//! * A list is its length as `u32`, then its elements.
+//! * A `TriangleOperation` is a `u8` tag, then the fields of the variant:
+//!   0 `Create(triangle)`, 1 `Subdivide(parent, 3 children)`,
+//!   2 `Transfer(from, to)`, 3 `Coinbase(recipient, amount, height)`,
+//!   4 `Merge(parent)`, 5 `TokenTransfer(to, amount)`, 6 `Defi(operation)`.
+//! * A `DefiOperation` is a `u8` tag, then the fields of the variant in
+//!   declaration order, see the tags in `core::defi`. An `OrderType` is a
+//!   `u8`, 0 for `Buy` and 1 for `Sell`.
+//! * A transaction is `version: u32`, `chain_id: u32`, `nonce: u64`,
+//!   `expiry: Option<u64>`, `fee`, the operation, the public key and the
+//!   signature.
+//! * A block header is `version: u32`, `previous_hash`, `merkle_root`,
+//!   `timestamp: i64`, `nonce: u64`, `bits: u32`, `base_fee`,
+//!   `transaction_count: u32`, `height: u64` and the geometric proof
+//!   address; a block is its header and its transaction list.
+//!
+//! Decoding is strict: unknown versions and tags, invalid keys or decimals,
+//! and trailing bytes are all errors.
+
+use crate::core::address::TriangleAddress;
+use crate::core::errors::DecodeError;
+use crate::core::geometry::Point;
+use crate::core::hash::H256;
+use crate::core::triangle::Triangle;
+use crate::wallet::address::Address;
+use ed25519_dalek::{PublicKey, Signature};
+use rust_decimal::Decimal;
+
+pub trait Encode {
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError>;
+
+    /// Decodes a value that must span all of `bytes`.
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let value = Self::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(value)
+    }
+}
+
+/// Cursor over the bytes being decoded.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Fails unless every byte has been consumed.
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            n => Err(DecodeError::TrailingBytes(n)),
+        }
+    }
+}
+
+macro_rules! impl_int {
+    ($($int:ty),*) => {$(
+        impl Encode for $int {
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $int {
+            fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+                Ok(<$int>::from_le_bytes(reader.array()?))
+            }
+        }
+    )*};
+}
+
+impl_int!(u8, u32, u64, i64);
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match u8::decode_from(reader)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_from(reader)?)),
+            tag => Err(DecodeError::UnknownTag { kind: "option", tag }),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let len = u32::try_from(self.len()).expect("lists are shorter than 2^32");
+        len.encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let len = u32::decode_from(reader)? as usize;
+        // Every element takes at least one byte, which bounds the allocation
+        // by the input size.
+        let mut items = Vec::with_capacity(len.min(reader.remaining()));
+        for _ in 0..len {
+            items.push(T::decode_from(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl Encode for H256 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_bytes());
+    }
+}
+
+impl Decode for H256 {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(H256::from(reader.array::<32>()?))
+    }
+}
+
+impl Encode for Decimal {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.serialize());
+    }
+}
+
+impl Decode for Decimal {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let bytes = reader.array::<16>()?;
+        // Only the scale (bits 16-23, at most 28) and the sign (bit 31) may
+        // be set in the flags word.
+        let flags = u32::from_le_bytes(bytes[..4].try_into().expect("4 bytes"));
+        if flags & 0x7f00_ffff != 0 || (flags >> 16) & 0xff > 28 {
+            return Err(DecodeError::Invalid("decimal"));
+        }
+        Ok(Decimal::deserialize(bytes))
+    }
+}
+
+impl Encode for Point {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.x.encode_to(out);
+        self.y.encode_to(out);
+    }
+}
+
+impl Decode for Point {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Point::new(Decimal::decode_from(reader)?, Decimal::decode_from(reader)?))
+    }
+}
+
+impl Encode for Triangle {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.a.encode_to(out);
+        self.b.encode_to(out);
+        self.c.encode_to(out);
+    }
+}
+
+impl Decode for Triangle {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Triangle::new(Point::decode_from(reader)?, Point::decode_from(reader)?, Point::decode_from(reader)?))
+    }
+}
+
+impl Encode for TriangleAddress {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_packed());
+    }
+}
+
+impl Decode for TriangleAddress {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let depth = reader.array::<1>()?[0];
+        let body = reader.take((depth as usize).div_ceil(4))?;
+        let mut packed = vec![depth];
+        packed.extend_from_slice(body);
+        TriangleAddress::from_packed(&packed).map_err(|_| DecodeError::Invalid("triangle address"))
+    }
+}
+
+impl Encode for PublicKey {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for PublicKey {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        PublicKey::from_bytes(reader.take(32)?).map_err(|_| DecodeError::Invalid("public key"))
+    }
+}
+
+impl Encode for Address {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for Address {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(Address::from_bytes(reader.array()?))
+    }
+}
+
+impl Encode for Signature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_bytes());
+    }
+}
+
+impl Decode for Signature {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Signature::from_bytes(reader.take(64)?).map_err(|_| DecodeError::Invalid("signature"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_primitives_are_little_endian_and_tagged() {
+        assert_eq!(0x0102_0304u32.encode(), vec![4, 3, 2, 1]);
+        assert_eq!(Some(1u64).encode(), vec![1, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(None::<u64>.encode(), vec![0]);
+        assert_eq!(vec![7u8, 8].encode(), vec![2, 0, 0, 0, 7, 8]);
+        assert_eq!(TriangleAddress::root().append(1).append(2).encode(), vec![2, 0b0110_0000]);
+    }
+
+    #[test]
+    fn test_decoding_is_strict() {
+        assert_eq!(u32::decode(&[1, 2, 3]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(u32::decode(&[1, 2, 3, 4, 5]), Err(DecodeError::TrailingBytes(1)));
+        assert_eq!(Option::<u8>::decode(&[2, 0]), Err(DecodeError::UnknownTag { kind: "option", tag: 2 }));
+        assert_eq!(Vec::<u64>::decode(&[0xff, 0xff, 0xff, 0xff]), Err(DecodeError::UnexpectedEnd));
+
+        let mut bad_scale = dec!(1.5).encode();
+        bad_scale[2] = 29;
+        assert_eq!(Decimal::decode(&bad_scale), Err(DecodeError::Invalid("decimal")));
+    }
+
+    #[test]
+    fn test_geometry_round_trip() {
+        let triangle = Triangle::new(
+            Point::new(dec!(0), dec!(-1.25)),
+            Point::new(dec!(1), dec!(0)),
+            Point::new(dec!(0.5), dec!(0.8660254037844386467637231708)),
+        );
+        assert_eq!(Triangle::decode(&triangle.encode()).unwrap(), triangle);
+        let address = TriangleAddress::root().append(2).append(0).append(1).append(1).append(2);
+        assert_eq!(TriangleAddress::decode(&address.encode()).unwrap(), address);
+    }
+}
@@ -1,3 +1,8 @@
+use crate::core::address::TriangleAddress;
+use crate::core::hash::H256;
+use crate::core::state::Encumbrance;
+use crate::wallet::address::Address;
+use rust_decimal::Decimal;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,6 +11,8 @@ pub enum TriangleError {
     InvalidAddressFormat,
     #[error("Invalid triangle: {0}")]
     InvalidTriangle(String),
+    #[error("Depth {0} is beyond the deepest exact subdivision")]
+    MaxDepthExceeded(u32),
 }
 
 #[derive(Error, Debug)]
@@ -14,4 +21,220 @@ pub enum BlockchainError {
     TriangleOccupied,
     #[error("NFT not found")]
     NFTNotFound,
+    #[error("Block is already known")]
+    DuplicateBlock,
+    #[error("Parent block not found")]
+    UnknownParent,
+    #[error("Invalid block: {0}")]
+    Invalid(#[from] ValidationError),
+    #[error("Stored chain was created with a different genesis block")]
+    GenesisMismatch,
+    #[error("Block {0:?} is indexed but missing from storage")]
+    MissingBlock(H256),
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// The consensus rule a block broke, as reported by `validate_block` and the
+/// triangle ledger.
+#[derive(Error, Debug, PartialEq)]
+pub enum ValidationError {
+    #[error("Previous block {0:?} is unknown")]
+    UnknownParent(H256),
+    #[error("Block height is {found}, expected {expected}")]
+    BadHeight { expected: u64, found: u64 },
+    #[error("Timestamp {timestamp} is not after the median time past {median_time_past}")]
+    TimestampTooEarly { timestamp: i64, median_time_past: i64 },
+    #[error("Timestamp {timestamp} is more than the allowed drift ahead of the local time {now}")]
+    TimestampTooFarInFuture { timestamp: i64, now: i64 },
+    #[error("Target bits are {found:#010x}, expected {expected:#010x}")]
+    WrongBits { expected: u32, found: u32 },
+    #[error("Block hash does not meet its target")]
+    InsufficientProofOfWork,
+    #[error("Geometric proof {0} is a void")]
+    VoidProof(TriangleAddress),
+    #[error("Geometric proof {0} does not lie below an active triangle")]
+    UnclaimableProof(TriangleAddress),
+    #[error("Geometric proof {0} was already mined")]
+    ProofAlreadyClaimed(TriangleAddress),
+    #[error("Base fee is {found}, expected {expected}")]
+    WrongBaseFee { expected: Decimal, found: Decimal },
+    #[error("Block weighs {weight}, more than the maximum of {max}")]
+    BlockTooHeavy { weight: u64, max: u64 },
+    #[error("Header counts {found} transactions, the block has {expected}")]
+    BadTransactionCount { expected: u32, found: u32 },
+    #[error("Transaction {index} pays {fee}, below the base fee {base_fee}")]
+    FeeBelowBaseFee { index: usize, fee: Decimal, base_fee: Decimal },
+    #[error("Merkle root is {found:?}, expected {expected:?}")]
+    MerkleRootMismatch { expected: H256, found: H256 },
+    #[error("Transaction {0} repeats an earlier transaction of the block")]
+    DuplicateTransaction(usize),
+    #[error("First transaction is not a coinbase")]
+    MissingCoinbase,
+    #[error("Coinbase must pay {expected_amount} at height {expected_height}")]
+    BadCoinbase { expected_amount: Decimal, expected_height: u64 },
+    #[error("Transaction {0} is a coinbase but is not the first transaction")]
+    UnexpectedCoinbase(usize),
+    #[error("Transaction {0} has an invalid signature")]
+    InvalidSignature(usize),
+    #[error("Transaction {index} is for chain {chain_id:#x}")]
+    WrongChain { index: usize, chain_id: u32 },
+    #[error("Transaction {index} expired at height {expiry}")]
+    Expired { index: usize, expiry: u64 },
+    #[error("Transaction {index} violates the triangle ledger: {error}")]
+    Transaction {
+        index: usize,
+        #[source]
+        error: StateError,
+    },
+    #[error("Triangle is not a child of its parent")]
+    GeometryMismatch,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum StateError {
+    #[error("Triangle {0} does not exist")]
+    UnknownTriangle(TriangleAddress),
+    #[error("Triangle {0} is not owned by the signer")]
+    NotOwner(TriangleAddress),
+    #[error("Triangle {0} is not active")]
+    NotActive(TriangleAddress),
+    #[error("Triangle {0} is spent more than once in the block")]
+    DoubleSpend(TriangleAddress),
+    #[error("Triangle {0} already exists")]
+    AlreadyExists(TriangleAddress),
+    #[error("Child {child} of triangle {parent} does not match its exact subdivision")]
+    SubdivisionMismatch { parent: TriangleAddress, child: usize },
+    #[error("Triangle {0} is a void and cannot be transferred or subdivided")]
+    Void(TriangleAddress),
+    #[error("Triangle {0} is too deep to be subdivided")]
+    MaxDepthExceeded(TriangleAddress),
+    #[error("Triangle {0} is not subdivided")]
+    NotSubdivided(TriangleAddress),
+    #[error("Triangle {triangle} is {encumbrance} and cannot change hands")]
+    Encumbered { triangle: TriangleAddress, encumbrance: Encumbrance },
+    #[error("Transaction nonce is {found}, expected {expected}")]
+    BadNonce { expected: u64, found: u64 },
+    #[error("{0}")]
+    Balance(#[from] BalanceError),
+    #[error("Account {0} belongs to a module and is only paid by its operations")]
+    ModuleAccount(Address),
+    #[error("{0}")]
+    Defi(#[from] DefiError),
+}
+
+/// Why a DeFi operation was refused, see `core::defi`.
+#[derive(Error, Debug, PartialEq)]
+pub enum DefiError {
+    #[error("The signer has nothing staked on triangle {0}")]
+    NothingStaked(TriangleAddress),
+    #[error("Nobody stakes on triangle {0}")]
+    NoStakers(TriangleAddress),
+    #[error("Block {0} has already passed")]
+    Expired(u64),
+    #[error("Insurance account holds {available}, which does not cover {needed} of running policies")]
+    InsufficientCover { needed: Decimal, available: Decimal },
+    #[error("No running policy of the signer covers triangle {0}")]
+    NoPolicy(TriangleAddress),
+    #[error("Interest rate {0} is negative")]
+    NegativeRate(Decimal),
+    #[error("Triangle {0} already backs a loan")]
+    LoanExists(TriangleAddress),
+    #[error("No loan is requested against triangle {0}")]
+    NoLoan(TriangleAddress),
+    #[error("Loan against triangle {0} is already funded")]
+    AlreadyFunded(TriangleAddress),
+    #[error("Loan against triangle {triangle} can be repaid until block {due}")]
+    NotDue { triangle: TriangleAddress, due: u64 },
+    #[error("Payment is {found}, expected {expected}")]
+    WrongPayment { expected: Decimal, found: Decimal },
+    #[error("The signer is not a party to the {0}")]
+    NotParty(&'static str),
+    #[error("A rental must last at least one block")]
+    ZeroDuration,
+    #[error("Triangle {0} is not offered for rent")]
+    NotForRent(TriangleAddress),
+    #[error("Triangle {triangle} is rented until block {until}")]
+    Rented { triangle: TriangleAddress, until: u64 },
+    #[error("The signer has no orders on triangle {0}")]
+    NoOrders(TriangleAddress),
+    #[error("The signer provides no liquidity to triangle {0}")]
+    NoLiquidity(TriangleAddress),
+    #[error("Swap {0:?} already exists")]
+    SwapExists(H256),
+    #[error("No open swap is locked with hash {0:?}")]
+    UnknownSwap(H256),
+    #[error("Swap {secret_hash:?} can only be refunded after block {timeout}")]
+    SwapRunning { secret_hash: H256, timeout: u64 },
+}
+
+/// Why the token ledger refused to move funds, see `core::balances`.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum BalanceError {
+    #[error("Amount {0} is not positive")]
+    InvalidAmount(Decimal),
+    #[error("Account {address} holds {balance}, which does not cover {amount}")]
+    InsufficientBalance { address: Address, balance: Decimal, amount: Decimal },
+    #[error("Amount does not fit in a balance")]
+    Overflow,
+}
+
+/// Why the mempool turned a transaction away.
+#[derive(Error, Debug, PartialEq)]
+pub enum MempoolError {
+    #[error("Transaction {0:?} is already pending")]
+    Duplicate(H256),
+    #[error("Coinbase transactions are only valid inside a block")]
+    Coinbase,
+    #[error("Transaction is for chain {0:#x}")]
+    WrongChain(u32),
+    #[error("Transaction expired at height {0}")]
+    Expired(u64),
+    #[error("Transaction has an invalid signature")]
+    InvalidSignature,
+    #[error("Transaction pays {fee}, below the next base fee {base_fee}")]
+    FeeBelowBaseFee { fee: Decimal, base_fee: Decimal },
+    #[error("Transaction violates the triangle ledger: {0}")]
+    State(#[from] StateError),
+    #[error("Triangle {address} is already spent by pending transaction {pending:?}")]
+    Conflict { address: TriangleAddress, pending: H256 },
+    #[error("Nonce {nonce} is already used by pending transaction {pending:?}")]
+    NonceInUse { nonce: u64, pending: H256 },
+    #[error("Nonce {found} leaves a gap after the signer's pending transactions, expected {expected}")]
+    NonceGap { expected: u64, found: u64 },
+    #[error("Transaction of {0} bytes is larger than the whole mempool")]
+    TooLarge(usize),
+    #[error("Mempool is full and the fee rate is not above the lowest pending one")]
+    FeeTooLow,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read config file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse config file: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+/// Why bytes could not be read as chain data, see `core::encoding`.
+#[derive(Error, Debug, PartialEq)]
+pub enum DecodeError {
+    #[error("Unexpected end of input")]
+    UnexpectedEnd,
+    #[error("{0} trailing bytes after the value")]
+    TrailingBytes(usize),
+    #[error("Unknown {kind} tag {tag}")]
+    UnknownTag { kind: &'static str, tag: u8 },
+    #[error("Unsupported {kind} version {version}")]
+    UnsupportedVersion { kind: &'static str, version: u32 },
+    #[error("Invalid {0}")]
+    Invalid(&'static str),
+}
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Decoding error: {0}")]
+    Decode(#[from] DecodeError),
 }
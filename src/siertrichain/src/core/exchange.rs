@@ -1,16 +1,19 @@
-use crate::core::triangle::Triangle;
+use crate::core::triangle::{resolve_triangle_id, Triangle};
 use crate::wallet::address::Address;
 use crate::core::hash::H256;
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Represents a buy or sell order for a triangular region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderType {
     Buy,
     Sell,
 }
 
 /// Represents an order in the exchange.
+#[derive(Debug, Clone)]
 pub struct Order {
     /// The address of the trader.
     pub trader: Address,
@@ -23,6 +26,7 @@ pub struct Order {
 }
 
 /// A simple order book for a single triangular region.
+#[derive(Debug, Clone, Default)]
 pub struct OrderBook {
     /// A list of buy orders.
     pub buy_orders: Vec<Order>,
@@ -39,7 +43,10 @@ impl OrderBook {
     }
 }
 
-/// Manages the decentralized exchange for triangular regions.
+/// Manages the decentralized exchange for triangular regions. Buy orders
+/// hold their price in `Exchange::account()` until they are filled or
+/// cancelled.
+#[derive(Debug, Clone, Default)]
 pub struct Exchange {
     /// A map from a triangle identifier to its order book.
     order_books: HashMap<H256, OrderBook>,
@@ -52,6 +59,11 @@ impl Exchange {
         }
     }
 
+    /// The escrow account of open buy orders.
+    pub fn account() -> Address {
+        Address::for_module("exchange")
+    }
+
     /// Places a new order in the exchange.
     pub fn place_order(&mut self, order: Order) {
         let triangle_id = order.triangle.hash();
@@ -59,15 +71,68 @@ impl Exchange {
 
         match order.order_type {
             OrderType::Buy => {
-                // In a real implementation, we would match against sell orders.
                 order_book.buy_orders.push(order);
                 order_book.buy_orders.sort_by(|a, b| b.price.cmp(&a.price)); // Highest price first
             }
             OrderType::Sell => {
-                // In a real implementation, we would match against buy orders.
                 order_book.sell_orders.push(order);
                 order_book.sell_orders.sort_by(|a, b| a.price.cmp(&b.price)); // Lowest price first
             }
         }
     }
+
+    /// The order book of a triangle, by current or legacy id.
+    pub fn order_book(&self, triangle_id: &H256) -> Option<&OrderBook> {
+        self.order_books.get(&self.resolve(triangle_id))
+    }
+
+    /// Removes and returns the best order of one side of a triangle's book,
+    /// the highest bid or the lowest ask, if `accept` takes it.
+    pub fn take_best_if(&mut self, triangle_id: &H256, order_type: OrderType, accept: impl FnOnce(&Order) -> bool) -> Option<Order> {
+        let key = self.resolve(triangle_id);
+        let order_book = self.order_books.get_mut(&key)?;
+        let orders = match order_type {
+            OrderType::Buy => &mut order_book.buy_orders,
+            OrderType::Sell => &mut order_book.sell_orders,
+        };
+        let best = orders.first().is_some_and(accept).then(|| orders.remove(0));
+        if order_book.buy_orders.is_empty() && order_book.sell_orders.is_empty() {
+            self.order_books.remove(&key);
+        }
+        best
+    }
+
+    /// Removes and returns the orders of `trader` on a triangle, of one type
+    /// or, with `None`, of both.
+    pub fn cancel_orders(&mut self, triangle_id: &H256, trader: &Address, order_type: Option<OrderType>) -> Vec<Order> {
+        let key = self.resolve(triangle_id);
+        let Some(order_book) = self.order_books.get_mut(&key) else {
+            return Vec::new();
+        };
+        let mut cancelled = Vec::new();
+        for orders in [&mut order_book.buy_orders, &mut order_book.sell_orders] {
+            let (mine, others) = orders
+                .drain(..)
+                .partition(|order| &order.trader == trader && order_type.is_none_or(|kind| order.order_type == kind));
+            *orders = others;
+            cancelled.extend::<Vec<Order>>(mine);
+        }
+        if order_book.buy_orders.is_empty() && order_book.sell_orders.is_empty() {
+            self.order_books.remove(&key);
+        }
+        cancelled
+    }
+
+    /// The key of the triangle `triangle_id` names, by current or legacy id.
+    fn resolve(&self, triangle_id: &H256) -> H256 {
+        if self.order_books.contains_key(triangle_id) {
+            return *triangle_id;
+        }
+        let stored = self
+            .order_books
+            .values()
+            .flat_map(|book| book.buy_orders.iter().chain(&book.sell_orders))
+            .map(|order| &order.triangle);
+        resolve_triangle_id(triangle_id, stored).unwrap_or(*triangle_id)
+    }
 }
@@ -1,8 +1,7 @@
 use crate::core::triangle::Triangle;
-use crate::core::address::TriangleAddress;
+use crate::core::address::{TriangleAddress, VOID_INDEX};
 use serde::{Deserialize, Serialize};
-use rand::Rng;
-use crate::core::subdivision::subdivide_triangle;
+use crate::core::barycentric::{cartesian_children, cartesian_void};
 use crate::core::errors::TriangleError;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -25,25 +24,36 @@ pub struct FractalTriangle {
 }
 
 impl FractalTriangle {
-    pub fn new(
-        triangle: Triangle,
-        state: TriangleState,
-        depth: u32,
-        parent_id: Option<u64>,
-        address: TriangleAddress,
-    ) -> Self {
-        let mut rng = rand::thread_rng();
+    /// Builds the node at `address`. Its id, depth and parent are all
+    /// derived from the address, so every node computes the same tree.
+    pub fn new(triangle: Triangle, state: TriangleState, address: TriangleAddress) -> Self {
+        let child_ids = if state == TriangleState::Subdivided {
+            (0..=VOID_INDEX).map(|i| Self::id_for(&address.append(i))).collect()
+        } else {
+            Vec::new()
+        };
         Self {
-            id: rng.gen(),
+            id: Self::id_for(&address),
             triangle,
             state,
-            depth,
-            parent_id,
-            child_ids: Vec::new(),
+            depth: address.depth(),
+            parent_id: address.parent().map(|parent| Self::id_for(&parent)),
+            child_ids,
             address,
         }
     }
 
+    /// The id of the triangle at `address`: the first eight bytes of a
+    /// domain-separated hash of the address path.
+    pub fn id_for(address: &TriangleAddress) -> u64 {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"siertrichain fractal id");
+        hasher.update(address.to_string().as_bytes());
+        u64::from_le_bytes(hasher.finalize().as_bytes()[..8].try_into().unwrap())
+    }
+
+    /// Splits an active triangle into its three corner children and the
+    /// central void, returned last.
     pub fn subdivide(&mut self) -> Result<Vec<FractalTriangle>, TriangleError> {
         if self.state != TriangleState::Active {
             return Err(TriangleError::InvalidTriangle(
@@ -51,39 +61,44 @@ impl FractalTriangle {
             ));
         }
 
-        let (child_triangle1, child_triangle2, child_triangle3) = subdivide_triangle(&self.triangle)?;
-
-        let child1_address = self.address.append(0);
-        let child2_address = self.address.append(1);
-        let child3_address = self.address.append(2);
+        let [child_triangle1, child_triangle2, child_triangle3] = cartesian_children(&self.address)?;
 
-        let child1 = FractalTriangle::new(
-            child_triangle1,
-            TriangleState::Active,
-            self.depth + 1,
-            Some(self.id),
-            child1_address,
-        );
-        let child2 = FractalTriangle::new(
-            child_triangle2,
-            TriangleState::Active,
-            self.depth + 1,
-            Some(self.id),
-            child2_address,
-        );
-        let child3 = FractalTriangle::new(
-            child_triangle3,
-            TriangleState::Active,
-            self.depth + 1,
-            Some(self.id),
-            child3_address,
-        );
+        let children = vec![
+            FractalTriangle::new(child_triangle1, TriangleState::Active, self.address.append(0)),
+            FractalTriangle::new(child_triangle2, TriangleState::Active, self.address.append(1)),
+            FractalTriangle::new(child_triangle3, TriangleState::Active, self.address.append(2)),
+            FractalTriangle::new(cartesian_void(&self.address)?, TriangleState::Void, self.address.void()?),
+        ];
 
         self.state = TriangleState::Subdivided;
-        self.child_ids.push(child1.id);
-        self.child_ids.push(child2.id);
-        self.child_ids.push(child3.id);
+        self.child_ids = children.iter().map(|child| child.id).collect();
+
+        Ok(children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::genesis::GenesisTriangle;
+
+    #[test]
+    fn test_ids_are_derived_from_address() {
+        let mut root = FractalTriangle::new(GenesisTriangle::new(), TriangleState::Active, TriangleAddress::root());
+        let again = FractalTriangle::new(GenesisTriangle::new(), TriangleState::Active, TriangleAddress::root());
+        assert_eq!(root.id, again.id);
+
+        let children = root.subdivide().unwrap();
+        for (i, child) in children.iter().enumerate() {
+            assert_eq!(child.id, FractalTriangle::id_for(&TriangleAddress::root().append(i as u8)));
+            assert_eq!(child.parent_id, Some(root.id));
+            assert_eq!(child.depth, 1);
+        }
+        assert_eq!(root.child_ids, children.iter().map(|child| child.id).collect::<Vec<_>>());
+        assert_eq!(children[3].state, TriangleState::Void);
+        assert!(children[3].address.is_void());
 
-        Ok(vec![child1, child2, child3])
+        let rebuilt = FractalTriangle::new(GenesisTriangle::new(), TriangleState::Subdivided, TriangleAddress::root());
+        assert_eq!(rebuilt.child_ids, root.child_ids);
     }
 }
@@ -0,0 +1,174 @@
+use crate::core::address::TriangleAddress;
+use crate::core::state::TriangleRecord;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Default)]
+struct Node {
+    record: Option<TriangleRecord>,
+    /// Corner children by index, then the void.
+    children: [Option<Box<Node>>; 4],
+}
+
+impl Node {
+    fn is_empty(&self) -> bool {
+        self.record.is_none() && self.children.iter().all(Option::is_none)
+    }
+}
+
+/// Trie of ledger records keyed by `TriangleAddress`. Lookups walk one node
+/// per level, and the spendable leaves are kept in a separate sorted set so
+/// they can be enumerated without visiting the rest of the tree.
+#[derive(Debug, Default)]
+pub struct FractalTree {
+    root: Node,
+    len: usize,
+    active: BTreeSet<TriangleAddress>,
+}
+
+impl FractalTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, address: &TriangleAddress) -> Option<&TriangleRecord> {
+        let mut node = &self.root;
+        for index in address.indices() {
+            node = node.children[index as usize].as_deref()?;
+        }
+        node.record.as_ref()
+    }
+
+    pub fn contains(&self, address: &TriangleAddress) -> bool {
+        self.get(address).is_some()
+    }
+
+    /// Stores `record` at `address` and returns the record it replaced.
+    pub fn insert(&mut self, address: TriangleAddress, record: TriangleRecord) -> Option<TriangleRecord> {
+        let mut node = &mut self.root;
+        for index in address.indices() {
+            node = node.children[index as usize].get_or_insert_with(Box::default);
+        }
+        if record.is_spendable() {
+            self.active.insert(address.clone());
+        } else {
+            self.active.remove(&address);
+        }
+        let previous = node.record.replace(record);
+        if previous.is_none() {
+            self.len += 1;
+        }
+        previous
+    }
+
+    /// Removes the record at `address`. Records below it are kept.
+    pub fn remove(&mut self, address: &TriangleAddress) -> Option<TriangleRecord> {
+        let indices: Vec<u8> = address.indices().collect();
+        let previous = Self::remove_at(&mut self.root, &indices)?;
+        self.active.remove(address);
+        self.len -= 1;
+        Some(previous)
+    }
+
+    /// Removes the record at the end of `path` below `node`, pruning nodes
+    /// that are left without a record or children.
+    fn remove_at(node: &mut Node, path: &[u8]) -> Option<TriangleRecord> {
+        let Some((&index, rest)) = path.split_first() else {
+            return node.record.take();
+        };
+        let child = node.children[index as usize].as_deref_mut()?;
+        let removed = Self::remove_at(child, rest);
+        if child.is_empty() {
+            node.children[index as usize] = None;
+        }
+        removed
+    }
+
+    /// Addresses of the triangles that can still be transferred or
+    /// subdivided, in depth-first order.
+    pub fn active_leaves(&self) -> impl Iterator<Item = &TriangleAddress> {
+        self.active.iter()
+    }
+
+    /// Every record in depth-first order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { stack: vec![(TriangleAddress::root(), &self.root)] }
+    }
+}
+
+/// Depth-first iterator returned by `FractalTree::iter`.
+pub struct Iter<'a> {
+    stack: Vec<(TriangleAddress, &'a Node)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (TriangleAddress, &'a TriangleRecord);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((address, node)) = self.stack.pop() {
+            for (index, child) in node.children.iter().enumerate().rev() {
+                if let Some(child) = child {
+                    self.stack.push((address.append(index as u8), child));
+                }
+            }
+            if let Some(record) = &node.record {
+                return Some((address, record));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::fractal::TriangleState;
+    use crate::core::genesis::{genesis_keypair, GenesisTriangle};
+
+    fn record(state: TriangleState) -> TriangleRecord {
+        TriangleRecord { triangle: GenesisTriangle::new(), owner: genesis_keypair().public, state }
+    }
+
+    #[test]
+    fn test_insert_get_and_remove() {
+        let mut tree = FractalTree::new();
+        let root = TriangleAddress::root();
+        let child = root.append(2).append(1);
+
+        tree.insert(root.clone(), record(TriangleState::Subdivided));
+        assert!(tree.insert(child.clone(), record(TriangleState::Active)).is_none());
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.get(&child).unwrap().state, TriangleState::Active);
+        assert!(tree.get(&root.append(2)).is_none());
+
+        assert_eq!(tree.remove(&child).unwrap().state, TriangleState::Active);
+        assert!(tree.remove(&child).is_none());
+        assert_eq!(tree.len(), 1);
+        assert!(tree.root.children.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_active_leaves_follow_state() {
+        let mut tree = FractalTree::new();
+        let root = TriangleAddress::root();
+        tree.insert(root.clone(), record(TriangleState::Genesis));
+        assert_eq!(tree.active_leaves().collect::<Vec<_>>(), vec![&root]);
+
+        for i in (0..3).rev() {
+            tree.insert(root.append(i), record(TriangleState::Active));
+        }
+        tree.insert(root.clone(), record(TriangleState::Subdivided));
+        let active: Vec<String> = tree.active_leaves().map(|a| a.to_compact_string()).collect();
+        assert_eq!(active, vec!["0", "1", "2"]);
+
+        let iterated: Vec<String> = tree.iter().map(|(a, _)| a.to_compact_string()).collect();
+        assert_eq!(iterated, vec!["", "0", "1", "2"]);
+    }
+}
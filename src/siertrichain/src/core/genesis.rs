@@ -1,9 +1,45 @@
 
+use crate::core::barycentric::SQRT3_2;
 use crate::core::geometry::Point;
 use crate::core::triangle::Triangle;
 
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
 use rust_decimal_macros::dec;
-use rust_decimal::MathematicalOps;
+
+/// Seed of the well-known key that signs the genesis transaction. It is public
+/// on purpose: every node must derive a byte-identical genesis block.
+const GENESIS_KEY_SEED: &[u8] = b"siertrichain genesis key";
+
+/// Owner of the genesis triangle on the public chain. Its secret key is not
+/// part of the source.
+const GENESIS_OWNER: [u8; 32] = [
+    0x5f, 0x25, 0x50, 0x3e, 0xde, 0x21, 0x82, 0x56, 0x79, 0xd1, 0x01, 0x50, 0x25, 0x73, 0x00, 0x15,
+    0xa8, 0x4d, 0xa1, 0xd6, 0x6d, 0x09, 0xbc, 0xc2, 0x9f, 0x0d, 0xdb, 0x07, 0xb0, 0x24, 0x6c, 0x9a,
+];
+
+/// Returns the deterministic keypair that signs the genesis transaction.
+/// Anyone can rebuild it, so it never owns the genesis triangle; see
+/// `genesis_owner`.
+pub fn genesis_keypair() -> Keypair {
+    let seed = blake3::hash(GENESIS_KEY_SEED);
+    let secret = SecretKey::from_bytes(seed.as_bytes()).expect("seed is 32 bytes");
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+/// The key the genesis triangle is assigned to on the public chain.
+pub fn genesis_owner() -> PublicKey {
+    PublicKey::from_bytes(&GENESIS_OWNER).expect("valid ed25519 public key")
+}
+
+/// A known owner for the genesis triangle of test chains.
+#[cfg(test)]
+pub(crate) fn test_genesis_owner() -> Keypair {
+    let seed = blake3::hash(b"siertrichain test genesis owner");
+    let secret = SecretKey::from_bytes(seed.as_bytes()).expect("seed is 32 bytes");
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
 
 pub struct GenesisTriangle;
 
@@ -11,20 +47,13 @@ impl GenesisTriangle {
     pub fn new() -> Triangle {
         let a = Point::new(dec!(0), dec!(0));
         let b = Point::new(dec!(1), dec!(0));
-        let y = (dec!(3).sqrt().unwrap()) / dec!(2);
-        let c = Point::new(dec!(0.5), y);
+        let c = Point::new(dec!(0.5), SQRT3_2);
         Triangle::new(a, b, c)
     }
 
+    /// Exact comparison; the genesis triangle has a single valid form.
     pub fn validate(triangle: &Triangle) -> bool {
-        let side1 = triangle.a.distance(&triangle.b);
-        let side2 = triangle.b.distance(&triangle.c);
-        let side3 = triangle.c.distance(&triangle.a);
-
-        let is_equilateral = (side1 - side2).abs() < dec!(1e-9) && (side2 - side3).abs() < dec!(1e-9);
-        let is_perfect_area = (triangle.area() - (dec!(3).sqrt().unwrap() / dec!(4))).abs() < dec!(1e-9);
-
-        is_equilateral && is_perfect_area
+        *triangle == Self::new()
     }
 }
 
@@ -37,4 +66,9 @@ mod tests {
         let genesis = GenesisTriangle::new();
         assert!(GenesisTriangle::validate(&genesis));
     }
+
+    #[test]
+    fn test_genesis_owner_is_not_the_public_genesis_key() {
+        assert_ne!(genesis_owner(), genesis_keypair().public);
+    }
 }
@@ -1,10 +1,11 @@
-use crate::core::triangle::Triangle;
+use crate::core::triangle::{resolve_triangle_id, Triangle};
 use crate::wallet::address::Address;
 use crate::core::hash::H256;
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 /// Represents an insurance policy for a triangular region.
+#[derive(Debug, Clone)]
 pub struct InsurancePolicy {
     /// The address of the policyholder.
     pub policyholder: Address,
@@ -18,7 +19,9 @@ pub struct InsurancePolicy {
     pub expiration_block: u64,
 }
 
-/// Manages the insurance of triangular regions.
+/// Manages the insurance of triangular regions. Premiums are paid into
+/// `InsuranceManager::account()`, which pays the claims.
+#[derive(Debug, Clone, Default)]
 pub struct InsuranceManager {
     /// A map from a triangle identifier to a list of insurance policies.
     policies: HashMap<H256, Vec<InsurancePolicy>>,
@@ -29,6 +32,11 @@ impl InsuranceManager {
         Self { policies: HashMap::new() }
     }
 
+    /// The account premiums are paid into and claims paid out of.
+    pub fn account() -> Address {
+        Address::for_module("insurance")
+    }
+
     /// Creates a new insurance policy for a triangle.
     pub fn create_policy(&mut self, policyholder: Address, insured_triangle: Triangle, insured_value: Decimal, premium: Decimal, expiration_block: u64) {
         let triangle_id = insured_triangle.hash();
@@ -42,19 +50,57 @@ impl InsuranceManager {
         self.policies.entry(triangle_id).or_default().push(policy);
     }
 
-    /// Processes a claim for a subdivision failure or other insured event.
-    pub fn process_claim(&mut self, triangle_id: &H256, current_block: u64) {
-        // In a real implementation, we would verify the claim and pay it out.
-        if let Some(policies) = self.policies.get_mut(triangle_id) {
+    /// The insured value of every policy still running at `current_block`,
+    /// which the insurance account must be able to pay at any time. `None`
+    /// if it does not fit in a `Decimal`.
+    pub fn outstanding_cover(&self, current_block: u64) -> Option<Decimal> {
+        self.policies
+            .values()
+            .flatten()
+            .filter(|policy| policy.expiration_block >= current_block)
+            .try_fold(Decimal::ZERO, |total, policy| total.checked_add(policy.insured_value))
+    }
+
+    /// What a claim of `policyholder` on a triangle would pay at
+    /// `current_block`.
+    pub fn claimable(&self, triangle_id: &H256, policyholder: &Address, current_block: u64) -> Decimal {
+        self.policies
+            .get(&self.resolve(triangle_id))
+            .into_iter()
+            .flatten()
+            .filter(|policy| &policy.policyholder == policyholder && policy.expiration_block >= current_block)
+            .map(|policy| policy.insured_value)
+            .sum()
+    }
+
+    /// Processes a claim for a subdivision failure or other insured event,
+    /// removing the policies of `policyholder` on the triangle that still run
+    /// at `current_block`. Returns the insured value they pay out.
+    pub fn process_claim(&mut self, triangle_id: &H256, policyholder: &Address, current_block: u64) -> Decimal {
+        let key = self.resolve(triangle_id);
+        let mut payout = Decimal::ZERO;
+        if let Some(policies) = self.policies.get_mut(&key) {
             policies.retain(|policy| {
-                if policy.expiration_block >= current_block {
-                    // Payout claim
-                    println!("Paying out claim for triangle {:?} to policyholder {:?}", triangle_id, policy.policyholder);
+                if &policy.policyholder == policyholder && policy.expiration_block >= current_block {
+                    payout += policy.insured_value;
                     false // Remove policy after payout
                 } else {
-                    true // Keep active policies
+                    true // Keep other policies
                 }
             });
+            if policies.is_empty() {
+                self.policies.remove(&key);
+            }
+        }
+        payout
+    }
+
+    /// The key of the triangle `triangle_id` names, by current or legacy id.
+    fn resolve(&self, triangle_id: &H256) -> H256 {
+        if self.policies.contains_key(triangle_id) {
+            return *triangle_id;
         }
+        let stored = self.policies.values().flatten().map(|policy| &policy.insured_triangle);
+        resolve_triangle_id(triangle_id, stored).unwrap_or(*triangle_id)
     }
 }
@@ -1,9 +1,11 @@
-use crate::core::triangle::Triangle;
+use crate::core::triangle::{resolve_triangle_id, Triangle};
 use crate::wallet::address::Address;
+use crate::core::hash::H256;
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 /// Represents a loan taken out against a triangular asset.
+#[derive(Debug, Clone)]
 pub struct Loan {
     /// The address of the borrower.
     pub borrower: Address,
@@ -11,18 +13,43 @@ pub struct Loan {
     pub collateral_triangle: Triangle,
     /// The amount of tokens borrowed.
     pub loan_amount: Decimal,
-    /// The value of the collateral at the time of the loan.
-    pub collateral_value: Decimal,
-    /// The block number when the loan was taken.
-    pub start_block: u64,
-    /// The interest rate of the loan.
+    /// The interest rate of the loan, owed on top of the amount for the
+    /// whole term.
     pub interest_rate: Decimal,
+    /// The number of blocks the borrower has to repay the loan.
+    pub term: u64,
+    /// The address of the lender, `None` while nobody has funded the loan.
+    pub lender: Option<Address>,
+    /// The block number when the loan was funded.
+    pub start_block: u64,
+}
+
+impl Loan {
+    /// What the borrower has to pay back: nothing while the loan is not
+    /// funded, otherwise the amount with interest. `None` if it does not fit
+    /// in a `Decimal`.
+    pub fn amount_due(&self) -> Option<Decimal> {
+        if self.lender.is_none() {
+            return Some(Decimal::ZERO);
+        }
+        let interest = self.loan_amount.checked_mul(self.interest_rate)?;
+        self.loan_amount.checked_add(interest)
+    }
+
+    /// The last block in which the loan can be repaid before the lender may
+    /// take the collateral.
+    pub fn due_block(&self) -> u64 {
+        self.start_block.saturating_add(self.term)
+    }
 }
 
 /// Manages the lending and borrowing of assets against triangular collateral.
+/// Lenders pay borrowers directly, and take the collateral of a loan that is
+/// not repaid in time.
+#[derive(Debug, Clone, Default)]
 pub struct LendingPlatform {
-    /// A map from a borrower's address to a list of their loans.
-    loans: HashMap<Address, Vec<Loan>>,
+    /// A map from the collateral's triangle identifier to its loan.
+    loans: HashMap<H256, Loan>,
 }
 
 impl LendingPlatform {
@@ -30,38 +57,46 @@ impl LendingPlatform {
         Self { loans: HashMap::new() }
     }
 
-    /// Creates a new loan, using a triangle as collateral.
-    pub fn create_loan(&mut self, borrower: Address, collateral_triangle: Triangle, loan_amount: Decimal, collateral_value: Decimal, start_block: u64, interest_rate: Decimal) {
+    /// Requests a new loan, using a triangle as collateral.
+    pub fn create_loan(&mut self, borrower: Address, collateral_triangle: Triangle, loan_amount: Decimal, interest_rate: Decimal, term: u64) {
         let loan = Loan {
-            borrower: borrower.clone(),
+            borrower,
             collateral_triangle,
             loan_amount,
-            collateral_value,
-            start_block,
             interest_rate,
+            term,
+            lender: None,
+            start_block: 0,
         };
-        self.loans.entry(borrower).or_default().push(loan);
+        self.loans.insert(loan.collateral_triangle.hash(), loan);
+    }
+
+    /// The loan a triangle backs, by current or legacy id.
+    pub fn loan(&self, triangle_id: &H256) -> Option<&Loan> {
+        self.loans.get(&self.resolve(triangle_id))
     }
 
-    /// Repays a loan.
-    pub fn repay_loan(&mut self, borrower: &Address, loan_index: usize, amount: Decimal) {
-        if let Some(loans) = self.loans.get_mut(borrower) {
-            if let Some(loan) = loans.get_mut(loan_index) {
-                loan.loan_amount -= amount;
-                // In a real implementation, we would handle full repayment and return of collateral.
-            }
+    /// Records that `lender` paid out the loan a triangle backs.
+    pub fn fund_loan(&mut self, triangle_id: &H256, lender: Address, start_block: u64) {
+        let key = self.resolve(triangle_id);
+        if let Some(loan) = self.loans.get_mut(&key) {
+            loan.lender = Some(lender);
+            loan.start_block = start_block;
         }
     }
 
-    /// Liquidates a loan if the collateral value falls below a certain threshold.
-    pub fn liquidate_loan(&mut self, borrower: &Address, loan_index: usize) {
-        // In a real implementation, we would check the current value of the collateral
-        // and if it's below a certain loan-to-value ratio, we would liquidate the position.
-        if let Some(loans) = self.loans.get_mut(borrower) {
-            if loan_index < loans.len() {
-                loans.remove(loan_index);
-                // The collateral would be sold to cover the loan.
-            }
+    /// Removes the loan a triangle backs once it is repaid or liquidated.
+    pub fn close_loan(&mut self, triangle_id: &H256) -> Option<Loan> {
+        let key = self.resolve(triangle_id);
+        self.loans.remove(&key)
+    }
+
+    /// The key of the triangle `triangle_id` names, by current or legacy id.
+    fn resolve(&self, triangle_id: &H256) -> H256 {
+        if self.loans.contains_key(triangle_id) {
+            return *triangle_id;
         }
+        let stored = self.loans.values().map(|loan| &loan.collateral_triangle);
+        resolve_triangle_id(triangle_id, stored).unwrap_or(*triangle_id)
     }
 }
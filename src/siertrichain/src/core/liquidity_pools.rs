@@ -1,42 +1,29 @@
-use crate::core::triangle::Triangle;
-
+use crate::core::triangle::{resolve_triangle_id, Triangle};
+use crate::wallet::address::Address;
 use crate::core::hash::H256;
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 /// Represents a liquidity pool for a specific triangular region or depth.
+#[derive(Debug, Clone)]
 pub struct LiquidityPool {
     /// The triangle associated with this pool. Can be a specific region or a representative for a depth.
     pub triangle: Triangle,
     /// The total amount of the native token in the pool.
     pub token_reserve: Decimal,
-    /// The total amount of the triangle asset in the pool.
-    pub asset_reserve: Decimal,
+    /// The tokens each provider has put into the pool.
+    pub providers: HashMap<Address, Decimal>,
 }
 
 impl LiquidityPool {
-    pub fn new(triangle: Triangle, token_reserve: Decimal, asset_reserve: Decimal) -> Self {
-        Self { triangle, token_reserve, asset_reserve }
-    }
-
-    /// Calculates the amount of tokens received for a given amount of asset.
-    pub fn get_token_amount(&self, asset_amount: Decimal) -> Decimal {
-        if self.asset_reserve.is_zero() || self.token_reserve.is_zero() {
-            return Decimal::ZERO;
-        }
-        (self.token_reserve * asset_amount) / self.asset_reserve
-    }
-
-    /// Calculates the amount of asset received for a given amount of tokens.
-    pub fn get_asset_amount(&self, token_amount: Decimal) -> Decimal {
-        if self.token_reserve.is_zero() || self.asset_reserve.is_zero() {
-            return Decimal::ZERO;
-        }
-        (self.asset_reserve * token_amount) / self.token_reserve
+    pub fn new(triangle: Triangle) -> Self {
+        Self { triangle, token_reserve: Decimal::ZERO, providers: HashMap::new() }
     }
 }
 
-/// Manages all liquidity pools.
+/// Manages all liquidity pools. The tokens of every pool are held by
+/// `LiquidityPoolManager::account()`.
+#[derive(Debug, Clone, Default)]
 pub struct LiquidityPoolManager {
     pools: HashMap<H256, LiquidityPool>,
 }
@@ -46,18 +33,46 @@ impl LiquidityPoolManager {
         Self { pools: HashMap::new() }
     }
 
-    /// Creates a new liquidity pool.
-    pub fn create_pool(&mut self, triangle: Triangle, token_reserve: Decimal, asset_reserve: Decimal) {
-        let triangle_id = triangle.hash();
-        let pool = LiquidityPool::new(triangle, token_reserve, asset_reserve);
-        self.pools.insert(triangle_id, pool);
+    /// The account holding the reserves of every pool.
+    pub fn account() -> Address {
+        Address::for_module("liquidity")
+    }
+
+    /// The pool of a triangle, by current or legacy id.
+    pub fn pool(&self, triangle_id: &H256) -> Option<&LiquidityPool> {
+        self.pools.get(&self.resolve(triangle_id))
+    }
+
+    /// Adds liquidity from `provider` to the pool of a triangle, creating
+    /// the pool if needed.
+    pub fn add_liquidity(&mut self, triangle: &Triangle, provider: Address, token_amount: Decimal) {
+        let key = self.resolve(&triangle.hash());
+        let pool = self.pools.entry(key).or_insert_with(|| LiquidityPool::new(triangle.clone()));
+        pool.token_reserve += token_amount;
+        *pool.providers.entry(provider).or_default() += token_amount;
+    }
+
+    /// Removes everything `provider` put into the pool of a triangle and
+    /// returns the amount.
+    pub fn remove_liquidity(&mut self, triangle_id: &H256, provider: &Address) -> Decimal {
+        let key = self.resolve(triangle_id);
+        let Some(pool) = self.pools.get_mut(&key) else {
+            return Decimal::ZERO;
+        };
+        let amount = pool.providers.remove(provider).unwrap_or(Decimal::ZERO);
+        pool.token_reserve -= amount;
+        if pool.providers.is_empty() {
+            self.pools.remove(&key);
+        }
+        amount
     }
 
-    /// Adds liquidity to an existing pool.
-    pub fn add_liquidity(&mut self, triangle_id: &H256, token_amount: Decimal, asset_amount: Decimal) {
-        if let Some(pool) = self.pools.get_mut(triangle_id) {
-            pool.token_reserve += token_amount;
-            pool.asset_reserve += asset_amount;
+    /// The key of the triangle `triangle_id` names, by current or legacy id.
+    fn resolve(&self, triangle_id: &H256) -> H256 {
+        if self.pools.contains_key(triangle_id) {
+            return *triangle_id;
         }
+        let stored = self.pools.values().map(|pool| &pool.triangle);
+        resolve_triangle_id(triangle_id, stored).unwrap_or(*triangle_id)
     }
 }
@@ -1,24 +1,700 @@
-use crate::core::transaction::Transaction;
-use std::collections::HashMap;
+//! Transactions waiting to be mined.
+//!
+//! The pool only admits transactions that could go into the next blocks:
+//! correctly signed for this chain, not expired, and valid against the
+//! ledger at the tip. No two pending transactions may consume the same
+//! triangle. Each signer's transactions form a queue whose nonces follow
+//! the signer's next nonce at the tip without gaps, and whose summed fees
+//! and token transfers the signer's balance covers. Transactions are ranked
+//! by fee per unit of block weight, see `consensus::weight`, ties going to
+//! the earlier arrival, and the lowest ranked ones are evicted when the pool
+//! is full.
+
+use crate::consensus::weight::transaction_weight;
+use crate::core::block::Block;
+use crate::core::blockchain::{Blockchain, ChainListener};
+use crate::core::encoding::Encode;
+use crate::core::errors::{BalanceError, MempoolError, StateError};
+use crate::core::hash::H256;
+use crate::core::address::TriangleAddress;
+use crate::core::storage::{BlockStorage, TransactionStorage};
+use crate::core::transaction::{Transaction, TriangleOperation, CHAIN_ID};
+use crate::wallet::address::Address;
+use rust_decimal::Decimal;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+/// Limits of the pool.
+#[derive(Debug, Clone)]
+pub struct MempoolConfig {
+    pub max_transactions: usize,
+    /// Upper bound on the summed encoded size of pending transactions.
+    pub max_bytes: usize,
+    /// Seconds a transaction may wait before it is dropped.
+    pub max_age: i64,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_transactions: 50_000,
+            max_bytes: 32 * 1024 * 1024,
+            max_age: 72 * 60 * 60,
+        }
+    }
+}
+
+/// Rank of a pending transaction; higher is mined first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Priority {
+    fee_rate: Decimal,
+    arrival: Reverse<u64>,
+}
+
+struct Entry {
+    tx: Transaction,
+    size: usize,
+    priority: Priority,
+    /// Tokens the transaction takes from its signer.
+    debit: Decimal,
+    /// When the transaction entered the pool, in Unix seconds.
+    added: i64,
+}
+
+/// Pending transactions of one signer, by nonce.
+#[derive(Default)]
+struct Queue {
+    by_nonce: BTreeMap<u64, H256>,
+    debits: Decimal,
+}
 
 pub struct Mempool {
-    transactions: HashMap<[u8; 32], Transaction>,
+    config: MempoolConfig,
+    entries: HashMap<H256, Entry>,
+    by_priority: BTreeMap<Priority, H256>,
+    /// Pending transaction consuming each triangle.
+    spends: HashMap<TriangleAddress, H256>,
+    /// Pending transactions of each signer that uses nonces.
+    queues: HashMap<[u8; 32], Queue>,
+    bytes: usize,
+    arrivals: u64,
+    /// Latest time the pool has seen, from the chain clock or block
+    /// timestamps.
+    now: i64,
 }
 
 impl Mempool {
     pub fn new() -> Self {
-        Self { transactions: HashMap::new() }
+        Self::with_config(MempoolConfig::default())
     }
 
-    pub fn add_transaction(&mut self, tx: Transaction) {
-        self.transactions.insert(tx.hash().to_bytes(), tx);
+    pub fn with_config(config: MempoolConfig) -> Self {
+        Self {
+            config,
+            entries: HashMap::new(),
+            by_priority: BTreeMap::new(),
+            spends: HashMap::new(),
+            queues: HashMap::new(),
+            bytes: 0,
+            arrivals: 0,
+            now: 0,
+        }
     }
 
-    pub fn get_transaction(&self, hash: &[u8; 32]) -> Option<&Transaction> {
-        self.transactions.get(hash)
+    /// Validates `tx` against the tip of `chain` and the signer's pending
+    /// transactions and adds it to the pool, evicting lower ranked
+    /// transactions if the pool is full.
+    pub fn add_transaction<S: BlockStorage + TransactionStorage>(
+        &mut self,
+        tx: Transaction,
+        chain: &Blockchain<S>,
+    ) -> Result<H256, MempoolError> {
+        self.expire(chain.clock().now());
+        if tx.is_coinbase() {
+            return Err(MempoolError::Coinbase);
+        }
+        if tx.chain_id != CHAIN_ID {
+            return Err(MempoolError::WrongChain(tx.chain_id));
+        }
+        if !tx.validate() {
+            return Err(MempoolError::InvalidSignature);
+        }
+        if self.entries.contains_key(tx.hash()) {
+            return Err(MempoolError::Duplicate(*tx.hash()));
+        }
+        let queue = self.queues.get(tx.public_key.as_bytes());
+        if let Some(pending) = queue.and_then(|queue| queue.by_nonce.get(&tx.nonce)).filter(|_| tx.uses_nonce()) {
+            return Err(MempoolError::NonceInUse { nonce: tx.nonce, pending: *pending });
+        }
+        // Queues never have gaps, so the next nonce follows the last one.
+        let next_nonce = chain.state().nonce(&tx.public_key) + queue.map_or(0, |queue| queue.by_nonce.len() as u64);
+        let pending_debits = queue.map_or(Decimal::ZERO, |queue| queue.debits);
+        check_after_pending(&tx, chain, next_nonce, pending_debits)?;
+        self.insert(tx, self.now)
     }
 
+    pub fn get_transaction(&self, hash: &H256) -> Option<&Transaction> {
+        self.entries.get(hash).map(|entry| &entry.tx)
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Pending transactions in the order they should be mined: highest fee
+    /// rate first, except that each signer's transactions come in nonce
+    /// order.
     pub fn get_pending_transactions(&self) -> Vec<Transaction> {
-        self.transactions.values().cloned().collect()
+        // The next transaction of every signer, and those without a nonce.
+        let mut ready: BTreeMap<Priority, H256> = self
+            .entries
+            .values()
+            .filter(|entry| !entry.tx.uses_nonce())
+            .map(|entry| (entry.priority, *entry.tx.hash()))
+            .collect();
+        for queue in self.queues.values() {
+            if let Some(hash) = queue.by_nonce.values().next() {
+                ready.insert(self.entries[hash].priority, *hash);
+            }
+        }
+
+        let mut pending = Vec::with_capacity(self.entries.len());
+        while let Some((_, hash)) = ready.pop_last() {
+            let tx = &self.entries[&hash].tx;
+            if tx.uses_nonce() {
+                let queue = &self.queues[tx.public_key.as_bytes()];
+                if let Some((_, next)) = queue.by_nonce.range(tx.nonce + 1..).next() {
+                    ready.insert(self.entries[next].priority, *next);
+                }
+            }
+            pending.push(tx.clone());
+        }
+        pending
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Summed encoded size of the pending transactions.
+    pub fn size_in_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Removes a transaction, and the signer's transactions with later
+    /// nonces, which could no longer be mined.
+    pub fn remove(&mut self, hash: &H256) -> Option<Transaction> {
+        let tx = self.remove_entry(hash)?;
+        if tx.uses_nonce() {
+            let later: Vec<H256> = self
+                .queues
+                .get(tx.public_key.as_bytes())
+                .map(|queue| queue.by_nonce.range(tx.nonce..).map(|(_, hash)| *hash).collect())
+                .unwrap_or_default();
+            for hash in later {
+                self.remove_entry(&hash);
+            }
+        }
+        Some(tx)
+    }
+
+    fn remove_entry(&mut self, hash: &H256) -> Option<Transaction> {
+        let entry = self.entries.remove(hash)?;
+        self.by_priority.remove(&entry.priority);
+        // Only transactions with valid spends are admitted.
+        for address in entry.tx.spends().unwrap_or_default() {
+            self.spends.remove(&address);
+        }
+        if entry.tx.uses_nonce() {
+            let key = entry.tx.public_key.to_bytes();
+            if let Some(queue) = self.queues.get_mut(&key) {
+                queue.by_nonce.remove(&entry.tx.nonce);
+                queue.debits -= entry.debit;
+                if queue.by_nonce.is_empty() {
+                    self.queues.remove(&key);
+                }
+            }
+        }
+        self.bytes -= entry.size;
+        Some(entry.tx)
+    }
+
+    /// Drops transactions that have waited longer than the configured
+    /// maximum age at time `now`.
+    pub fn expire(&mut self, now: i64) {
+        self.now = self.now.max(now);
+        let cutoff = self.now - self.config.max_age;
+        let stale: Vec<H256> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.added < cutoff)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in stale {
+            self.remove(&hash);
+        }
+    }
+
+    /// Drops stale transactions and those that are no longer valid at the
+    /// tip of `chain`, together with the later nonces of their signers.
+    /// Call it after replaying a `ChainUpdate` into the pool, as
+    /// transactions re-injected from disconnected blocks are only checked
+    /// for conflicts with the pool.
+    pub fn revalidate<S: BlockStorage + TransactionStorage>(&mut self, chain: &Blockchain<S>) {
+        self.expire(chain.clock().now());
+        let mut invalid: Vec<H256> = self
+            .entries
+            .values()
+            .filter(|entry| !entry.tx.uses_nonce() && check_against_tip(&entry.tx, chain).is_err())
+            .map(|entry| *entry.tx.hash())
+            .collect();
+        for queue in self.queues.values() {
+            let mut next_nonce = None;
+            let mut debits = Decimal::ZERO;
+            for hash in queue.by_nonce.values() {
+                let tx = &self.entries[hash].tx;
+                let expected = *next_nonce.get_or_insert_with(|| chain.state().nonce(&tx.public_key));
+                match check_after_pending(tx, chain, expected, debits) {
+                    Ok(total) if tx.nonce == expected => {
+                        next_nonce = Some(expected + 1);
+                        debits = total;
+                    }
+                    _ => {
+                        invalid.push(*hash);
+                        break;
+                    }
+                }
+            }
+        }
+        for hash in invalid {
+            self.remove(&hash);
+        }
+    }
+
+    /// Adds an already validated transaction, making room for it if needed.
+    fn insert(&mut self, tx: Transaction, added: i64) -> Result<H256, MempoolError> {
+        let hash = *tx.hash();
+        if self.entries.contains_key(&hash) {
+            return Err(MempoolError::Duplicate(hash));
+        }
+        let spends = tx.spends()?;
+        for address in &spends {
+            if let Some(pending) = self.spends.get(address) {
+                return Err(MempoolError::Conflict { address: address.clone(), pending: *pending });
+            }
+        }
+        let key = tx.public_key.to_bytes();
+        if tx.uses_nonce() {
+            if let Some(pending) = self.queues.get(&key).and_then(|queue| queue.by_nonce.get(&tx.nonce)) {
+                return Err(MempoolError::NonceInUse { nonce: tx.nonce, pending: *pending });
+            }
+        }
+
+        let size = tx.encode().len();
+        if size > self.config.max_bytes {
+            return Err(MempoolError::TooLarge(size));
+        }
+        let priority = Priority {
+            fee_rate: tx.fee / Decimal::from(transaction_weight(&tx)),
+            arrival: Reverse(self.arrivals),
+        };
+
+        // Evict from the bottom until the transaction fits, but never a
+        // transaction that ranks above it. Only the last transaction of a
+        // queue can go without leaving a gap, and never one of the same
+        // signer, which the new transaction may follow.
+        let (mut count, mut bytes) = (self.entries.len(), self.bytes);
+        let mut evicted = Vec::new();
+        for (lowest, pending) in &self.by_priority {
+            if count < self.config.max_transactions && bytes + size <= self.config.max_bytes {
+                break;
+            }
+            if *lowest > priority {
+                return Err(MempoolError::FeeTooLow);
+            }
+            let candidate = &self.entries[pending].tx;
+            let is_last = !candidate.uses_nonce()
+                || self.queues[candidate.public_key.as_bytes()].by_nonce.keys().next_back() == Some(&candidate.nonce);
+            if !is_last || (tx.uses_nonce() && candidate.public_key == tx.public_key) {
+                continue;
+            }
+            evicted.push(*pending);
+            count -= 1;
+            bytes -= self.entries[pending].size;
+        }
+        if count >= self.config.max_transactions || bytes + size > self.config.max_bytes {
+            return Err(MempoolError::FeeTooLow);
+        }
+        for pending in evicted {
+            self.remove(&pending);
+        }
+
+        for address in spends {
+            self.spends.insert(address, hash);
+        }
+        let debit = debit(&tx);
+        if tx.uses_nonce() {
+            let queue = self.queues.entry(key).or_default();
+            queue.by_nonce.insert(tx.nonce, hash);
+            queue.debits = queue.debits.saturating_add(debit);
+        }
+        self.arrivals += 1;
+        self.bytes += size;
+        self.by_priority.insert(priority, hash);
+        self.entries.insert(hash, Entry { tx, size, priority, debit, added });
+        Ok(hash)
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChainListener for Mempool {
+    /// Removes the block's transactions and every pending transaction they
+    /// conflict with, and those that expire with the block.
+    fn block_connected(&mut self, block: &Block) {
+        self.now = self.now.max(block.header.timestamp);
+        for tx in &block.triangle_transactions {
+            // The block took the nonce, so later ones stay minable.
+            self.remove_entry(tx.hash());
+            if tx.uses_nonce() {
+                let taken = self.queues.get(tx.public_key.as_bytes()).and_then(|queue| queue.by_nonce.get(&tx.nonce));
+                if let Some(hash) = taken.copied() {
+                    self.remove_entry(&hash);
+                }
+            }
+            let conflicts: Vec<H256> = tx
+                .spends()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|address| self.spends.get(address).copied())
+                .collect();
+            for hash in conflicts {
+                self.remove(&hash);
+            }
+        }
+        let next_height = block.header.height + 1;
+        let expired: Vec<H256> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| matches!(entry.tx.expiry, Some(expiry) if expiry < next_height))
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired {
+            self.remove(&hash);
+        }
+    }
+
+    /// Puts the block's transactions back into the pool so they can be
+    /// mined on the new branch. Coinbases only belong to their block.
+    fn block_disconnected(&mut self, block: &Block) {
+        self.now = self.now.max(block.header.timestamp);
+        for tx in block.triangle_transactions.iter().filter(|tx| !tx.is_coinbase()) {
+            // A pending transaction that took its place wins, and a full
+            // pool may turn it away.
+            let _ = self.insert(tx.clone(), self.now);
+        }
+    }
+}
+
+/// Tokens `tx` takes from its signer: the fee, and the amount of a token
+/// transfer or the payment of a DeFi operation.
+fn debit(tx: &Transaction) -> Decimal {
+    match &tx.operation {
+        TriangleOperation::TokenTransfer { amount, .. } => amount.saturating_add(tx.fee),
+        TriangleOperation::Defi(operation) => operation.payment().saturating_add(tx.fee),
+        _ => tx.fee,
+    }
+}
+
+/// Checks that `tx` can still be mined in the block after the tip.
+fn check_against_tip<S: BlockStorage + TransactionStorage>(
+    tx: &Transaction,
+    chain: &Blockchain<S>,
+) -> Result<(), MempoolError> {
+    let next_height = chain.latest_header().height + 1;
+    if let Some(expiry) = tx.expiry.filter(|expiry| *expiry < next_height) {
+        return Err(MempoolError::Expired(expiry));
+    }
+    let base_fee = chain.next_base_fee(&chain.tip());
+    if tx.fee < base_fee {
+        return Err(MempoolError::FeeBelowBaseFee { fee: tx.fee, base_fee });
+    }
+    Ok(chain.state().check_transaction(tx, next_height)?)
+}
+
+/// Checks that `tx` can be mined right after pending transactions of its
+/// signer that use nonces up to `next_nonce` and debit `pending_debits`.
+/// Returns the debits with `tx` added.
+fn check_after_pending<S: BlockStorage + TransactionStorage>(
+    tx: &Transaction,
+    chain: &Blockchain<S>,
+    next_nonce: u64,
+    pending_debits: Decimal,
+) -> Result<Decimal, MempoolError> {
+    check_against_tip(tx, chain)?;
+    if !tx.uses_nonce() {
+        return Ok(pending_debits);
+    }
+    if tx.nonce > next_nonce {
+        return Err(MempoolError::NonceGap { expected: next_nonce, found: tx.nonce });
+    }
+    let debits = pending_debits.checked_add(debit(tx)).ok_or(StateError::Balance(BalanceError::Overflow))?;
+    if !debits.is_zero() {
+        let signer = Address::from_pubkey(&tx.public_key);
+        chain.state().balances().check_debit(&signer, debits).map_err(StateError::Balance)?;
+    }
+    Ok(debits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::clock::MockClock;
+    use crate::core::errors::{BalanceError, StateError};
+    use crate::core::genesis::test_genesis_owner;
+    use crate::core::merkle::MerkleTree;
+    use crate::core::tokenomics::BlockReward;
+    use ed25519_dalek::Keypair;
+    use rust_decimal_macros::dec;
+
+    fn test_chain() -> Blockchain {
+        Blockchain::with_genesis_owner(1, test_genesis_owner().public)
+    }
+
+    fn keypair() -> Keypair {
+        Keypair::generate(&mut rand::thread_rng())
+    }
+
+    /// Comfortably above the base fee of the first blocks.
+    const FEE: Decimal = dec!(0.01);
+
+    fn transfer(owner: &Keypair, from: TriangleAddress, nonce: u64, fee: Decimal) -> Transaction {
+        Transaction::with_fee(TriangleOperation::Transfer { from, to: keypair().public }, nonce, fee, owner)
+    }
+
+    /// A mined block on `parent` with a coinbase to `miner` followed by
+    /// `transactions`.
+    fn child_with(
+        chain: &Blockchain,
+        parent: &H256,
+        seconds: i64,
+        miner: &Keypair,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let parent_header = chain.get_header(parent).unwrap();
+        let mut block = Block::new(
+            *parent,
+            H256::default(),
+            parent_header.timestamp + seconds,
+            chain.next_bits(parent),
+            chain.next_base_fee(parent),
+            parent_header.height + 1,
+            transactions,
+        );
+        block.header.geometric_proof = chain.unclaimed_proof(parent);
+        let amount = BlockReward::for_block(&block).coinbase_amount();
+        block.triangle_transactions.insert(0, Transaction::new_coinbase(amount, block.header.height, miner));
+        block.header.merkle_root = MerkleTree::new(&block.triangle_transactions).get_root();
+        while !crate::consensus::rules::is_valid_proof_of_work(&block.header) {
+            block.header.nonce += 1;
+        }
+        block
+    }
+
+    /// Extends the chain with a block paying the genesis owner, so its
+    /// transactions can pay fees.
+    fn fund_owner(chain: &mut Blockchain) -> H256 {
+        let tip = chain.tip();
+        let block = child_with(chain, &tip, 60, &test_genesis_owner(), Vec::new());
+        let hash = block.hash();
+        chain.add_block(block).unwrap();
+        hash
+    }
+
+    #[test]
+    fn test_admits_only_transactions_valid_at_the_tip() {
+        let mut chain = test_chain();
+        let owner = test_genesis_owner();
+        let root = TriangleAddress::root();
+        let mut mempool = Mempool::new();
+
+        let thief = transfer(&keypair(), root.clone(), 0, FEE);
+        assert_eq!(
+            mempool.add_transaction(thief, &chain),
+            Err(MempoolError::State(StateError::NotOwner(root.clone())))
+        );
+        let other_chain = Transaction::signed(1, TriangleOperation::Merge { parent: root.clone() }, 0, None, FEE, &owner);
+        assert_eq!(mempool.add_transaction(other_chain, &chain), Err(MempoolError::WrongChain(1)));
+        let expired = Transaction::signed(CHAIN_ID, TriangleOperation::Merge { parent: root.clone() }, 0, Some(0), FEE, &owner);
+        assert_eq!(mempool.add_transaction(expired, &chain), Err(MempoolError::Expired(0)));
+        let coinbase = Transaction::new_coinbase(Decimal::ONE, 1, &owner);
+        assert_eq!(mempool.add_transaction(coinbase, &chain), Err(MempoolError::Coinbase));
+        let mut forged = transfer(&owner, root.clone(), 0, FEE);
+        forged.nonce = 1;
+        assert_eq!(mempool.add_transaction(forged, &chain), Err(MempoolError::InvalidSignature));
+        let base_fee = chain.next_base_fee(&chain.tip());
+        let cheap = transfer(&owner, root.clone(), 0, base_fee / dec!(2));
+        assert_eq!(
+            mempool.add_transaction(cheap, &chain),
+            Err(MempoolError::FeeBelowBaseFee { fee: base_fee / dec!(2), base_fee })
+        );
+
+        let valid = transfer(&owner, root, 0, FEE);
+        assert!(matches!(
+            mempool.add_transaction(valid.clone(), &chain),
+            Err(MempoolError::State(StateError::Balance(BalanceError::InsufficientBalance { .. })))
+        ));
+        fund_owner(&mut chain);
+        let hash = mempool.add_transaction(valid.clone(), &chain).unwrap();
+        assert_eq!(mempool.get_transaction(&hash).unwrap().hash(), valid.hash());
+        assert_eq!(mempool.size_in_bytes(), valid.encode().len());
+    }
+
+    #[test]
+    fn test_reject_conflicting_spends_and_nonces() {
+        let mut chain = test_chain();
+        fund_owner(&mut chain);
+        let owner = test_genesis_owner();
+        let root = TriangleAddress::root();
+        let mut mempool = Mempool::new();
+        let first = mempool.add_transaction(transfer(&owner, root.clone(), 0, FEE), &chain).unwrap();
+
+        assert_eq!(
+            mempool.add_transaction(transfer(&owner, root.clone(), 1, FEE), &chain),
+            Err(MempoolError::Conflict { address: root.clone(), pending: first })
+        );
+        let same_nonce = transfer(&owner, root.append(1), 0, FEE);
+        assert_eq!(
+            mempool.insert(same_nonce, 0),
+            Err(MempoolError::NonceInUse { nonce: 0, pending: first })
+        );
+        assert_eq!(mempool.len(), 1);
+
+        mempool.remove(&first);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.size_in_bytes(), 0);
+        mempool.add_transaction(transfer(&owner, root, 0, FEE), &chain).unwrap();
+    }
+
+    #[test]
+    fn test_full_pool_evicts_lowest_fee_rate() {
+        let config = MempoolConfig { max_transactions: 2, ..MempoolConfig::default() };
+        let mut mempool = Mempool::with_config(config);
+        let fees = [FEE, FEE, FEE, FEE * dec!(2)];
+        let txs: Vec<Transaction> = (0..4)
+            .map(|i| transfer(&keypair(), TriangleAddress::root().append(i % 3).append(i / 3), 0, fees[i as usize]))
+            .collect();
+
+        mempool.insert(txs[0].clone(), 0).unwrap();
+        mempool.insert(txs[1].clone(), 0).unwrap();
+        // An equal fee rate does not displace an earlier arrival.
+        assert_eq!(mempool.insert(txs[2].clone(), 0), Err(MempoolError::FeeTooLow));
+        mempool.insert(txs[3].clone(), 0).unwrap();
+
+        let pending: Vec<H256> = mempool.get_pending_transactions().iter().map(|tx| *tx.hash()).collect();
+        assert_eq!(pending, vec![*txs[3].hash(), *txs[0].hash()]);
+
+        let tiny = MempoolConfig { max_bytes: 10, ..MempoolConfig::default() };
+        let size = txs[0].encode().len();
+        assert_eq!(Mempool::with_config(tiny).insert(txs[0].clone(), 0), Err(MempoolError::TooLarge(size)));
+    }
+
+    #[test]
+    fn test_reject_nonce_gaps_and_overdrafts() {
+        let mut chain = test_chain();
+        fund_owner(&mut chain);
+        let owner = test_genesis_owner();
+        let pay = |amount, nonce| {
+            let operation = TriangleOperation::TokenTransfer { to: Address::from_pubkey(&keypair().public), amount };
+            Transaction::with_fee(operation, nonce, FEE, &owner)
+        };
+        let mut mempool = Mempool::new();
+
+        assert_eq!(
+            mempool.add_transaction(pay(dec!(1), 1), &chain),
+            Err(MempoolError::NonceGap { expected: 0, found: 1 })
+        );
+        mempool.add_transaction(pay(dec!(30), 0), &chain).unwrap();
+        // Covered by the balance on its own, but not after the first one.
+        chain.state().check_transaction(&pay(dec!(30), 1), 2).unwrap();
+        assert!(matches!(
+            mempool.add_transaction(pay(dec!(30), 1), &chain),
+            Err(MempoolError::State(StateError::Balance(BalanceError::InsufficientBalance { .. })))
+        ));
+        mempool.add_transaction(pay(dec!(10), 1), &chain).unwrap();
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn test_pending_follow_nonce_order_and_drop_with_predecessors() {
+        let (owner, other) = (keypair(), keypair());
+        let root = TriangleAddress::root();
+        let mut mempool = Mempool::new();
+        let first = transfer(&owner, root.append(0), 0, FEE);
+        let second = transfer(&owner, root.append(1), 1, FEE * dec!(3));
+        let between = transfer(&other, root.append(2), 0, FEE * dec!(2));
+        for tx in [&first, &second, &between] {
+            mempool.insert(tx.clone(), 0).unwrap();
+        }
+
+        // The higher fee of the second nonce does not jump the first one.
+        let pending: Vec<H256> = mempool.get_pending_transactions().iter().map(|tx| *tx.hash()).collect();
+        assert_eq!(pending, vec![*between.hash(), *first.hash(), *second.hash()]);
+
+        mempool.remove(first.hash());
+        assert!(!mempool.contains(second.hash()));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_expire_stale_transactions() {
+        let clock = MockClock::new(1_800_000_000);
+        let mut chain = test_chain().with_clock(clock.clone());
+        fund_owner(&mut chain);
+        let owner = test_genesis_owner();
+        let mut mempool = Mempool::with_config(MempoolConfig { max_age: 600, ..MempoolConfig::default() });
+        let hash = mempool.add_transaction(transfer(&owner, TriangleAddress::root(), 0, FEE), &chain).unwrap();
+
+        clock.advance(600);
+        mempool.revalidate(&chain);
+        assert!(mempool.contains(&hash));
+        clock.advance(1);
+        mempool.revalidate(&chain);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn test_follows_blocks_and_reinjects_on_reorg() {
+        let mut chain = test_chain();
+        let funded = fund_owner(&mut chain);
+        let owner = test_genesis_owner();
+        let root = TriangleAddress::root();
+        let mut mempool = Mempool::new();
+        let tx = transfer(&owner, root.clone(), 0, FEE);
+        mempool.add_transaction(tx.clone(), &chain).unwrap();
+
+        let update = chain.add_block(child_with(&chain, &funded, 60, &keypair(), vec![tx.clone()])).unwrap();
+        update.notify(&mut mempool);
+        mempool.revalidate(&chain);
+        assert!(mempool.is_empty());
+
+        // A longer branch without the transfer takes over.
+        let b1 = child_with(&chain, &funded, 61, &keypair(), Vec::new());
+        let b1_hash = b1.hash();
+        chain.add_block(b1).unwrap();
+        let update = chain.add_block(child_with(&chain, &b1_hash, 60, &keypair(), Vec::new())).unwrap();
+        assert!(update.is_reorg());
+        update.notify(&mut mempool);
+        mempool.revalidate(&chain);
+        assert_eq!(mempool.get_pending_transactions().len(), 1);
+        assert!(mempool.contains(tx.hash()));
     }
 }
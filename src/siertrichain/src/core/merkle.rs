@@ -1,42 +1,164 @@
 use crate::core::hash::H256;
 use crate::core::transaction::Transaction;
+use serde::{Deserialize, Serialize};
 
 pub struct MerkleTree {
-    root: H256,
-    // In a full implementation, we would store the tree structure
-    // to generate proofs.
+    /// Every level of the tree, from the transaction hashes up to the root.
+    levels: Vec<Vec<H256>>,
+}
+
+/// One step from a node towards the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProofStep {
+    /// The sibling is on the left: `parent = hash(sibling || node)`.
+    Left(H256),
+    /// The sibling is on the right: `parent = hash(node || sibling)`.
+    Right(H256),
+    /// The node was the last one on an odd-sized level and was paired with
+    /// itself: `parent = hash(node || node)`.
+    Duplicate,
+}
+
+/// Inclusion proof for a single transaction, ordered from the leaf upwards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub steps: Vec<ProofStep>,
 }
 
 impl MerkleTree {
     pub fn new(transactions: &[Transaction]) -> Self {
         if transactions.is_empty() {
-            return Self { root: H256::default() };
+            return Self { levels: Vec::new() };
         }
 
-        let mut level: Vec<H256> = transactions.iter().map(|tx| tx.hash().clone()).collect();
+        let mut level: Vec<H256> = transactions.iter().map(|tx| *tx.hash()).collect();
+        let mut levels = Vec::new();
 
         while level.len() > 1 {
-            let mut next_level = Vec::new();
-            let mut i = 0;
-            while i < level.len() {
-                let left = &level[i];
-                let right = if i + 1 < level.len() { &level[i+1] } else { left };
-                let mut combined_hash_data = [0u8; 64];
-                combined_hash_data[..32].copy_from_slice(&left.to_bytes());
-                combined_hash_data[32..].copy_from_slice(&right.to_bytes());
-                let combined_hash = blake3::hash(&combined_hash_data).into();
-                next_level.push(combined_hash);
-                i += 2;
-            }
+            let next_level = level
+                .chunks(2)
+                .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+                .collect();
+            levels.push(level);
             level = next_level;
         }
+        levels.push(level);
 
-        Self { root: level[0].clone() }
+        Self { levels }
     }
 
     pub fn get_root(&self) -> H256 {
-        self.root.clone()
+        self.levels.last().map(|level| level[0]).unwrap_or_default()
+    }
+
+    /// Builds the inclusion proof for the transaction at `index`, or `None`
+    /// if the tree has no such leaf.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.levels.first()?.len() {
+            return None;
+        }
+        let mut steps = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let step = if position % 2 == 1 {
+                ProofStep::Left(level[position - 1])
+            } else if position + 1 < level.len() {
+                ProofStep::Right(level[position + 1])
+            } else {
+                ProofStep::Duplicate
+            };
+            steps.push(step);
+            position /= 2;
+        }
+        Some(MerkleProof { index, steps })
+    }
+}
+
+/// Checks that `tx_hash` is committed to by `root` through `proof`. Needs
+/// nothing but the block header, so light clients can use it directly.
+///
+/// The proof does not commit to the number of leaves: `[a, b, c]` and
+/// `[a, b, c, c]` share a root. Consensus rejects blocks that repeat a
+/// transaction, so a proof against a valid block's root is unambiguous.
+pub fn verify_proof(root: &H256, tx_hash: &H256, proof: &MerkleProof) -> bool {
+    let mut position = proof.index;
+    let mut node = *tx_hash;
+    for step in &proof.steps {
+        // The side of each sibling is fixed by the leaf index; a proof that
+        // disagrees with its own index is rejected.
+        let is_right_child = position % 2 == 1;
+        node = match step {
+            ProofStep::Left(sibling) if is_right_child => hash_pair(sibling, &node),
+            ProofStep::Right(sibling) if !is_right_child => hash_pair(&node, sibling),
+            ProofStep::Duplicate if !is_right_child => hash_pair(&node, &node),
+            _ => return false,
+        };
+        position /= 2;
+    }
+    position == 0 && node == *root
+}
+
+fn hash_pair(left: &H256, right: &H256) -> H256 {
+    let mut combined_hash_data = [0u8; 64];
+    combined_hash_data[..32].copy_from_slice(&left.to_bytes());
+    combined_hash_data[32..].copy_from_slice(&right.to_bytes());
+    blake3::hash(&combined_hash_data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::address::TriangleAddress;
+    use crate::core::transaction::TriangleOperation;
+    use ed25519_dalek::Keypair;
+
+    fn transactions(count: usize) -> Vec<Transaction> {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        (0..count)
+            .map(|i| {
+                let operation = TriangleOperation::Transfer {
+                    from: TriangleAddress::root().append((i % 3) as u8).append((i / 3) as u8),
+                    to: keypair.public,
+                };
+                Transaction::new(operation, i as u64, &keypair)
+            })
+            .collect()
     }
 
-    // Methods for generating and verifying proofs would be added here
+    #[test]
+    fn test_every_leaf_proves_against_root() {
+        for count in 1..=7 {
+            let txs = transactions(count);
+            let tree = MerkleTree::new(&txs);
+            let root = tree.get_root();
+            for (i, tx) in txs.iter().enumerate() {
+                let proof = tree.proof(i).unwrap();
+                assert!(verify_proof(&root, tx.hash(), &proof), "leaf {} of {}", i, count);
+            }
+            assert!(tree.proof(count).is_none());
+        }
+    }
+
+    #[test]
+    fn test_odd_level_is_encoded_as_duplicate() {
+        let txs = transactions(3);
+        let tree = MerkleTree::new(&txs);
+        let proof = tree.proof(2).unwrap();
+        assert_eq!(proof.steps[0], ProofStep::Duplicate);
+        assert!(verify_proof(&tree.get_root(), txs[2].hash(), &proof));
+    }
+
+    #[test]
+    fn test_reject_wrong_leaf_or_index() {
+        let txs = transactions(4);
+        let tree = MerkleTree::new(&txs);
+        let root = tree.get_root();
+        let proof = tree.proof(1).unwrap();
+
+        assert!(!verify_proof(&root, txs[0].hash(), &proof));
+        let mut moved = proof.clone();
+        moved.index = 3;
+        assert!(!verify_proof(&root, txs[1].hash(), &moved));
+    }
 }
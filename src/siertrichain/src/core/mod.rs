@@ -1,9 +1,16 @@
+pub mod balances;
 pub mod block;
 pub mod blockchain;
+pub mod clock;
+pub mod encoding;
 pub mod hash;
+pub mod mempool;
 pub mod merkle;
 pub mod storage;
+pub mod sqlite_storage;
+pub mod state;
 pub mod transaction;
+pub mod tokenomics;
 pub mod staking;
 pub mod rental;
 pub mod exchange;
@@ -11,12 +18,15 @@ pub mod liquidity_pools;
 pub mod lending;
 pub mod insurance;
 pub mod swaps;
+pub mod defi;
 pub mod genesis;
 pub mod triangle;
+pub mod barycentric;
 pub mod address;
 pub mod geometry;
 pub mod errors;
 pub mod fractal;
+pub mod fractal_tree;
 pub mod subdivision;
 pub mod nft;
 pub mod nft_manager;
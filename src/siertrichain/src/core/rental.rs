@@ -1,10 +1,24 @@
-use crate::core::triangle::Triangle;
+use crate::core::triangle::{resolve_triangle_id, Triangle};
 use crate::wallet::address::Address;
 use crate::core::hash::H256;
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 
+/// A triangle its owner offers for rent.
+#[derive(Debug, Clone)]
+pub struct RentalOffer {
+    /// The address of the owner, who is paid the rent.
+    pub owner: Address,
+    /// The triangle on offer.
+    pub triangle: Triangle,
+    /// The rental price.
+    pub price: Decimal,
+    /// The duration of a rental in blocks.
+    pub duration: u64,
+}
+
 /// Represents a rental agreement for a triangular region.
+#[derive(Debug, Clone)]
 pub struct RentalAgreement {
     /// The address of the renter.
     pub renter: Address,
@@ -19,20 +33,40 @@ pub struct RentalAgreement {
 }
 
 /// Manages the rental of triangular regions.
+#[derive(Debug, Clone, Default)]
 pub struct RentalManager {
+    /// A map from a triangle identifier to the terms its owner offers.
+    offers: HashMap<H256, RentalOffer>,
     /// A map from a triangle identifier to a list of rental agreements.
     rentals: HashMap<H256, Vec<RentalAgreement>>,
 }
 
 impl RentalManager {
     pub fn new() -> Self {
-        Self { rentals: HashMap::new() }
+        Self { offers: HashMap::new(), rentals: HashMap::new() }
+    }
+
+    /// Offers a triangle for rent, replacing earlier terms.
+    pub fn offer(&mut self, offer: RentalOffer) {
+        self.offers.insert(offer.triangle.hash(), offer);
     }
 
-    /// Creates a new rental agreement for a triangle.
+    /// The terms a triangle is offered for rent on.
+    pub fn offer_of(&self, triangle_id: &H256) -> Option<&RentalOffer> {
+        self.offers.get(&self.resolve(triangle_id))
+    }
+
+    /// Takes a triangle off the rental market. Running rentals continue.
+    pub fn withdraw_offer(&mut self, triangle_id: &H256) -> Option<RentalOffer> {
+        let key = self.resolve(triangle_id);
+        self.offers.remove(&key)
+    }
+
+    /// Creates a new rental agreement for a triangle. Agreements that have
+    /// run out are dropped.
     pub fn rent_triangle(&mut self, renter: Address, triangle: Triangle, price: Decimal, duration: u64, current_block: u64) {
         let triangle_id = triangle.hash();
-        let expiration_block = current_block + duration;
+        let expiration_block = current_block.saturating_add(duration);
         let agreement = RentalAgreement {
             renter,
             triangle,
@@ -40,15 +74,34 @@ impl RentalManager {
             duration,
             expiration_block,
         };
-        self.rentals.entry(triangle_id).or_default().push(agreement);
+        let agreements = self.rentals.entry(triangle_id).or_default();
+        agreements.retain(|agreement| agreement.expiration_block > current_block);
+        agreements.push(agreement);
+    }
+
+    /// The rental of a triangle running at `current_block`, if any.
+    pub fn current_rental(&self, triangle_id: &H256, current_block: u64) -> Option<&RentalAgreement> {
+        self.rentals
+            .get(&self.resolve(triangle_id))?
+            .iter()
+            .find(|agreement| agreement.expiration_block > current_block)
     }
 
     /// Checks if a triangle is currently rented.
     pub fn is_rented(&self, triangle_id: &H256, current_block: u64) -> bool {
-        if let Some(agreements) = self.rentals.get(triangle_id) {
-            agreements.iter().any(|agreement| agreement.expiration_block > current_block)
-        } else {
-            false
+        self.current_rental(triangle_id, current_block).is_some()
+    }
+
+    /// The key of the triangle `triangle_id` names, by current or legacy id.
+    fn resolve(&self, triangle_id: &H256) -> H256 {
+        if self.offers.contains_key(triangle_id) || self.rentals.contains_key(triangle_id) {
+            return *triangle_id;
         }
+        let stored = self
+            .offers
+            .values()
+            .map(|offer| &offer.triangle)
+            .chain(self.rentals.values().flatten().map(|agreement| &agreement.triangle));
+        resolve_triangle_id(triangle_id, stored).unwrap_or(*triangle_id)
     }
 }
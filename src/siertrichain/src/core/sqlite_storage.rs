@@ -0,0 +1,362 @@
+use crate::core::block::{Block, BlockHeader};
+use crate::core::encoding::{Decode, Encode};
+use crate::core::errors::StorageError;
+use crate::core::hash::H256;
+use crate::core::storage::{BlockStorage, TransactionStorage};
+use crate::core::transaction::Transaction;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS blocks (
+        hash BLOB PRIMARY KEY,
+        height INTEGER NOT NULL,
+        header BLOB NOT NULL,
+        transactions BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS blocks_by_height ON blocks (height);
+    CREATE TABLE IF NOT EXISTS transactions (
+        hash BLOB PRIMARY KEY,
+        body BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transaction_blocks (
+        hash BLOB NOT NULL,
+        block_hash BLOB NOT NULL,
+        PRIMARY KEY (hash, block_hash)
+    );
+    CREATE INDEX IF NOT EXISTS transaction_blocks_by_block ON transaction_blocks (block_hash);
+    CREATE TABLE IF NOT EXISTS main_chain (
+        height INTEGER PRIMARY KEY,
+        hash BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS chain_state (
+        key TEXT PRIMARY KEY,
+        value BLOB NOT NULL
+    );
+";
+
+const TIP_KEY: &str = "tip";
+
+/// SQLite-backed block and transaction store.
+///
+/// Headers are stored apart from the transaction list so the block index can
+/// be rebuilt on startup without deserializing block bodies. A transaction
+/// is stored once and linked to every block that includes it, on any branch.
+/// All blobs use the canonical encoding of `core::encoding`.
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+}
+
+impl BlockStorage for SqliteStorage {
+    fn get_block(&self, hash: &H256) -> Result<Option<Block>, StorageError> {
+        let row: Option<(Vec<u8>, Vec<u8>)> = self
+            .conn
+            .query_row(
+                "SELECT header, transactions FROM blocks WHERE hash = ?1",
+                params![hash.to_bytes().to_vec()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        match row {
+            Some((header, transactions)) => Ok(Some(Block {
+                header: BlockHeader::decode(&header)?,
+                triangle_transactions: Vec::decode(&transactions)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn put_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        let block_hash = block.hash().to_bytes().to_vec();
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO blocks (hash, height, header, transactions) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                block_hash,
+                block.header.height as i64,
+                block.header.encode(),
+                block.triangle_transactions.encode(),
+            ],
+        )?;
+        for transaction in &block.triangle_transactions {
+            let hash = transaction.hash().to_bytes().to_vec();
+            tx.execute(
+                "INSERT OR IGNORE INTO transactions (hash, body) VALUES (?1, ?2)",
+                params![hash, transaction.encode()],
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO transaction_blocks (hash, block_hash) VALUES (?1, ?2)",
+                params![hash, block_hash],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn remove_block(&mut self, hash: &H256) -> Result<(), StorageError> {
+        let block_hash = hash.to_bytes().to_vec();
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM blocks WHERE hash = ?1", params![block_hash])?;
+        tx.execute(
+            "DELETE FROM transactions WHERE hash IN (
+                SELECT hash FROM transaction_blocks WHERE block_hash = ?1
+                EXCEPT SELECT hash FROM transaction_blocks WHERE block_hash != ?1
+            )",
+            params![block_hash],
+        )?;
+        tx.execute("DELETE FROM transaction_blocks WHERE block_hash = ?1", params![block_hash])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_header(&self, hash: &H256) -> Result<Option<BlockHeader>, StorageError> {
+        let header: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT header FROM blocks WHERE hash = ?1",
+                params![hash.to_bytes().to_vec()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match header {
+            Some(header) => Ok(Some(BlockHeader::decode(&header)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_headers(&self) -> Result<Vec<BlockHeader>, StorageError> {
+        let mut stmt = self.conn.prepare("SELECT header FROM blocks ORDER BY height")?;
+        let rows = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut headers = Vec::new();
+        for row in rows {
+            headers.push(BlockHeader::decode(&row?)?);
+        }
+        Ok(headers)
+    }
+
+    fn get_hash_at_height(&self, height: u64) -> Result<Option<H256>, StorageError> {
+        let hash: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT hash FROM main_chain WHERE height = ?1",
+                params![height as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(hash.map(|hash| H256::from_slice(&hash)))
+    }
+
+    fn put_hash_at_height(&mut self, height: u64, hash: &H256) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO main_chain (height, hash) VALUES (?1, ?2)",
+            params![height as i64, hash.to_bytes().to_vec()],
+        )?;
+        Ok(())
+    }
+
+    fn remove_hash_at_height(&mut self, height: u64) -> Result<(), StorageError> {
+        self.conn.execute("DELETE FROM main_chain WHERE height = ?1", params![height as i64])?;
+        Ok(())
+    }
+
+    fn get_tip(&self) -> Result<Option<H256>, StorageError> {
+        let tip: Option<Vec<u8>> = self
+            .conn
+            .query_row("SELECT value FROM chain_state WHERE key = ?1", params![TIP_KEY], |row| row.get(0))
+            .optional()?;
+        Ok(tip.map(|hash| H256::from_slice(&hash)))
+    }
+
+    fn put_tip(&mut self, hash: &H256) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO chain_state (key, value) VALUES (?1, ?2)",
+            params![TIP_KEY, hash.to_bytes().to_vec()],
+        )?;
+        Ok(())
+    }
+}
+
+impl TransactionStorage for SqliteStorage {
+    fn get_transaction(&self, hash: &H256) -> Result<Option<Transaction>, StorageError> {
+        let body: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT body FROM transactions WHERE hash = ?1",
+                params![hash.to_bytes().to_vec()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match body {
+            Some(body) => Ok(Some(Transaction::decode(&body)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put_transaction(&mut self, tx: &Transaction) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO transactions (hash, body) VALUES (?1, ?2)",
+            params![tx.hash().to_bytes().to_vec(), tx.encode()],
+        )?;
+        Ok(())
+    }
+
+    fn get_transaction_blocks(&self, hash: &H256) -> Result<Vec<H256>, StorageError> {
+        let mut stmt = self.conn.prepare("SELECT block_hash FROM transaction_blocks WHERE hash = ?1")?;
+        let rows = stmt.query_map(params![hash.to_bytes().to_vec()], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut blocks = Vec::new();
+        for row in rows {
+            blocks.push(H256::from_slice(&row?));
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::blockchain::Blockchain;
+    use crate::core::merkle::MerkleTree;
+    use crate::core::address::TriangleAddress;
+    use crate::core::tokenomics::BlockReward;
+    use crate::core::transaction::TriangleOperation;
+    use rust_decimal_macros::dec;
+
+    fn temp_db_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("siertrichain-{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn mine_child(chain: &Blockchain<SqliteStorage>) -> Block {
+        mine_child_of(chain, &chain.tip(), 60, Vec::new())
+    }
+
+    /// A mined block on `parent` with a coinbase followed by `transactions`.
+    fn mine_child_of(
+        chain: &Blockchain<SqliteStorage>,
+        parent: &H256,
+        seconds: i64,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let parent_header = chain.get_header(parent).unwrap();
+        let mut block = Block::new(
+            *parent,
+            H256::default(),
+            parent_header.timestamp + seconds,
+            chain.next_bits(parent),
+            chain.next_base_fee(parent),
+            parent_header.height + 1,
+            transactions,
+        );
+        block.header.geometric_proof = chain.unclaimed_proof(parent);
+        let miner = ed25519_dalek::Keypair::generate(&mut rand::thread_rng());
+        let amount = BlockReward::for_block(&block).coinbase_amount();
+        block.triangle_transactions.insert(0, Transaction::new_coinbase(amount, block.header.height, &miner));
+        block.header.merkle_root = MerkleTree::new(&block.triangle_transactions).get_root();
+        while !crate::consensus::rules::is_valid_proof_of_work(&block.header) {
+            block.header.nonce += 1;
+        }
+        block
+    }
+
+    #[test]
+    fn test_block_and_transaction_round_trip() {
+        let chain = Blockchain::open(SqliteStorage::open_in_memory().unwrap(), 1).unwrap();
+        let genesis = chain.latest_block().unwrap();
+        let storage = chain.storage();
+
+        let stored = storage.get_block(&genesis.hash()).unwrap().unwrap();
+        assert_eq!(stored.hash(), genesis.hash());
+        assert_eq!(storage.get_header(&genesis.hash()).unwrap().unwrap().hash(), genesis.hash());
+        assert_eq!(storage.get_hash_at_height(0).unwrap(), Some(genesis.hash()));
+
+        let tx_hash = *genesis.triangle_transactions[0].hash();
+        assert!(storage.get_transaction(&tx_hash).unwrap().is_some());
+        assert_eq!(storage.get_transaction_blocks(&tx_hash).unwrap(), vec![genesis.hash()]);
+    }
+
+    #[test]
+    fn test_reopen_resumes_at_stored_tip() {
+        let path = temp_db_path("reopen");
+        let tip = {
+            let mut chain = Blockchain::open(SqliteStorage::open(&path).unwrap(), 1).unwrap();
+            for _ in 0..3 {
+                let block = mine_child(&chain);
+                chain.add_block(block).unwrap();
+            }
+            chain.tip()
+        };
+
+        let chain = Blockchain::open(SqliteStorage::open(&path).unwrap(), 1).unwrap();
+        assert_eq!(chain.tip(), tip);
+        assert_eq!(chain.latest_header().height, 3);
+        assert_eq!(chain.get_chain_work(&tip), crate::consensus::difficulty::U256::from(4));
+        assert_eq!(chain.get_header_by_height(3).unwrap().hash(), tip);
+        assert_eq!(chain.supply().issued, dec!(150));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_reopen_forgets_invalid_side_branch() {
+        let path = temp_db_path("invalid-branch");
+        let (a1, b1, b2, theft) = {
+            let mut chain = Blockchain::open(SqliteStorage::open(&path).unwrap(), 1).unwrap();
+            let genesis = chain.tip();
+            let a1 = mine_child_of(&chain, &genesis, 60, Vec::new());
+            chain.add_block(a1.clone()).unwrap();
+            let b1 = mine_child_of(&chain, &genesis, 61, Vec::new());
+            chain.add_block(b1.clone()).unwrap();
+
+            // The heavier branch spends a triangle its signer does not own,
+            // so the chain stays on `a1` and `b2` is dropped.
+            let thief = ed25519_dalek::Keypair::generate(&mut rand::thread_rng());
+            let operation = TriangleOperation::Transfer { from: TriangleAddress::root(), to: thief.public };
+            let theft = Transaction::with_fee(operation, 0, dec!(0.01), &thief);
+            let b2 = mine_child_of(&chain, &b1.hash(), 60, vec![theft.clone()]);
+            assert!(chain.add_block(b2.clone()).is_err());
+            assert_eq!(chain.tip(), a1.hash());
+            assert!(chain.get_block(&b2.hash()).unwrap().is_none());
+            (a1, b1, b2, theft)
+        };
+
+        let chain = Blockchain::open(SqliteStorage::open(&path).unwrap(), 1).unwrap();
+        assert_eq!(chain.tip(), a1.hash());
+        assert!(chain.get_header(&b1.hash()).is_some());
+        assert!(chain.get_header(&b2.hash()).is_none());
+        assert!(chain.storage().get_block(&b2.hash()).unwrap().is_none());
+        assert!(chain.get_transaction(theft.hash()).unwrap().is_none());
+        assert!(chain.storage().get_transaction_blocks(theft.hash()).unwrap().is_empty());
+
+        // A side branch transaction is stored but not resolved to a block.
+        let side_coinbase = b1.triangle_transactions[0].hash();
+        assert!(chain.get_transaction(side_coinbase).unwrap().is_some());
+        assert_eq!(chain.get_transaction_block(side_coinbase).unwrap(), None);
+        let coinbase = a1.triangle_transactions[0].hash();
+        assert_eq!(chain.get_transaction_block(coinbase).unwrap(), Some(a1.hash()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_reopen_rejects_foreign_genesis() {
+        let path = temp_db_path("genesis");
+        Blockchain::open(SqliteStorage::open(&path).unwrap(), 1).unwrap();
+        let reopened = Blockchain::open(SqliteStorage::open(&path).unwrap(), 2);
+        assert!(matches!(reopened, Err(crate::core::errors::BlockchainError::GenesisMismatch)));
+        let _ = std::fs::remove_file(&path);
+    }
+}
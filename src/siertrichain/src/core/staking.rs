@@ -1,10 +1,11 @@
-use crate::core::triangle::Triangle;
+use crate::core::triangle::{resolve_triangle_id, Triangle};
 use crate::wallet::address::Address;
 use crate::core::hash::H256;
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 /// Represents a stake in a specific triangular region.
+#[derive(Debug, Clone)]
 pub struct Stake {
     /// The address of the staker.
     pub staker: Address,
@@ -14,7 +15,9 @@ pub struct Stake {
     pub amount: Decimal,
 }
 
-/// Manages all staking activities within the blockchain.
+/// Manages all staking activities within the blockchain. The staked tokens
+/// are held by `StakingManager::account()`.
+#[derive(Debug, Clone, Default)]
 pub struct StakingManager {
     /// A map from a triangle identifier to a list of stakes in that triangle.
     stakes: HashMap<H256, Vec<Stake>>,
@@ -25,25 +28,83 @@ impl StakingManager {
         Self { stakes: HashMap::new() }
     }
 
+    /// The account holding every stake.
+    pub fn account() -> Address {
+        Address::for_module("staking")
+    }
+
     /// Adds a new stake to a specific triangle.
     pub fn add_stake(&mut self, stake: Stake) {
         let triangle_id = stake.triangle.hash();
         self.stakes.entry(triangle_id).or_default().push(stake);
     }
 
+    /// The total `staker` has staked on a triangle.
+    pub fn staked_by(&self, triangle_id: &H256, staker: &Address) -> Decimal {
+        self.stakes
+            .get(&self.resolve(triangle_id))
+            .map(|stakes| stakes.iter().filter(|stake| &stake.staker == staker).map(|stake| stake.amount).sum())
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Whether anybody stakes on a triangle.
+    pub fn is_staked(&self, triangle_id: &H256) -> bool {
+        self.stakes.contains_key(&self.resolve(triangle_id))
+    }
+
+    /// Removes every stake of `staker` on a triangle and returns their total.
+    pub fn remove_stakes(&mut self, triangle_id: &H256, staker: &Address) -> Decimal {
+        let key = self.resolve(triangle_id);
+        let Some(stakes) = self.stakes.get_mut(&key) else {
+            return Decimal::ZERO;
+        };
+        let mut total = Decimal::ZERO;
+        stakes.retain(|stake| {
+            if &stake.staker != staker {
+                return true;
+            }
+            total += stake.amount;
+            false
+        });
+        if stakes.is_empty() {
+            self.stakes.remove(&key);
+        }
+        total
+    }
+
     /// Calculates the staking rewards for a given triangle and subdivision activity.
     /// Rewards are distributed to stakers in proportion to their staked amount.
-    pub fn distribute_rewards(&self, triangle_id: &H256, subdivision_reward: Decimal) {
-        if let Some(stakes) = self.stakes.get(triangle_id) {
-            let total_staked: Decimal = stakes.iter().map(|s| s.amount).sum();
-
-            if total_staked > Decimal::ZERO {
-                for stake in stakes {
-                    let reward = (stake.amount / total_staked) * subdivision_reward;
-                    // In a real implementation, this reward would be transferred to the staker's address.
-                    println!("Rewarding staker {:?} with {:?} tokens", stake.staker, reward);
-                }
-            }
+    /// The shares add up to exactly `subdivision_reward`; there are none if
+    /// nobody stakes on the triangle.
+    pub fn distribute_rewards(&self, triangle_id: &H256, subdivision_reward: Decimal) -> Vec<(Address, Decimal)> {
+        let Some(stakes) = self.stakes.get(&self.resolve(triangle_id)) else {
+            return Vec::new();
+        };
+        let total_staked: Decimal = stakes.iter().map(|s| s.amount).sum();
+        if total_staked <= Decimal::ZERO {
+            return Vec::new();
+        }
+        // The last staker gets what rounding left over.
+        let mut remaining = subdivision_reward;
+        let mut rewards = Vec::with_capacity(stakes.len());
+        for (i, stake) in stakes.iter().enumerate() {
+            let reward = if i + 1 == stakes.len() {
+                remaining
+            } else {
+                ((stake.amount / total_staked) * subdivision_reward).min(remaining)
+            };
+            remaining -= reward;
+            rewards.push((stake.staker, reward));
+        }
+        rewards
+    }
+
+    /// The key of the triangle `triangle_id` names, by current or legacy id.
+    fn resolve(&self, triangle_id: &H256) -> H256 {
+        if self.stakes.contains_key(triangle_id) {
+            return *triangle_id;
         }
+        let stored = self.stakes.values().flatten().map(|stake| &stake.triangle);
+        resolve_triangle_id(triangle_id, stored).unwrap_or(*triangle_id)
     }
 }
@@ -0,0 +1,1004 @@
+use crate::core::address::TriangleAddress;
+use crate::core::balances::BalanceLedger;
+use crate::core::block::Block;
+use crate::core::defi::{DefiOperation, DefiState, Effect};
+use crate::core::errors::{BalanceError, StateError, ValidationError};
+use crate::core::fractal::TriangleState;
+use crate::core::fractal_tree::FractalTree;
+use crate::core::genesis::{genesis_keypair, genesis_owner};
+use crate::core::barycentric::{cartesian_children, cartesian_void};
+use crate::core::transaction::{Transaction, TriangleOperation};
+use crate::core::triangle::Triangle;
+use crate::wallet::address::Address;
+use ed25519_dalek::PublicKey;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Ownership and lifecycle of a single triangle in the ledger.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriangleRecord {
+    pub triangle: Triangle,
+    pub owner: PublicKey,
+    pub state: TriangleState,
+}
+
+impl TriangleRecord {
+    /// Whether the triangle can still be transferred or subdivided.
+    pub fn is_spendable(&self) -> bool {
+        matches!(self.state, TriangleState::Genesis | TriangleState::Active)
+    }
+}
+
+/// Why a triangle cannot be transferred, subdivided or merged for now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encumbrance {
+    /// Its owner stakes on it, until they unstake.
+    Staked,
+    /// It backs a loan, until the loan is repaid or liquidated.
+    Collateral,
+    /// Someone rents it, until the rental runs out.
+    Rented,
+}
+
+impl Encumbrance {
+    const ALL: [Encumbrance; 3] = [Encumbrance::Staked, Encumbrance::Collateral, Encumbrance::Rented];
+}
+
+impl fmt::Display for Encumbrance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encumbrance::Staked => "staked",
+            Encumbrance::Collateral => "pledged as loan collateral",
+            Encumbrance::Rented => "rented out",
+        })
+    }
+}
+
+/// Everything a block changed in the ledger, kept so the block can be
+/// disconnected again during a reorganization.
+#[derive(Debug, Clone, Default)]
+pub struct BlockUndo {
+    /// The record each touched address held before the block, in the order
+    /// the addresses were first touched. `None` means it did not exist.
+    previous: Vec<(TriangleAddress, Option<TriangleRecord>)>,
+    /// The next nonce of each account the block advanced, before the block.
+    nonces: Vec<([u8; 32], u64)>,
+    /// The token balance of each account the block touched, before it.
+    balances: Vec<(Address, Decimal)>,
+    /// The geometric proof the block claimed.
+    claimed_proof: Option<TriangleAddress>,
+    /// The DeFi books before the block, if it changed them.
+    defi: Option<Box<DefiState>>,
+    /// The end of each encumbrance the block set or lifted, before it.
+    encumbrances: Vec<((TriangleAddress, Encumbrance), Option<u64>)>,
+}
+
+/// The triangle ownership ledger: who owns which `TriangleAddress` and in
+/// which state, as of the tip of the active chain, together with the token
+/// balances and the books of the DeFi modules.
+pub struct WorldState {
+    triangles: FractalTree,
+    /// Next expected transaction nonce per signer. Absent means zero.
+    nonces: HashMap<[u8; 32], u64>,
+    /// Coinbases mint into it, fees are burned from it.
+    balances: BalanceLedger,
+    /// Geometric proofs of the blocks so far. Each can be mined once.
+    claimed_proofs: HashSet<TriangleAddress>,
+    defi: DefiState,
+    /// The block each encumbrance of a triangle ends at. Lifted ones are
+    /// removed; rentals are left to run out.
+    encumbrances: HashMap<(TriangleAddress, Encumbrance), u64>,
+    /// Receives the root triangle created by the genesis transaction, which
+    /// is signed by a key anyone can rebuild.
+    genesis_owner: PublicKey,
+}
+
+impl Default for WorldState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WorldState {
+    pub fn new() -> Self {
+        Self::with_genesis_owner(genesis_owner())
+    }
+
+    /// A ledger whose genesis triangle goes to `owner`, e.g. a key held by
+    /// a test or a private network.
+    ///
+    /// Panics if `owner` is the public genesis key.
+    pub fn with_genesis_owner(owner: PublicKey) -> Self {
+        assert!(owner != genesis_keypair().public, "the genesis key is public and cannot own the genesis triangle");
+        Self {
+            triangles: FractalTree::new(),
+            nonces: HashMap::new(),
+            balances: BalanceLedger::new(),
+            claimed_proofs: HashSet::new(),
+            defi: DefiState::new(),
+            encumbrances: HashMap::new(),
+            genesis_owner: owner,
+        }
+    }
+
+    /// Owner of the root triangle when the genesis transaction created it.
+    pub fn genesis_owner(&self) -> &PublicKey {
+        &self.genesis_owner
+    }
+
+    pub fn get(&self, address: &TriangleAddress) -> Option<&TriangleRecord> {
+        self.triangles.get(address)
+    }
+
+    pub fn owner_of(&self, address: &TriangleAddress) -> Option<&PublicKey> {
+        self.triangles.get(address).map(|record| &record.owner)
+    }
+
+    /// Every triangle in the ledger, in depth-first order.
+    pub fn iter(&self) -> impl Iterator<Item = (TriangleAddress, &TriangleRecord)> {
+        self.triangles.iter()
+    }
+
+    /// Triangles that can still be transferred or subdivided, in
+    /// depth-first order.
+    pub fn active_leaves(&self) -> impl Iterator<Item = &TriangleAddress> {
+        self.triangles.active_leaves()
+    }
+
+    /// Returns every triangle currently owned by `owner`.
+    pub fn triangles_owned_by(&self, owner: &PublicKey) -> Vec<(TriangleAddress, &TriangleRecord)> {
+        self.triangles.iter().filter(|(_, record)| &record.owner == owner).collect()
+    }
+
+    /// The nonce the next transaction signed by `account` must carry.
+    pub fn nonce(&self, account: &PublicKey) -> u64 {
+        self.nonces.get(account.as_bytes()).copied().unwrap_or(0)
+    }
+
+    /// Native token balances as of the tip.
+    pub fn balances(&self) -> &BalanceLedger {
+        &self.balances
+    }
+
+    /// Stakes, policies, loans, rentals, orders, pools and swaps as of the
+    /// tip.
+    pub fn defi(&self) -> &DefiState {
+        &self.defi
+    }
+
+    /// What keeps `address` from changing hands in the block at `height`,
+    /// if anything.
+    pub fn encumbrance(&self, address: &TriangleAddress, height: u64) -> Option<Encumbrance> {
+        Encumbrance::ALL
+            .into_iter()
+            .find(|&encumbrance| self.encumbrances.get(&(address.clone(), encumbrance)).is_some_and(|&end| height < end))
+    }
+
+    /// Whether a block of the chain already mined `proof`.
+    pub fn is_claimed(&self, proof: &TriangleAddress) -> bool {
+        self.claimed_proofs.contains(proof)
+    }
+
+    /// Applies every operation in the block and claims its geometric proof,
+    /// except for the genesis block. Either the whole block applies or the
+    /// ledger is left untouched and the first violation is returned.
+    pub fn apply_block(&mut self, block: &Block) -> Result<BlockUndo, ValidationError> {
+        let mut undo = BlockUndo::default();
+        let mut spent = HashSet::new();
+        for (index, tx) in block.triangle_transactions.iter().enumerate() {
+            if let Err(error) = self.apply_transaction(tx, block.header.height, &mut spent, &mut undo) {
+                self.undo_block(undo);
+                return Err(ValidationError::Transaction { index, error });
+            }
+        }
+        let proof = &block.header.geometric_proof;
+        if block.header.height > 0 && self.claimed_proofs.insert(proof.clone()) {
+            undo.claimed_proof = Some(proof.clone());
+        }
+        Ok(undo)
+    }
+
+    /// Reverts the changes recorded by `apply_block`.
+    pub fn undo_block(&mut self, undo: BlockUndo) {
+        if let Some(proof) = &undo.claimed_proof {
+            self.claimed_proofs.remove(proof);
+        }
+        if let Some(defi) = undo.defi {
+            self.defi = *defi;
+        }
+        for (key, end) in undo.encumbrances.into_iter().rev() {
+            match end {
+                Some(end) => self.encumbrances.insert(key, end),
+                None => self.encumbrances.remove(&key),
+            };
+        }
+        for (account, balance) in undo.balances.into_iter().rev() {
+            self.balances.restore(&account, balance);
+        }
+        for (account, nonce) in undo.nonces.into_iter().rev() {
+            if nonce == 0 {
+                self.nonces.remove(&account);
+            } else {
+                self.nonces.insert(account, nonce);
+            }
+        }
+        for (address, previous) in undo.previous.into_iter().rev() {
+            match previous {
+                Some(record) => self.triangles.insert(address, record),
+                None => self.triangles.remove(&address),
+            };
+        }
+    }
+
+    fn apply_transaction(
+        &mut self,
+        tx: &Transaction,
+        height: u64,
+        spent: &mut HashSet<TriangleAddress>,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        if tx.uses_nonce() {
+            let expected = self.nonce(&tx.public_key);
+            if tx.nonce != expected {
+                return Err(StateError::BadNonce { expected, found: tx.nonce });
+            }
+            let account = tx.public_key.to_bytes();
+            undo.nonces.push((account, expected));
+            self.nonces.insert(account, expected + 1);
+        }
+        match &tx.operation {
+            TriangleOperation::Create(triangle) => {
+                let address = TriangleAddress::root();
+                if self.triangles.contains(&address) {
+                    return Err(StateError::AlreadyExists(address));
+                }
+                let record = TriangleRecord {
+                    triangle: triangle.clone(),
+                    owner: self.genesis_owner,
+                    state: TriangleState::Genesis,
+                };
+                self.write(address, record, undo);
+            }
+            TriangleOperation::Subdivide { parent, children } => {
+                let mut record = self.spend(parent, &tx.public_key, spent)?;
+                self.check_unencumbered(parent, height)?;
+                check_children(parent, children)?;
+                for (i, child) in children.iter().enumerate() {
+                    let address = parent.child(i as u8).map_err(|_| StateError::MaxDepthExceeded(parent.clone()))?;
+                    if self.triangles.contains(&address) {
+                        return Err(StateError::AlreadyExists(address));
+                    }
+                    let child_record = TriangleRecord {
+                        triangle: child.clone(),
+                        owner: tx.public_key,
+                        state: TriangleState::Active,
+                    };
+                    self.write(address, child_record, undo);
+                }
+                // The removed middle is recorded too, so the children and the
+                // void together account for the whole parent.
+                let void = parent.void().map_err(|_| StateError::MaxDepthExceeded(parent.clone()))?;
+                let void_record = TriangleRecord {
+                    triangle: cartesian_void(parent).map_err(|_| StateError::MaxDepthExceeded(parent.clone()))?,
+                    owner: tx.public_key,
+                    state: TriangleState::Void,
+                };
+                self.write(void, void_record, undo);
+                record.state = TriangleState::Subdivided;
+                self.write(parent.clone(), record, undo);
+            }
+            TriangleOperation::Merge { parent } => {
+                let mut record = self.spend_children(parent, &tx.public_key, height, spent)?;
+                let void = parent.void().map_err(|_| StateError::MaxDepthExceeded(parent.clone()))?;
+                for i in 0..3 {
+                    self.erase(&parent.append(i), undo);
+                }
+                self.erase(&void, undo);
+                spent.insert(parent.clone());
+                record.owner = tx.public_key;
+                record.state = TriangleState::Active;
+                self.write(parent.clone(), record, undo);
+            }
+            TriangleOperation::Transfer { from, to } => {
+                let mut record = self.spend(from, &tx.public_key, spent)?;
+                self.check_unencumbered(from, height)?;
+                record.owner = *to;
+                self.write(from.clone(), record, undo);
+            }
+            // The amount is checked by consensus.
+            TriangleOperation::Coinbase { recipient, amount, .. } => {
+                if !amount.is_zero() {
+                    let account = Address::from_pubkey(recipient);
+                    self.touch(&account, undo);
+                    self.balances.mint(&account, *amount)?;
+                }
+            }
+            TriangleOperation::TokenTransfer { to, amount } => {
+                if to.is_module() {
+                    return Err(StateError::ModuleAccount(*to));
+                }
+                let from = Address::from_pubkey(&tx.public_key);
+                self.touch(&from, undo);
+                self.touch(to, undo);
+                self.balances.transfer(&from, to, *amount)?;
+            }
+            TriangleOperation::Defi(operation) => {
+                if let Some(address) = operation.owned() {
+                    self.spend(address, &tx.public_key, spent)?;
+                }
+                self.defi.check(operation, &tx.public_key, height, self)?;
+                // The books are restored as a whole; copy them before the
+                // block first changes them.
+                if undo.defi.is_none() {
+                    undo.defi = Some(Box::new(self.defi.clone()));
+                }
+                let record = operation.triangle().and_then(|address| self.triangles.get(address));
+                let effects = self.defi.apply(operation, &tx.public_key, height, record)?;
+                self.update_encumbrances(operation, height, undo);
+                for effect in effects {
+                    match effect {
+                        Effect::Pay { from, to, amount } => {
+                            self.touch(&from, undo);
+                            self.touch(&to, undo);
+                            self.balances.transfer(&from, &to, amount)?;
+                        }
+                        Effect::HandOver { triangle, from, to } => self.hand_over(&triangle, &from, &to, height, spent, undo)?,
+                    }
+                }
+            }
+        }
+        // The whole fee leaves the signer: the base fee is burned and the
+        // coinbase mints the tip to the miner.
+        if !tx.is_coinbase() && !tx.fee.is_zero() {
+            let payer = Address::from_pubkey(&tx.public_key);
+            self.touch(&payer, undo);
+            self.balances.burn(&payer, tx.fee)?;
+        }
+        Ok(())
+    }
+
+    /// Records the balance of `account` before the block changes it.
+    fn touch(&self, account: &Address, undo: &mut BlockUndo) {
+        undo.balances.push((*account, self.balances.balance(account)));
+    }
+
+    /// Checks `tx` against the ledger without applying it, as if it were
+    /// mined at `height`: its nonce must not be used yet, the signer must be
+    /// able to spend every triangle it consumes and hold the tokens it pays. Later nonces pass, so a signer
+    /// can queue several transactions; `apply_block` still requires them in
+    /// order.
+    pub fn check_transaction(&self, tx: &Transaction, height: u64) -> Result<(), StateError> {
+        if tx.uses_nonce() {
+            let expected = self.nonce(&tx.public_key);
+            if tx.nonce < expected {
+                return Err(StateError::BadNonce { expected, found: tx.nonce });
+            }
+        }
+        let mut spent = HashSet::new();
+        match &tx.operation {
+            TriangleOperation::Create(_) => {
+                let address = TriangleAddress::root();
+                if self.triangles.contains(&address) {
+                    return Err(StateError::AlreadyExists(address));
+                }
+            }
+            TriangleOperation::Subdivide { parent, children } => {
+                self.spend(parent, &tx.public_key, &mut spent)?;
+                self.check_unencumbered(parent, height)?;
+                check_children(parent, children)?;
+            }
+            TriangleOperation::Merge { parent } => {
+                self.spend_children(parent, &tx.public_key, height, &mut spent)?;
+            }
+            TriangleOperation::Transfer { from, .. } => {
+                self.spend(from, &tx.public_key, &mut spent)?;
+                self.check_unencumbered(from, height)?;
+            }
+            TriangleOperation::TokenTransfer { to, .. } => {
+                if to.is_module() {
+                    return Err(StateError::ModuleAccount(*to));
+                }
+            }
+            TriangleOperation::Defi(operation) => {
+                if let Some(address) = operation.owned() {
+                    self.spend(address, &tx.public_key, &mut spent)?;
+                }
+                self.defi.check(operation, &tx.public_key, height, self)?;
+            }
+            TriangleOperation::Coinbase { .. } => {}
+        }
+        if tx.is_coinbase() {
+            return Ok(());
+        }
+        let cost = match &tx.operation {
+            TriangleOperation::TokenTransfer { amount, .. } => {
+                if *amount <= Decimal::ZERO {
+                    return Err(BalanceError::InvalidAmount(*amount).into());
+                }
+                amount.checked_add(tx.fee).ok_or(BalanceError::Overflow)?
+            }
+            TriangleOperation::Defi(operation) => operation.payment().checked_add(tx.fee).ok_or(BalanceError::Overflow)?,
+            _ => tx.fee,
+        };
+        if !cost.is_zero() {
+            self.balances.check_debit(&Address::from_pubkey(&tx.public_key), cost)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that `signer` may merge the children of the subdivided
+    /// `parent` at `height`, marks them as spent and returns the parent's
+    /// record.
+    fn spend_children(
+        &self,
+        parent: &TriangleAddress,
+        signer: &PublicKey,
+        height: u64,
+        spent: &mut HashSet<TriangleAddress>,
+    ) -> Result<TriangleRecord, StateError> {
+        let record = self
+            .triangles
+            .get(parent)
+            .cloned()
+            .ok_or_else(|| StateError::UnknownTriangle(parent.clone()))?;
+        if record.state != TriangleState::Subdivided {
+            return Err(StateError::NotSubdivided(parent.clone()));
+        }
+        // Only active children can be merged, so none of them has records
+        // of its own below it.
+        for i in 0..3 {
+            let child = parent.append(i);
+            self.spend(&child, signer, spent)?;
+            self.check_unencumbered(&child, height)?;
+        }
+        Ok(record)
+    }
+
+    /// Checks that `signer` may spend `address` and marks it as spent for
+    /// the rest of the block.
+    fn spend(
+        &self,
+        address: &TriangleAddress,
+        signer: &PublicKey,
+        spent: &mut HashSet<TriangleAddress>,
+    ) -> Result<TriangleRecord, StateError> {
+        if address.is_void() {
+            return Err(StateError::Void(address.clone()));
+        }
+        if spent.contains(address) {
+            return Err(StateError::DoubleSpend(address.clone()));
+        }
+        let record = self
+            .triangles
+            .get(address)
+            .ok_or_else(|| StateError::UnknownTriangle(address.clone()))?;
+        if &record.owner != signer {
+            return Err(StateError::NotOwner(address.clone()));
+        }
+        if !record.is_spendable() {
+            return Err(StateError::NotActive(address.clone()));
+        }
+        spent.insert(address.clone());
+        Ok(record.clone())
+    }
+
+    fn check_unencumbered(&self, address: &TriangleAddress, height: u64) -> Result<(), StateError> {
+        match self.encumbrance(address, height) {
+            Some(encumbrance) => Err(StateError::Encumbered { triangle: address.clone(), encumbrance }),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets or lifts the encumbrance `operation`, just applied to the
+    /// books, puts on its triangle.
+    fn update_encumbrances(&mut self, operation: &DefiOperation, height: u64, undo: &mut BlockUndo) {
+        let Some(address) = operation.triangle() else {
+            return;
+        };
+        let Some(id) = self.triangles.get(address).map(|record| record.triangle.hash()) else {
+            return;
+        };
+        let (encumbrance, end) = match operation {
+            DefiOperation::Stake { .. } => (Encumbrance::Staked, Some(u64::MAX)),
+            DefiOperation::Unstake { .. } if !self.defi.staking.is_staked(&id) => (Encumbrance::Staked, None),
+            DefiOperation::RequestLoan { .. } => (Encumbrance::Collateral, Some(u64::MAX)),
+            DefiOperation::RepayLoan { .. } | DefiOperation::Liquidate { .. } => (Encumbrance::Collateral, None),
+            DefiOperation::Rent { .. } => {
+                let end = self.defi.rental.current_rental(&id, height).map(|rental| rental.expiration_block);
+                (Encumbrance::Rented, end)
+            }
+            _ => return,
+        };
+        let key = (address.clone(), encumbrance);
+        let previous = match end {
+            Some(end) => self.encumbrances.insert(key.clone(), end),
+            None => self.encumbrances.remove(&key),
+        };
+        undo.encumbrances.push((key, previous));
+    }
+
+    /// Gives the triangle `from` owns at `address` to `to` in the block at
+    /// `height` and marks it as spent for the rest of the block.
+    fn hand_over(
+        &mut self,
+        address: &TriangleAddress,
+        from: &Address,
+        to: &Address,
+        height: u64,
+        spent: &mut HashSet<TriangleAddress>,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        let new_owner = to.public_key().ok_or(StateError::ModuleAccount(*to))?;
+        let mut record = self
+            .triangles
+            .get(address)
+            .cloned()
+            .ok_or_else(|| StateError::UnknownTriangle(address.clone()))?;
+        if Address::from_pubkey(&record.owner) != *from {
+            return Err(StateError::NotOwner(address.clone()));
+        }
+        if !record.is_spendable() {
+            return Err(StateError::NotActive(address.clone()));
+        }
+        self.check_unencumbered(address, height)?;
+        spent.insert(address.clone());
+        record.owner = new_owner;
+        self.write(address.clone(), record, undo);
+        Ok(())
+    }
+
+    fn write(&mut self, address: TriangleAddress, record: TriangleRecord, undo: &mut BlockUndo) {
+        let previous = self.triangles.insert(address.clone(), record);
+        undo.previous.push((address, previous));
+    }
+
+    fn erase(&mut self, address: &TriangleAddress, undo: &mut BlockUndo) {
+        let previous = self.triangles.remove(address);
+        undo.previous.push((address.clone(), previous));
+    }
+}
+
+/// Children are fully determined by the parent's address.
+fn check_children(parent: &TriangleAddress, children: &[Triangle; 3]) -> Result<(), StateError> {
+    let expected = cartesian_children(parent).map_err(|_| StateError::MaxDepthExceeded(parent.clone()))?;
+    match expected.iter().zip(children.iter()).position(|(e, c)| e != c) {
+        Some(child) => Err(StateError::SubdivisionMismatch { parent: parent.clone(), child }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::block::Block;
+    use crate::core::genesis::{genesis_keypair, test_genesis_owner, GenesisTriangle};
+    use crate::core::hash::H256;
+    use crate::core::errors::DefiError;
+    use crate::core::insurance::InsuranceManager;
+    use crate::core::staking::StakingManager;
+    use crate::core::swaps::{hash_secret, SwapManager};
+    use ed25519_dalek::Keypair;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn block_of(transactions: Vec<Transaction>) -> Block {
+        Block::new(H256::default(), H256::default(), 0, 1, Decimal::ZERO, 0, transactions)
+    }
+
+    fn block_at(height: u64, transactions: Vec<Transaction>) -> Block {
+        Block::new(H256::default(), H256::default(), 0, 1, Decimal::ZERO, height, transactions)
+    }
+
+    fn defi(keypair: &Keypair, operation: DefiOperation, nonce: u64) -> Transaction {
+        Transaction::new(TriangleOperation::Defi(operation), nonce, keypair)
+    }
+
+    fn balance(state: &WorldState, account: &Address) -> Decimal {
+        state.balances().balance(account)
+    }
+
+    fn subdivide(keypair: &Keypair, parent: TriangleAddress, nonce: u64) -> Transaction {
+        let children = cartesian_children(&parent).unwrap();
+        Transaction::new(TriangleOperation::Subdivide { parent, children }, nonce, keypair)
+    }
+
+    /// The ledger rule the single-block `transactions` break.
+    fn rejection(state: &mut WorldState, transactions: Vec<Transaction>) -> StateError {
+        match state.apply_block(&block_of(transactions)) {
+            Err(ValidationError::Transaction { error, .. }) => error,
+            other => panic!("expected a ledger violation, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn genesis_state() -> WorldState {
+        let mut state = WorldState::with_genesis_owner(test_genesis_owner().public);
+        let genesis = block_of(vec![Transaction::new_genesis(GenesisTriangle::new())]);
+        state.apply_block(&genesis).unwrap();
+        state
+    }
+
+    #[test]
+    fn test_public_genesis_key_cannot_spend_root() {
+        let mut state = WorldState::new();
+        let genesis = block_of(vec![Transaction::new_genesis(GenesisTriangle::new())]);
+        state.apply_block(&genesis).unwrap();
+        let root = TriangleAddress::root();
+        assert_eq!(state.owner_of(&root), Some(&genesis_owner()));
+
+        let signer = genesis_keypair();
+        let thief = Keypair::generate(&mut rand::thread_rng());
+        let transfer = Transaction::new(TriangleOperation::Transfer { from: root.clone(), to: thief.public }, 0, &signer);
+        assert_eq!(rejection(&mut state, vec![transfer]), StateError::NotOwner(root.clone()));
+        assert_eq!(rejection(&mut state, vec![subdivide(&signer, root.clone(), 0)]), StateError::NotOwner(root));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot own the genesis triangle")]
+    fn test_public_genesis_key_cannot_be_genesis_owner() {
+        WorldState::with_genesis_owner(genesis_keypair().public);
+    }
+
+    #[test]
+    fn test_subdivide_and_transfer_by_owner() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+
+        let subdivide_tx = subdivide(&owner, root.clone(), 0);
+        let transfer_tx = Transaction::new(
+            TriangleOperation::Transfer { from: root.append(1), to: other.public },
+            1,
+            &owner,
+        );
+        state.apply_block(&block_of(vec![subdivide_tx, transfer_tx])).unwrap();
+
+        assert_eq!(state.get(&root).unwrap().state, TriangleState::Subdivided);
+        assert_eq!(state.owner_of(&root.append(0)), Some(&owner.public));
+        assert_eq!(state.owner_of(&root.append(1)), Some(&other.public));
+        assert_eq!(state.triangles_owned_by(&other.public).len(), 1);
+    }
+
+    #[test]
+    fn test_subdivide_records_void_that_cannot_be_spent() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let root = TriangleAddress::root();
+        state.apply_block(&block_of(vec![subdivide(&owner, root.clone(), 0)])).unwrap();
+
+        let void = root.void().unwrap();
+        let record = state.get(&void).unwrap();
+        assert_eq!(record.state, TriangleState::Void);
+        assert_eq!(record.triangle, cartesian_void(&root).unwrap());
+        assert_eq!(state.active_leaves().count(), 3);
+
+        let transfer = Transaction::new(TriangleOperation::Transfer { from: void.clone(), to: owner.public }, 1, &owner);
+        assert_eq!(rejection(&mut state, vec![transfer]), StateError::Void(void));
+    }
+
+    fn merge(keypair: &Keypair, parent: TriangleAddress, nonce: u64) -> Transaction {
+        Transaction::new(TriangleOperation::Merge { parent }, nonce, keypair)
+    }
+
+    #[test]
+    fn test_merge_restores_parent_and_undo_restores_children() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let buyer = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+        state.apply_block(&block_of(vec![subdivide(&owner, root.clone(), 0)])).unwrap();
+        let transfers = (0..3)
+            .map(|i| {
+                let operation = TriangleOperation::Transfer { from: root.append(i), to: buyer.public };
+                Transaction::new(operation, 1 + i as u64, &owner)
+            })
+            .collect();
+        state.apply_block(&block_of(transfers)).unwrap();
+
+        let undo = state.apply_block(&block_of(vec![merge(&buyer, root.clone(), 0)])).unwrap();
+        let record = state.get(&root).unwrap();
+        assert_eq!(record.state, TriangleState::Active);
+        assert_eq!(record.owner, buyer.public);
+        assert_eq!(state.iter().count(), 1);
+        assert_eq!(state.active_leaves().collect::<Vec<_>>(), vec![&root]);
+
+        state.undo_block(undo);
+        assert_eq!(state.get(&root).unwrap().state, TriangleState::Subdivided);
+        assert_eq!(state.get(&root.void().unwrap()).unwrap().state, TriangleState::Void);
+        assert_eq!(state.active_leaves().count(), 3);
+    }
+
+    #[test]
+    fn test_reject_merge_of_unavailable_children() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+        assert_eq!(
+            rejection(&mut state, vec![merge(&owner, root.clone(), 0)]),
+            StateError::NotSubdivided(root.clone())
+        );
+
+        state.apply_block(&block_of(vec![subdivide(&owner, root.clone(), 0)])).unwrap();
+        assert_eq!(
+            rejection(&mut state, vec![merge(&other, root.clone(), 0)]),
+            StateError::NotOwner(root.append(0))
+        );
+
+        state.apply_block(&block_of(vec![subdivide(&owner, root.append(1), 1)])).unwrap();
+        assert_eq!(
+            rejection(&mut state, vec![merge(&owner, root.clone(), 2)]),
+            StateError::NotActive(root.append(1))
+        );
+        assert_eq!(state.get(&root).unwrap().state, TriangleState::Subdivided);
+    }
+
+    #[test]
+    fn test_reject_non_owner() {
+        let mut state = genesis_state();
+        let thief = Keypair::generate(&mut rand::thread_rng());
+        let tx = subdivide(&thief, TriangleAddress::root(), 0);
+        assert_eq!(
+            rejection(&mut state, vec![tx]),
+            StateError::NotOwner(TriangleAddress::root())
+        );
+    }
+
+    #[test]
+    fn test_reject_spending_subdivided_triangle() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let tx = subdivide(&owner, TriangleAddress::root(), 0);
+        state.apply_block(&block_of(vec![tx])).unwrap();
+
+        let transfer = Transaction::new(
+            TriangleOperation::Transfer { from: TriangleAddress::root(), to: owner.public },
+            1,
+            &owner,
+        );
+        assert_eq!(
+            rejection(&mut state, vec![transfer]),
+            StateError::NotActive(TriangleAddress::root())
+        );
+    }
+
+    #[test]
+    fn test_reject_children_that_are_not_the_exact_subdivision() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let root = TriangleAddress::root();
+        let [c1, c2, c3] = cartesian_children(&root).unwrap();
+
+        let mut forged = c2.clone();
+        forged.b.y += rust_decimal_macros::dec!(1);
+        let tx = Transaction::new(
+            TriangleOperation::Subdivide { parent: root.clone(), children: [c1, forged, c3] },
+            0,
+            &owner,
+        );
+        assert_eq!(
+            rejection(&mut state, vec![tx]),
+            StateError::SubdivisionMismatch { parent: root.clone(), child: 1 }
+        );
+        assert_eq!(state.get(&root).unwrap().state, TriangleState::Genesis);
+    }
+
+    #[test]
+    fn test_reject_double_spend_and_leave_state_untouched() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+
+        let first = Transaction::new(TriangleOperation::Transfer { from: root.clone(), to: other.public }, 0, &owner);
+        let second = Transaction::new(TriangleOperation::Transfer { from: root.clone(), to: owner.public }, 1, &owner);
+        assert_eq!(
+            state.apply_block(&block_of(vec![first, second])).unwrap_err(),
+            ValidationError::Transaction { index: 1, error: StateError::DoubleSpend(root.clone()) }
+        );
+        assert_eq!(state.owner_of(&root), Some(&owner.public));
+    }
+
+    #[test]
+    fn test_undo_block_restores_previous_state() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let root = TriangleAddress::root();
+        let tx = subdivide(&owner, root.clone(), 0);
+
+        let undo = state.apply_block(&block_of(vec![tx])).unwrap();
+        assert_eq!(state.nonce(&owner.public), 1);
+        state.undo_block(undo);
+
+        assert_eq!(state.get(&root).unwrap().state, TriangleState::Genesis);
+        assert!(state.get(&root.append(0)).is_none());
+        assert_eq!(state.nonce(&owner.public), 0);
+    }
+
+    #[test]
+    fn test_reject_replayed_transaction() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let other = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+
+        let give = Transaction::new(TriangleOperation::Transfer { from: root.clone(), to: other.public }, 0, &owner);
+        let give_back = Transaction::new(TriangleOperation::Transfer { from: root.clone(), to: owner.public }, 0, &other);
+        state.apply_block(&block_of(vec![give.clone()])).unwrap();
+        state.apply_block(&block_of(vec![give_back])).unwrap();
+
+        // The owner holds the triangle again, but the old transfer is spent.
+        assert_eq!(rejection(&mut state, vec![give]), StateError::BadNonce { expected: 1, found: 0 });
+        assert_eq!(state.owner_of(&root), Some(&owner.public));
+    }
+
+    #[test]
+    fn test_staking_rewards_and_unstake_move_balances() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let backer = Keypair::generate(&mut rand::thread_rng());
+        let staker = Address::from_pubkey(&owner.public);
+        let pool = StakingManager::account();
+        let root = TriangleAddress::root();
+        state
+            .apply_block(&block_of(vec![
+                Transaction::new_coinbase(dec!(100), 1, &owner),
+                Transaction::new_coinbase(dec!(50), 1, &backer),
+            ]))
+            .unwrap();
+
+        state.apply_block(&block_of(vec![defi(&owner, DefiOperation::Stake { triangle: root.clone(), amount: dec!(40) }, 0)])).unwrap();
+        assert_eq!(balance(&state, &staker), dec!(60));
+        assert_eq!(balance(&state, &pool), dec!(40));
+
+        let reward = defi(&backer, DefiOperation::RewardStakers { triangle: root.clone(), amount: dec!(10) }, 0);
+        state.apply_block(&block_of(vec![reward])).unwrap();
+        assert_eq!(balance(&state, &staker), dec!(70));
+        assert_eq!(balance(&state, &Address::from_pubkey(&backer.public)), dec!(40));
+        assert_eq!(balance(&state, &pool), dec!(40));
+
+        let undo = state.apply_block(&block_of(vec![defi(&owner, DefiOperation::Unstake { triangle: root.clone() }, 1)])).unwrap();
+        assert_eq!(balance(&state, &staker), dec!(110));
+        assert_eq!(balance(&state, &pool), Decimal::ZERO);
+        assert!(!state.defi().staking.is_staked(&state.get(&root).unwrap().triangle.hash()));
+
+        state.undo_block(undo);
+        assert_eq!(balance(&state, &staker), dec!(70));
+        assert_eq!(balance(&state, &pool), dec!(40));
+        assert!(state.defi().staking.is_staked(&state.get(&root).unwrap().triangle.hash()));
+    }
+
+    #[test]
+    fn test_insurance_claim_pays_policyholder_from_pool() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let holder = Address::from_pubkey(&owner.public);
+        let pool = InsuranceManager::account();
+        let root = TriangleAddress::root();
+        state.apply_block(&block_of(vec![Transaction::new_coinbase(dec!(100), 1, &owner)])).unwrap();
+
+        // The pool cannot promise more than it holds.
+        let greedy = DefiOperation::Insure { triangle: root.clone(), insured_value: dec!(30), premium: dec!(25), expiration_block: 10 };
+        assert_eq!(
+            rejection(&mut state, vec![defi(&owner, greedy, 0)]),
+            DefiError::InsufficientCover { needed: dec!(30), available: dec!(25) }.into()
+        );
+
+        let insure = DefiOperation::Insure { triangle: root.clone(), insured_value: dec!(20), premium: dec!(25), expiration_block: 10 };
+        state.apply_block(&block_at(2, vec![defi(&owner, insure, 0)])).unwrap();
+        assert_eq!(balance(&state, &holder), dec!(75));
+        assert_eq!(balance(&state, &pool), dec!(25));
+
+        // Only a subdivided triangle has a loss to claim.
+        let claim = defi(&owner, DefiOperation::Claim { triangle: root.clone() }, 1);
+        assert_eq!(rejection(&mut state, vec![claim.clone()]), StateError::NotSubdivided(root.clone()));
+
+        state.apply_block(&block_at(3, vec![subdivide(&owner, root.clone(), 1)])).unwrap();
+        let claim = defi(&owner, DefiOperation::Claim { triangle: root.clone() }, 2);
+        let undo = state.apply_block(&block_at(4, vec![claim.clone()])).unwrap();
+        assert_eq!(balance(&state, &holder), dec!(95));
+        assert_eq!(balance(&state, &pool), dec!(5));
+        assert_eq!(
+            rejection(&mut state, vec![defi(&owner, DefiOperation::Claim { triangle: root.clone() }, 3)]),
+            DefiError::NoPolicy(root.clone()).into()
+        );
+
+        state.undo_block(undo);
+        assert_eq!(balance(&state, &holder), dec!(75));
+        assert_eq!(balance(&state, &pool), dec!(25));
+        state.apply_block(&block_at(4, vec![claim])).unwrap();
+        assert_eq!(balance(&state, &pool), dec!(5));
+    }
+
+    #[test]
+    fn test_swap_redeems_to_participant_or_refunds_after_timeout() {
+        let mut state = genesis_state();
+        let initiator = test_genesis_owner();
+        let participant = Keypair::generate(&mut rand::thread_rng());
+        let locked = SwapManager::account();
+        state.apply_block(&block_of(vec![Transaction::new_coinbase(dec!(10), 1, &initiator)])).unwrap();
+
+        let secret = H256::from(blake3::hash(b"secret"));
+        let initiate = |secret_hash, nonce| {
+            let operation = DefiOperation::InitiateSwap {
+                participant: Address::from_pubkey(&participant.public),
+                amount: dec!(4),
+                secret_hash,
+                timeout: 5,
+            };
+            defi(&initiator, operation, nonce)
+        };
+        state.apply_block(&block_at(1, vec![initiate(hash_secret(&secret), 0)])).unwrap();
+        assert_eq!(balance(&state, &locked), dec!(4));
+
+        // Only the participant can redeem, and only before the timeout.
+        let redeem = DefiOperation::RedeemSwap { secret };
+        assert_eq!(
+            rejection(&mut state, vec![defi(&initiator, redeem.clone(), 1)]),
+            DefiError::NotParty("swap").into()
+        );
+        assert!(matches!(
+            state.apply_block(&block_at(6, vec![defi(&participant, redeem.clone(), 0)])),
+            Err(ValidationError::Transaction { error: StateError::Defi(DefiError::Expired(5)), .. })
+        ));
+        state.apply_block(&block_at(5, vec![defi(&participant, redeem, 0)])).unwrap();
+        assert_eq!(balance(&state, &Address::from_pubkey(&participant.public)), dec!(4));
+        assert_eq!(balance(&state, &locked), Decimal::ZERO);
+
+        let other_hash = hash_secret(&H256::from(blake3::hash(b"other")));
+        state.apply_block(&block_at(5, vec![initiate(other_hash, 1)])).unwrap();
+        let refund = defi(&initiator, DefiOperation::RefundSwap { secret_hash: other_hash }, 2);
+        assert!(matches!(
+            state.apply_block(&block_at(5, vec![refund.clone()])),
+            Err(ValidationError::Transaction { error: StateError::Defi(DefiError::SwapRunning { .. }), .. })
+        ));
+        state.apply_block(&block_at(6, vec![refund])).unwrap();
+        assert_eq!(balance(&state, &Address::from_pubkey(&initiator.public)), dec!(6));
+        assert_eq!(balance(&state, &locked), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_reject_token_transfer_into_module_account() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        state.apply_block(&block_of(vec![Transaction::new_coinbase(dec!(10), 1, &owner)])).unwrap();
+
+        let pool = StakingManager::account();
+        let transfer = TriangleOperation::TokenTransfer { to: pool, amount: dec!(1) };
+        let tx = Transaction::new(transfer, 0, &owner);
+        assert_eq!(state.check_transaction(&tx, 1), Err(StateError::ModuleAccount(pool)));
+        assert_eq!(rejection(&mut state, vec![tx]), StateError::ModuleAccount(pool));
+    }
+
+    #[test]
+    fn test_reject_merge_of_staked_child() {
+        let mut state = genesis_state();
+        let owner = test_genesis_owner();
+        let buyer = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+        state.apply_block(&block_of(vec![Transaction::new_coinbase(dec!(10), 1, &owner), subdivide(&owner, root.clone(), 0)])).unwrap();
+
+        let staked = root.append(2);
+        let stake = defi(&owner, DefiOperation::Stake { triangle: staked.clone(), amount: dec!(5) }, 1);
+        let undo = state.apply_block(&block_of(vec![stake.clone()])).unwrap();
+        let encumbered = || StateError::Encumbered { triangle: staked.clone(), encumbrance: Encumbrance::Staked };
+        assert_eq!(state.encumbrance(&staked, 1), Some(Encumbrance::Staked));
+        let merge_tx = merge(&owner, root.clone(), 2);
+        assert_eq!(state.check_transaction(&merge_tx, 1), Err(encumbered()));
+        assert_eq!(rejection(&mut state, vec![merge_tx]), encumbered());
+        let transfer = Transaction::new(TriangleOperation::Transfer { from: staked.clone(), to: buyer.public }, 2, &owner);
+        assert_eq!(rejection(&mut state, vec![transfer]), encumbered());
+        assert_eq!(state.get(&root).unwrap().state, TriangleState::Subdivided);
+
+        // Undoing the stake lifts the encumbrance with it.
+        state.undo_block(undo);
+        assert_eq!(state.encumbrance(&staked, 1), None);
+        state.apply_block(&block_of(vec![stake])).unwrap();
+
+        let unstake = defi(&owner, DefiOperation::Unstake { triangle: staked.clone() }, 2);
+        state.apply_block(&block_of(vec![unstake, merge(&owner, root.clone(), 3)])).unwrap();
+        assert_eq!(state.get(&root).unwrap().state, TriangleState::Active);
+        assert_eq!(state.encumbrance(&staked, 1), None);
+    }
+}
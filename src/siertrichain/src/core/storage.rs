@@ -1,21 +1,41 @@
-use crate::core::block::Block;
+use crate::core::block::{Block, BlockHeader};
+use crate::core::errors::StorageError;
 use crate::core::hash::H256;
 use crate::core::transaction::Transaction;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+/// Stores every known block (on any branch) plus the height index and tip of
+/// the active chain.
 pub trait BlockStorage {
-    fn get_block(&self, hash: &H256) -> Option<Block>;
-    fn put_block(&mut self, block: &Block);
+    fn get_block(&self, hash: &H256) -> Result<Option<Block>, StorageError>;
+    fn put_block(&mut self, block: &Block) -> Result<(), StorageError>;
+    /// Forgets a block that turned out to be invalid, together with its
+    /// links to the transactions it included.
+    fn remove_block(&mut self, hash: &H256) -> Result<(), StorageError>;
+    fn get_header(&self, hash: &H256) -> Result<Option<BlockHeader>, StorageError>;
+    /// Returns every stored header in ascending height order.
+    fn load_headers(&self) -> Result<Vec<BlockHeader>, StorageError>;
+    fn get_hash_at_height(&self, height: u64) -> Result<Option<H256>, StorageError>;
+    fn put_hash_at_height(&mut self, height: u64, hash: &H256) -> Result<(), StorageError>;
+    fn remove_hash_at_height(&mut self, height: u64) -> Result<(), StorageError>;
+    fn get_tip(&self) -> Result<Option<H256>, StorageError>;
+    fn put_tip(&mut self, hash: &H256) -> Result<(), StorageError>;
 }
 
 pub trait TransactionStorage {
-    fn get_transaction(&self, hash: &H256) -> Option<Transaction>;
-    fn put_transaction(&mut self, tx: &Transaction);
+    fn get_transaction(&self, hash: &H256) -> Result<Option<Transaction>, StorageError>;
+    fn put_transaction(&mut self, tx: &Transaction) -> Result<(), StorageError>;
+    /// Returns the hashes of every stored block, on any branch, that
+    /// includes the transaction.
+    fn get_transaction_blocks(&self, hash: &H256) -> Result<Vec<H256>, StorageError>;
 }
 
 pub struct InMemoryStorage {
     blocks: HashMap<H256, Block>,
     transactions: HashMap<H256, Transaction>,
+    transaction_blocks: HashMap<H256, Vec<H256>>,
+    heights: BTreeMap<u64, H256>,
+    tip: Option<H256>,
 }
 
 impl InMemoryStorage {
@@ -23,26 +43,92 @@ impl InMemoryStorage {
         Self {
             blocks: HashMap::new(),
             transactions: HashMap::new(),
+            transaction_blocks: HashMap::new(),
+            heights: BTreeMap::new(),
+            tip: None,
         }
     }
 }
 
 impl BlockStorage for InMemoryStorage {
-    fn get_block(&self, hash: &H256) -> Option<Block> {
-        self.blocks.get(hash).cloned()
+    fn get_block(&self, hash: &H256) -> Result<Option<Block>, StorageError> {
+        Ok(self.blocks.get(hash).cloned())
     }
 
-    fn put_block(&mut self, block: &Block) {
-        self.blocks.insert(block.hash(), block.clone());
+    fn put_block(&mut self, block: &Block) -> Result<(), StorageError> {
+        let block_hash = block.hash();
+        for tx in &block.triangle_transactions {
+            self.transactions.insert(*tx.hash(), tx.clone());
+            let blocks = self.transaction_blocks.entry(*tx.hash()).or_default();
+            if !blocks.contains(&block_hash) {
+                blocks.push(block_hash);
+            }
+        }
+        self.blocks.insert(block_hash, block.clone());
+        Ok(())
+    }
+
+    fn remove_block(&mut self, hash: &H256) -> Result<(), StorageError> {
+        let Some(block) = self.blocks.remove(hash) else {
+            return Ok(());
+        };
+        for tx in &block.triangle_transactions {
+            if let Some(blocks) = self.transaction_blocks.get_mut(tx.hash()) {
+                blocks.retain(|block_hash| block_hash != hash);
+                if blocks.is_empty() {
+                    self.transaction_blocks.remove(tx.hash());
+                    self.transactions.remove(tx.hash());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_header(&self, hash: &H256) -> Result<Option<BlockHeader>, StorageError> {
+        Ok(self.blocks.get(hash).map(|block| block.header.clone()))
+    }
+
+    fn load_headers(&self) -> Result<Vec<BlockHeader>, StorageError> {
+        let mut headers: Vec<BlockHeader> = self.blocks.values().map(|block| block.header.clone()).collect();
+        headers.sort_by_key(|header| header.height);
+        Ok(headers)
+    }
+
+    fn get_hash_at_height(&self, height: u64) -> Result<Option<H256>, StorageError> {
+        Ok(self.heights.get(&height).copied())
+    }
+
+    fn put_hash_at_height(&mut self, height: u64, hash: &H256) -> Result<(), StorageError> {
+        self.heights.insert(height, *hash);
+        Ok(())
+    }
+
+    fn remove_hash_at_height(&mut self, height: u64) -> Result<(), StorageError> {
+        self.heights.remove(&height);
+        Ok(())
+    }
+
+    fn get_tip(&self) -> Result<Option<H256>, StorageError> {
+        Ok(self.tip)
+    }
+
+    fn put_tip(&mut self, hash: &H256) -> Result<(), StorageError> {
+        self.tip = Some(*hash);
+        Ok(())
     }
 }
 
 impl TransactionStorage for InMemoryStorage {
-    fn get_transaction(&self, hash: &H256) -> Option<Transaction> {
-        self.transactions.get(hash).cloned()
+    fn get_transaction(&self, hash: &H256) -> Result<Option<Transaction>, StorageError> {
+        Ok(self.transactions.get(hash).cloned())
+    }
+
+    fn put_transaction(&mut self, tx: &Transaction) -> Result<(), StorageError> {
+        self.transactions.insert(*tx.hash(), tx.clone());
+        Ok(())
     }
 
-    fn put_transaction(&mut self, tx: &Transaction) {
-        self.transactions.insert(tx.hash().clone(), tx.clone());
+    fn get_transaction_blocks(&self, hash: &H256) -> Result<Vec<H256>, StorageError> {
+        Ok(self.transaction_blocks.get(hash).cloned().unwrap_or_default())
     }
 }
@@ -1,71 +1,82 @@
-use crate::core::triangle::Triangle;
 use crate::wallet::address::Address;
 use crate::core::hash::H256;
 use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 /// Represents the state of an atomic swap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SwapState {
     Initiated,  // The swap has been initiated but not yet confirmed
-    Confirmed,  // The swap has been confirmed by the second party
+    Confirmed,  // The participant has redeemed the swap with the secret
     Refunded,   // The swap has been refunded to the initiator
 }
 
-/// Represents an atomic swap agreement.
+/// Represents an atomic swap agreement: tokens locked by the initiator that
+/// the participant can take by revealing the secret before the timeout.
+#[derive(Debug, Clone)]
 pub struct AtomicSwap {
     pub initiator: Address,             // The address of the swap initiator
     pub participant: Address,           // The address of the swap participant
-    pub from_triangle: Triangle,        // The triangle the initiator is swapping from
-    pub to_triangle: Triangle,          // The triangle the initiator is swapping to
-    pub from_amount: Decimal,           // The amount the initiator is swapping
-    pub to_amount: Decimal,             // The amount the participant is swapping
-    pub secret_hash: H256,          // A hash of a secret, used to lock the swap
+    pub amount: Decimal,                // The amount the initiator locked
+    pub secret_hash: H256,              // A hash of a secret, used to lock the swap
+    pub timeout: u64,                   // The last block in which the participant can redeem
     pub state: SwapState,               // The current state of the swap
 }
 
-/// Manages atomic swaps between different triangular regions.
+/// Manages atomic swaps. The locked tokens are held by
+/// `SwapManager::account()`.
+#[derive(Debug, Clone, Default)]
 pub struct SwapManager {
+    /// Swaps by the hash of their secret. Finished swaps are kept, so a hash
+    /// cannot be used twice.
     swaps: HashMap<H256, AtomicSwap>,
 }
 
+/// The hash a swap is locked with.
+pub fn hash_secret(secret: &H256) -> H256 {
+    blake3::hash(&secret.to_bytes()).into()
+}
+
 impl SwapManager {
     /// Creates a new `SwapManager`
     pub fn new() -> Self {
         Self { swaps: HashMap::new() }
     }
 
+    /// The account holding the tokens of open swaps.
+    pub fn account() -> Address {
+        Address::for_module("swaps")
+    }
+
     /// Initiates a new atomic swap.
-    pub fn initiate_swap(&mut self, initiator: Address, participant: Address, from_triangle: Triangle, to_triangle: Triangle, from_amount: Decimal, to_amount: Decimal, secret_hash: H256) {
+    pub fn initiate_swap(&mut self, initiator: Address, participant: Address, amount: Decimal, secret_hash: H256, timeout: u64) {
         let swap = AtomicSwap {
             initiator,
             participant,
-            from_triangle,
-            to_triangle,
-            from_amount,
-            to_amount,
-            secret_hash: secret_hash.clone(),
+            amount,
+            secret_hash,
+            timeout,
             state: SwapState::Initiated,
         };
         self.swaps.insert(secret_hash, swap);
     }
 
-    /// Confirms a swap with the secret.
-    pub fn confirm_swap(&mut self, secret_hash: &H256, _secret: &[u8]) {
-        // In a real implementation, we would hash the provided secret
-        // and check if it matches the secret_hash.
+    /// The swap locked with `secret_hash`, open or finished.
+    pub fn swap(&self, secret_hash: &H256) -> Option<&AtomicSwap> {
+        self.swaps.get(secret_hash)
+    }
+
+    /// Confirms a swap whose secret was revealed.
+    pub fn confirm_swap(&mut self, secret_hash: &H256) {
         if let Some(swap) = self.swaps.get_mut(secret_hash) {
-            // Simplified logic for demonstration
             swap.state = SwapState::Confirmed;
-            // Perform the asset exchange
         }
     }
 
-    /// Refunds a swap if it has expired.
+    /// Refunds a swap that has timed out.
     pub fn refund_swap(&mut self, secret_hash: &H256) {
         if let Some(swap) = self.swaps.get_mut(secret_hash) {
-            // In a real implementation, we would check if the swap has expired.
             swap.state = SwapState::Refunded;
-            // Return the assets to the initiator
         }
     }
 }
@@ -1,6 +1,6 @@
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, MathematicalOps};
 use rust_decimal_macros::dec;
-use crate::core::triangle::Triangle;
+use crate::core::block::Block;
 
 /// The fractal dimension of the Sierpinski triangle (log(3)/log(2)).
 pub const SIERPINSKI_FRACTAL_DIMENSION: f64 = 1.584962500721156;
@@ -15,13 +15,11 @@ pub fn calculate_subdivision_reward(triangle_area: Decimal) -> Decimal {
     triangle_area * reward_factor
 }
 
-/// Calculates the portion of fees to be burned from a transaction.
-/// Burning a portion of fees introduces deflationary pressure on the token supply.
-pub fn calculate_fee_burn(fee: Decimal) -> Decimal {
-    // The burn rate is a fixed percentage of the transaction fee.
-    // This can be adjusted as a core consensus rule.
-    let burn_rate = dec!(0.1); // 10% of the fee is burned
-    fee * burn_rate
+/// Calculates the portion of a transaction fee that is burned.
+/// Burning the base fee of every transaction introduces deflationary pressure
+/// on the token supply; only the tip above it is paid to the miner.
+pub fn calculate_fee_burn(fee: Decimal, base_fee: Decimal) -> Decimal {
+    fee.min(base_fee)
 }
 
 /// Calculates the mining reward based on the subdivision depth.
@@ -31,3 +29,91 @@ pub fn calculate_mining_reward(depth: u32) -> Decimal {
     let depth_factor = dec!(2.0).powu(depth as u64);
     base_reward / depth_factor
 }
+
+/// How the reward of a single block is made up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockReward {
+    /// Newly issued tokens for mining at the depth of the geometric proof.
+    pub subsidy: Decimal,
+    /// Fees paid by the transactions in the block.
+    pub fees: Decimal,
+    /// The part of the fees that is burned instead of paid to the miner.
+    pub burned: Decimal,
+}
+
+impl BlockReward {
+    /// The reward for a proof at `depth` in a block with base fee
+    /// `base_fee` whose transactions pay `fees`.
+    pub fn new(depth: u32, base_fee: Decimal, fees: &[Decimal]) -> Self {
+        Self {
+            subsidy: calculate_mining_reward(depth),
+            fees: fees.iter().sum(),
+            burned: fees.iter().map(|fee| calculate_fee_burn(*fee, base_fee)).sum(),
+        }
+    }
+
+    /// The reward owed to the miner of `block`.
+    pub fn for_block(block: &Block) -> Self {
+        let fees: Vec<Decimal> = block
+            .triangle_transactions
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .map(|tx| tx.fee)
+            .collect();
+        Self::new(block.header.geometric_proof.depth(), block.header.base_fee, &fees)
+    }
+
+    /// The exact amount the block's coinbase transaction must pay.
+    pub fn coinbase_amount(&self) -> Decimal {
+        self.subsidy + self.fees - self.burned
+    }
+}
+
+/// Running totals of the token supply along the active chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Supply {
+    pub issued: Decimal,
+    pub burned: Decimal,
+}
+
+impl Supply {
+    pub fn connect(&mut self, reward: &BlockReward) {
+        self.issued += reward.subsidy;
+        self.burned += reward.burned;
+    }
+
+    pub fn disconnect(&mut self, reward: &BlockReward) {
+        self.issued -= reward.subsidy;
+        self.burned -= reward.burned;
+    }
+
+    /// Tokens issued and not burned.
+    pub fn circulating(&self) -> Decimal {
+        self.issued - self.burned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coinbase_amount_keeps_tips() {
+        let reward = BlockReward::new(2, dec!(1), &[dec!(4), dec!(6)]);
+        assert_eq!(reward.subsidy, dec!(25));
+        assert_eq!(reward.fees, dec!(10));
+        assert_eq!(reward.burned, dec!(2));
+        assert_eq!(reward.coinbase_amount(), dec!(33));
+    }
+
+    #[test]
+    fn test_supply_connect_and_disconnect() {
+        let reward = BlockReward::new(1, dec!(1), &[dec!(10)]);
+        let mut supply = Supply::default();
+        supply.connect(&reward);
+        assert_eq!(supply.issued, dec!(50));
+        assert_eq!(supply.circulating(), dec!(49));
+        supply.disconnect(&reward);
+        assert_eq!(supply, Supply::default());
+    }
+}
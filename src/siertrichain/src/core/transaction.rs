@@ -1,9 +1,16 @@
 use serde::{Deserialize, Serialize};
 use crate::core::triangle::Triangle;
 use crate::core::address::TriangleAddress;
+use crate::core::barycentric::{cartesian_void, ExactTriangle};
+use crate::core::defi::DefiOperation;
+use crate::core::encoding::{Decode, Encode, Reader};
+use crate::core::errors::{DecodeError, StateError};
 use crate::core::hash::H256;
-use ed25519_dalek::{Signature, Signer, Keypair, PublicKey, Verifier};
+use ed25519_dalek::{Signature, Signer, Keypair, PublicKey};
 use crate::core::fractal::{FractalTriangle, TriangleState};
+use crate::core::genesis::genesis_keypair;
+use crate::wallet::address::Address;
+use rust_decimal::Decimal;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,60 +24,176 @@ pub enum TriangleOperation {
         from: TriangleAddress,
         to: PublicKey,
     },
+    /// Retires the three children of a subdivided `parent`, and its void,
+    /// and makes the parent an active triangle of the signer again.
+    Merge {
+        parent: TriangleAddress,
+    },
+    /// Pays the block reward to the miner. Must be the first transaction of
+    /// every block after genesis.
+    Coinbase {
+        recipient: PublicKey,
+        amount: Decimal,
+        height: u64,
+    },
+    /// Moves native tokens from the signer's account to `to`.
+    TokenTransfer {
+        to: Address,
+        amount: Decimal,
+    },
+    /// Staking, insurance, lending, rental, exchange, liquidity and swap
+    /// actions, see `core::defi`.
+    Defi(DefiOperation),
 }
 
+/// Identifies the network a transaction is meant for. Part of every signing
+/// payload, so a signature made for one network is invalid on all others.
+pub const CHAIN_ID: u32 = 0x5349_4552; // "SIER"
+
+/// Domain separator prepended to every signing payload.
+const SIGNING_DOMAIN: &[u8] = b"siertrichain transaction v1";
+
+/// Version of the transaction encoding, see `core::encoding`.
+pub const TRANSACTION_VERSION: u32 = 2;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
+    pub version: u32,
     pub operation: TriangleOperation,
+    pub chain_id: u32,
+    /// Position of this transaction among those signed by `public_key`.
+    /// Consensus accepts each nonce once and in order, so a transaction
+    /// cannot be replayed.
+    pub nonce: u64,
+    /// Last block height at which the transaction may be included.
+    pub expiry: Option<u64>,
+    /// Paid in the native token for inclusion. The part up to the block's
+    /// base fee is burned, the rest is the miner's tip.
+    pub fee: Decimal,
     pub signature: Signature,
     pub public_key: PublicKey,
     hash: H256, // Cached hash
 }
 
 impl Transaction {
-    pub fn new(operation: TriangleOperation, keypair: &Keypair) -> Self {
-        let public_key = keypair.public;
-        let operation_bytes = bincode::serialize(&operation).unwrap();
-        let signature = keypair.sign(&operation_bytes);
-        let hash = blake3::hash(&operation_bytes).into();
+    /// Signs `operation` for this network as the signer's transaction number
+    /// `nonce`, without an expiry or a fee.
+    pub fn new(operation: TriangleOperation, nonce: u64, keypair: &Keypair) -> Self {
+        Self::signed(CHAIN_ID, operation, nonce, None, Decimal::ZERO, keypair)
+    }
+
+    /// Like `new`, offering `fee` for inclusion.
+    pub fn with_fee(operation: TriangleOperation, nonce: u64, fee: Decimal, keypair: &Keypair) -> Self {
+        Self::signed(CHAIN_ID, operation, nonce, None, fee, keypair)
+    }
+
+    pub fn signed(
+        chain_id: u32,
+        operation: TriangleOperation,
+        nonce: u64,
+        expiry: Option<u64>,
+        fee: Decimal,
+        keypair: &Keypair,
+    ) -> Self {
+        let payload = signing_payload(TRANSACTION_VERSION, chain_id, nonce, expiry, fee, &operation);
+        let signature = keypair.sign(&payload);
+        let hash = transaction_hash(&payload, &keypair.public, &signature);
         Self {
+            version: TRANSACTION_VERSION,
             operation,
+            chain_id,
+            nonce,
+            expiry,
+            fee,
             signature,
-            public_key,
+            public_key: keypair.public,
             hash,
         }
     }
 
     pub fn new_genesis(triangle: Triangle) -> Self {
-        // Create a dummy keypair for the genesis transaction
-        let mut csprng = rand::thread_rng();
-        let keypair = Keypair::generate(&mut csprng);
-        Self::new(TriangleOperation::Create(triangle), &keypair)
+        Self::new(TriangleOperation::Create(triangle), 0, &genesis_keypair())
     }
 
+    /// Builds the coinbase for the block at `height`, paying `amount` to the
+    /// signer. Coinbases are unique by height and do not use the nonce.
+    pub fn new_coinbase(amount: Decimal, height: u64, keypair: &Keypair) -> Self {
+        let operation = TriangleOperation::Coinbase { recipient: keypair.public, amount, height };
+        Self::new(operation, 0, keypair)
+    }
+
+    /// Whether the signer's nonce is checked and advanced by this
+    /// transaction. The genesis `Create` and coinbases cannot repeat anyway.
+    pub fn uses_nonce(&self) -> bool {
+        !matches!(self.operation, TriangleOperation::Create(_) | TriangleOperation::Coinbase { .. })
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        matches!(self.operation, TriangleOperation::Coinbase { .. })
+    }
+
+    /// Checks the signature and that the cached hash belongs to this
+    /// transaction. Keys and signature points of small order are rejected,
+    /// as they would let one signature pass for any message.
     pub fn validate(&self) -> bool {
-        let operation_bytes = bincode::serialize(&self.operation).unwrap();
-        self.public_key.verify(&operation_bytes, &self.signature).is_ok()
+        match self.signed_payload() {
+            Some(payload) => self.public_key.verify_strict(&payload, &self.signature).is_ok(),
+            None => false,
+        }
+    }
+
+    /// The bytes the signature must cover, or `None` if the cached hash does
+    /// not belong to this transaction.
+    pub(crate) fn signed_payload(&self) -> Option<Vec<u8>> {
+        let payload = signing_payload(self.version, self.chain_id, self.nonce, self.expiry, self.fee, &self.operation);
+        (transaction_hash(&payload, &self.public_key, &self.signature) == self.hash).then_some(payload)
     }
 
     pub fn hash(&self) -> &H256 {
         &self.hash
     }
 
+    /// The triangles this transaction consumes. Two transactions that
+    /// consume the same triangle cannot both be mined. Fails for a merge of
+    /// a triangle that cannot have children, which no ledger accepts.
+    pub fn spends(&self) -> Result<Vec<TriangleAddress>, StateError> {
+        let spends = match &self.operation {
+            TriangleOperation::Subdivide { parent, .. } => vec![parent.clone()],
+            TriangleOperation::Transfer { from, .. } => vec![from.clone()],
+            TriangleOperation::Merge { parent } => {
+                let mut spends = vec![parent.clone()];
+                for i in 0..3 {
+                    spends.push(parent.child(i).map_err(|_| StateError::MaxDepthExceeded(parent.clone()))?);
+                }
+                spends
+            }
+            TriangleOperation::Create(_) => vec![TriangleAddress::root()],
+            TriangleOperation::Defi(operation) => operation.spends().into_iter().cloned().collect(),
+            TriangleOperation::Coinbase { .. } | TriangleOperation::TokenTransfer { .. } => Vec::new(),
+        };
+        Ok(spends)
+    }
+
+    /// The triangles this transaction brings into existence.
     pub fn get_fractal_triangles(&self) -> Vec<FractalTriangle> {
         let mut triangles = Vec::new();
         match &self.operation {
             TriangleOperation::Create(triangle) => {
-                let address = TriangleAddress::new(Vec::new());
-                triangles.push(FractalTriangle::new(triangle.clone(), TriangleState::Genesis, 0, None, address));
+                triangles.push(FractalTriangle::new(triangle.clone(), TriangleState::Genesis, TriangleAddress::root()));
             }
             TriangleOperation::Subdivide { parent, children } => {
                 for (i, child) in children.iter().enumerate() {
-                    let address = parent.append(i as u8);
-                    // This is not quite right, we need to get the parent from the blockchain
-                    // to get the correct depth and parent_id.
-                    // For now, we will just use a depth of 0 and no parent.
-                    triangles.push(FractalTriangle::new(child.clone(), TriangleState::Active, 0, None, address));
+                    if let Ok(address) = parent.child(i as u8) {
+                        triangles.push(FractalTriangle::new(child.clone(), TriangleState::Active, address));
+                    }
+                }
+                if let (Ok(void), Ok(address)) = (cartesian_void(parent), parent.void()) {
+                    triangles.push(FractalTriangle::new(void, TriangleState::Void, address));
+                }
+            }
+            TriangleOperation::Merge { parent } => {
+                if let Ok(triangle) = ExactTriangle::from_address(parent) {
+                    triangles.push(FractalTriangle::new(triangle.to_cartesian(), TriangleState::Active, parent.clone()));
                 }
             }
             _ => {}
@@ -79,3 +202,257 @@ impl Transaction {
     }
 
 }
+
+/// The signed bytes: a domain separator, then the transaction without its
+/// public key and signature in canonical encoding.
+fn signing_payload(
+    version: u32,
+    chain_id: u32,
+    nonce: u64,
+    expiry: Option<u64>,
+    fee: Decimal,
+    operation: &TriangleOperation,
+) -> Vec<u8> {
+    let mut bytes = SIGNING_DOMAIN.to_vec();
+    version.encode_to(&mut bytes);
+    chain_id.encode_to(&mut bytes);
+    nonce.encode_to(&mut bytes);
+    expiry.encode_to(&mut bytes);
+    fee.encode_to(&mut bytes);
+    operation.encode_to(&mut bytes);
+    bytes
+}
+
+/// The transaction id: commits to the signed payload and to who signed it
+/// and how, so no two distinct transactions share an id.
+fn transaction_hash(payload: &[u8], public_key: &PublicKey, signature: &Signature) -> H256 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(payload);
+    hasher.update(public_key.as_bytes());
+    hasher.update(&signature.to_bytes());
+    hasher.finalize().into()
+}
+
+const TAG_CREATE: u8 = 0;
+const TAG_SUBDIVIDE: u8 = 1;
+const TAG_TRANSFER: u8 = 2;
+const TAG_COINBASE: u8 = 3;
+const TAG_MERGE: u8 = 4;
+const TAG_TOKEN_TRANSFER: u8 = 5;
+const TAG_DEFI: u8 = 6;
+
+impl Encode for TriangleOperation {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            TriangleOperation::Create(triangle) => {
+                out.push(TAG_CREATE);
+                triangle.encode_to(out);
+            }
+            TriangleOperation::Subdivide { parent, children } => {
+                out.push(TAG_SUBDIVIDE);
+                parent.encode_to(out);
+                for child in children {
+                    child.encode_to(out);
+                }
+            }
+            TriangleOperation::Transfer { from, to } => {
+                out.push(TAG_TRANSFER);
+                from.encode_to(out);
+                to.encode_to(out);
+            }
+            TriangleOperation::Coinbase { recipient, amount, height } => {
+                out.push(TAG_COINBASE);
+                recipient.encode_to(out);
+                amount.encode_to(out);
+                height.encode_to(out);
+            }
+            TriangleOperation::Merge { parent } => {
+                out.push(TAG_MERGE);
+                parent.encode_to(out);
+            }
+            TriangleOperation::TokenTransfer { to, amount } => {
+                out.push(TAG_TOKEN_TRANSFER);
+                to.encode_to(out);
+                amount.encode_to(out);
+            }
+            TriangleOperation::Defi(operation) => {
+                out.push(TAG_DEFI);
+                operation.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for TriangleOperation {
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match u8::decode_from(reader)? {
+            TAG_CREATE => Ok(TriangleOperation::Create(Triangle::decode_from(reader)?)),
+            TAG_SUBDIVIDE => Ok(TriangleOperation::Subdivide {
+                parent: TriangleAddress::decode_from(reader)?,
+                children: [Triangle::decode_from(reader)?, Triangle::decode_from(reader)?, Triangle::decode_from(reader)?],
+            }),
+            TAG_TRANSFER => Ok(TriangleOperation::Transfer {
+                from: TriangleAddress::decode_from(reader)?,
+                to: PublicKey::decode_from(reader)?,
+            }),
+            TAG_COINBASE => Ok(TriangleOperation::Coinbase {
+                recipient: PublicKey::decode_from(reader)?,
+                amount: Decimal::decode_from(reader)?,
+                height: u64::decode_from(reader)?,
+            }),
+            TAG_MERGE => Ok(TriangleOperation::Merge { parent: TriangleAddress::decode_from(reader)? }),
+            TAG_TOKEN_TRANSFER => Ok(TriangleOperation::TokenTransfer {
+                to: Address::decode_from(reader)?,
+                amount: Decimal::decode_from(reader)?,
+            }),
+            TAG_DEFI => Ok(TriangleOperation::Defi(DefiOperation::decode_from(reader)?)),
+            tag => Err(DecodeError::UnknownTag { kind: "operation", tag }),
+        }
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.version.encode_to(out);
+        self.chain_id.encode_to(out);
+        self.nonce.encode_to(out);
+        self.expiry.encode_to(out);
+        self.fee.encode_to(out);
+        self.operation.encode_to(out);
+        self.public_key.encode_to(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl Decode for Transaction {
+    /// The hash is recomputed from the decoded fields; the signature is not
+    /// checked here, see `Transaction::validate`.
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        let version = u32::decode_from(reader)?;
+        if version != TRANSACTION_VERSION {
+            return Err(DecodeError::UnsupportedVersion { kind: "transaction", version });
+        }
+        let chain_id = u32::decode_from(reader)?;
+        let nonce = u64::decode_from(reader)?;
+        let expiry = Option::<u64>::decode_from(reader)?;
+        let fee = Decimal::decode_from(reader)?;
+        let operation = TriangleOperation::decode_from(reader)?;
+        let public_key = PublicKey::decode_from(reader)?;
+        let signature = Signature::decode_from(reader)?;
+        let payload = signing_payload(version, chain_id, nonce, expiry, fee, &operation);
+        let hash = transaction_hash(&payload, &public_key, &signature);
+        Ok(Self { version, operation, chain_id, nonce, expiry, fee, signature, public_key, hash })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(keypair: &Keypair) -> TriangleOperation {
+        TriangleOperation::Transfer { from: TriangleAddress::root(), to: keypair.public }
+    }
+
+    #[test]
+    fn test_signature_covers_chain_nonce_expiry_and_fee() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let tx = Transaction::signed(CHAIN_ID, transfer(&keypair), 3, Some(10), Decimal::ONE, &keypair);
+        assert!(tx.validate());
+
+        let mut other_chain = tx.clone();
+        other_chain.chain_id += 1;
+        assert!(!other_chain.validate());
+        let mut replayed = tx.clone();
+        replayed.nonce = 4;
+        assert!(!replayed.validate());
+        let mut extended = tx.clone();
+        extended.expiry = None;
+        assert!(!extended.validate());
+        let mut cheaper = tx;
+        cheaper.fee = Decimal::ZERO;
+        assert!(!cheaper.validate());
+    }
+
+    #[test]
+    fn test_every_operation_round_trips() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let root = TriangleAddress::root();
+        let operations = vec![
+            TriangleOperation::Create(crate::core::genesis::GenesisTriangle::new()),
+            TriangleOperation::Subdivide {
+                parent: root.append(1),
+                children: crate::core::barycentric::cartesian_children(&root.append(1)).unwrap(),
+            },
+            transfer(&keypair),
+            TriangleOperation::Coinbase { recipient: keypair.public, amount: Decimal::new(1234, 2), height: 7 },
+            TriangleOperation::Merge { parent: root.append(2) },
+            TriangleOperation::TokenTransfer { to: Address::from_pubkey(&keypair.public), amount: Decimal::new(5, 1) },
+            TriangleOperation::Defi(DefiOperation::Insure {
+                triangle: root.append(0),
+                insured_value: Decimal::new(40, 0),
+                premium: Decimal::new(25, 1),
+                expiration_block: 90,
+            }),
+        ];
+        for (tag, operation) in operations.into_iter().enumerate() {
+            let tx = Transaction::signed(CHAIN_ID, operation, 5, Some(9), Decimal::new(25, 3), &keypair);
+            let bytes = tx.encode();
+            assert_eq!(bytes[41], tag as u8);
+            let decoded = Transaction::decode(&bytes).unwrap();
+            assert_eq!(decoded.hash(), tx.hash());
+            assert_eq!(decoded.encode(), bytes);
+            assert!(decoded.validate());
+        }
+    }
+
+    #[test]
+    fn test_reject_unknown_version_and_tag() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let mut bytes = Transaction::new(transfer(&keypair), 0, &keypair).encode();
+        bytes[33] = 9;
+        assert_eq!(Transaction::decode(&bytes).unwrap_err(), DecodeError::UnknownTag { kind: "operation", tag: 9 });
+        bytes[0] = 1;
+        assert_eq!(
+            Transaction::decode(&bytes).unwrap_err(),
+            DecodeError::UnsupportedVersion { kind: "transaction", version: 1 }
+        );
+    }
+
+    /// Pins the encoding of the genesis transaction, which is fully
+    /// deterministic. A change here is a consensus change.
+    #[test]
+    fn test_genesis_transaction_golden_vector() {
+        let tx = Transaction::new_genesis(crate::core::genesis::GenesisTriangle::new());
+        let bytes = tx.encode();
+        assert_eq!(hex::encode(&bytes[..34]), "02000000524549530000000000000000000000000000000000000000000000000000");
+        assert_eq!(bytes.len(), 4 + 4 + 8 + 1 + 16 + 1 + 6 * 16 + 32 + 64);
+        assert_eq!(format!("{:?}", tx.hash()), "0x759974fe4e8921e866333d4b0a037cccc1f86d7fa759ccceba282ee42b89f1f5");
+    }
+
+    #[test]
+    fn test_hash_commits_to_signer() {
+        let alice = Keypair::generate(&mut rand::thread_rng());
+        let bob = Keypair::generate(&mut rand::thread_rng());
+        let operation = transfer(&alice);
+        let by_alice = Transaction::new(operation.clone(), 0, &alice);
+        let by_bob = Transaction::new(operation, 0, &bob);
+        assert_ne!(by_alice.hash(), by_bob.hash());
+
+        let mut swapped = by_alice.clone();
+        swapped.public_key = bob.public;
+        swapped.signature = by_bob.signature;
+        assert!(!swapped.validate());
+    }
+
+    #[test]
+    fn test_merge_spends_parent_and_children() {
+        let keypair = Keypair::generate(&mut rand::thread_rng());
+        let parent = TriangleAddress::root().append(2);
+        let merge = Transaction::new(TriangleOperation::Merge { parent: parent.clone() }, 0, &keypair);
+        assert_eq!(merge.spends().unwrap(), vec![parent.clone(), parent.append(0), parent.append(1), parent.append(2)]);
+
+        let deepest = TriangleAddress::new(vec![1; crate::core::barycentric::MAX_DEPTH as usize]).unwrap();
+        let merge = Transaction::new(TriangleOperation::Merge { parent: deepest.clone() }, 0, &keypair);
+        assert_eq!(merge.spends(), Err(StateError::MaxDepthExceeded(deepest)));
+    }
+}
@@ -5,6 +5,10 @@ use crate::core::geometry::Point;
 use serde::{Deserialize, Serialize};
 use crate::core::hash::H256;
 
+/// Version of the canonical triangle encoding used by `Triangle::hash`.
+/// Version 0 is the original bincode encoding, see `Triangle::legacy_hash`.
+pub const TRIANGLE_HASH_VERSION: u8 = 1;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Triangle {
     pub a: Point,
@@ -36,12 +40,46 @@ impl Triangle {
         self.area() > dec!(0)
     }
 
+    /// The vertices with decimal scale normalized and sorted by (x, y), so
+    /// equal triangles have equal canonical forms however they were written.
+    pub fn canonical_vertices(&self) -> [Point; 3] {
+        let mut vertices = [&self.a, &self.b, &self.c]
+            .map(|point| Point::new(point.x.normalize(), point.y.normalize()));
+        vertices.sort_by_key(|point| (point.x, point.y));
+        vertices
+    }
+
+    /// Canonical id of the region covered by this triangle.
     pub fn hash(&self) -> H256 {
+        let mut bytes = Vec::with_capacity(1 + 6 * 16);
+        bytes.push(TRIANGLE_HASH_VERSION);
+        for point in self.canonical_vertices() {
+            bytes.extend_from_slice(&point.x.serialize());
+            bytes.extend_from_slice(&point.y.serialize());
+        }
+        blake3::hash(&bytes).into()
+    }
+
+    /// The id used before version 1: a hash of the bincode encoding, which
+    /// depends on decimal scale and vertex order.
+    pub fn legacy_hash(&self) -> H256 {
         let bytes = bincode::serialize(&self).unwrap();
         blake3::hash(&bytes).into()
     }
 }
 
+/// The current id of the triangle among `stored` that `id` names, under
+/// either the current or the legacy encoding. Legacy ids are recomputed from
+/// the stored triangles rather than remembered, so an id handed out before
+/// canonical hashing resolves the same way in every process, including
+/// after a restart.
+pub fn resolve_triangle_id<'a>(id: &H256, stored: impl IntoIterator<Item = &'a Triangle>) -> Option<H256> {
+    stored
+        .into_iter()
+        .find(|triangle| triangle.hash() == *id || triangle.legacy_hash() == *id)
+        .map(Triangle::hash)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,6 +125,40 @@ mod tests {
         assert_eq!(centroid.y, dec!(1));
     }
 
+    #[test]
+    fn test_hash_ignores_decimal_scale_and_vertex_order() {
+        let t = Triangle::new(
+            Point::new(dec!(0), dec!(0)),
+            Point::new(dec!(1), dec!(0)),
+            Point::new(dec!(0.5), dec!(0.75)),
+        );
+        let rescaled = Triangle::new(
+            Point::new(dec!(0.00), dec!(0)),
+            Point::new(dec!(1.0), dec!(0.000)),
+            Point::new(dec!(0.50), dec!(0.750)),
+        );
+        let rotated = Triangle::new(t.c.clone(), t.a.clone(), t.b.clone());
+
+        assert_ne!(t.legacy_hash(), rescaled.legacy_hash());
+        assert_eq!(t.hash(), rescaled.hash());
+        assert_eq!(t.hash(), rotated.hash());
+    }
+
+    #[test]
+    fn test_resolve_legacy_ids_from_stored_triangles() {
+        let t = Triangle::new(
+            Point::new(dec!(0), dec!(0)),
+            Point::new(dec!(1), dec!(0)),
+            Point::new(dec!(0), dec!(1)),
+        );
+        let other = Triangle::new(t.b.clone(), t.c.clone(), Point::new(dec!(1), dec!(1)));
+        // Only the stored triangles are needed, nothing recorded earlier.
+        let stored = [other.clone(), t.clone()];
+        assert_eq!(resolve_triangle_id(&t.legacy_hash(), &stored), Some(t.hash()));
+        assert_eq!(resolve_triangle_id(&t.hash(), &stored), Some(t.hash()));
+        assert_eq!(resolve_triangle_id(&t.legacy_hash(), &stored[..1]), None);
+    }
+
     #[test]
     fn test_triangle_is_valid() {
         let p1 = Point::new(dec!(0), dec!(0));
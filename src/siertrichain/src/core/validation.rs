@@ -47,13 +47,7 @@ mod tests {
     #[test]
     fn test_validate_fractal_triangle() {
         let genesis_tri = GenesisTriangle::new();
-        let fractal_tri = FractalTriangle::new(
-            genesis_tri,
-            TriangleState::Genesis,
-            0,
-            None,
-            TriangleAddress::root(),
-        );
+        let fractal_tri = FractalTriangle::new(genesis_tri, TriangleState::Genesis, TriangleAddress::root());
         assert!(validate_fractal_triangle(&fractal_tri, None));
     }
 }
@@ -1,4 +1,4 @@
-use crate::core::triangle::Triangle;
+use crate::core::triangle::{resolve_triangle_id, Triangle};
 use crate::wallet::address::Address;
 use crate::core::hash::H256;
 use rust_decimal::Decimal;
@@ -70,4 +70,22 @@ impl Exchange {
             }
         }
     }
+
+    /// The order book of a triangle, by current or legacy id.
+    pub fn order_book(&self, triangle_id: &H256) -> Option<&OrderBook> {
+        self.order_books.get(&self.resolve(triangle_id))
+    }
+
+    /// The key of the triangle `triangle_id` names, by current or legacy id.
+    fn resolve(&self, triangle_id: &H256) -> H256 {
+        if self.order_books.contains_key(triangle_id) {
+            return *triangle_id;
+        }
+        let stored = self
+            .order_books
+            .values()
+            .flat_map(|book| book.buy_orders.iter().chain(&book.sell_orders))
+            .map(|order| &order.triangle);
+        resolve_triangle_id(triangle_id, stored).unwrap_or(*triangle_id)
+    }
 }
@@ -1,3 +1,4 @@
+use crate::core::errors::ConfigError;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
@@ -15,8 +16,8 @@ pub enum HardwareSelection {
 }
 
 impl MiningConfig {
-    pub fn from_toml(path: &str) -> Result<Self, toml::de::Error> {
-        let toml_str = std::fs::read_to_string(path).unwrap();
-        toml::from_str(&toml_str)
+    pub fn from_toml(path: &str) -> Result<Self, ConfigError> {
+        let toml_str = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&toml_str)?)
     }
 }
@@ -1,72 +1,160 @@
+use crate::consensus::rules::is_valid_proof_of_work;
+use crate::consensus::weight::{transaction_weight, MAX_BLOCK_WEIGHT};
+use crate::core::address::TriangleAddress;
 use crate::core::block::{Block};
 use crate::core::blockchain::Blockchain;
+use crate::core::errors::BlockchainError;
 use crate::core::merkle::MerkleTree;
+use crate::core::state::WorldState;
+use crate::core::storage::{BlockStorage, InMemoryStorage, TransactionStorage};
+use crate::core::hash::H256;
+use crate::core::tokenomics::BlockReward;
 use crate::core::transaction::Transaction;
 use crate::mining::config::MiningConfig;
-use crate::core::hash::H256;
+use ed25519_dalek::Keypair;
+use rust_decimal::Decimal;
+use std::collections::HashSet;
 
 
-pub struct Miner {
+pub struct Miner<S = InMemoryStorage> {
     config: MiningConfig,
-    blockchain: Blockchain,
+    blockchain: Blockchain<S>,
+    /// Signs the coinbase; block rewards are paid to its public key.
+    keypair: Keypair,
+}
+
+/// A geometric proof together with the coinbase and Merkle root it implies,
+/// since the reward depends on the depth of the proof.
+struct ProofCandidate {
+    proof: TriangleAddress,
+    coinbase: Transaction,
+    merkle_root: H256,
 }
 
-impl Miner {
-    pub fn new(config: MiningConfig, blockchain: Blockchain) -> Self {
-        Self { config, blockchain }
+impl<S: BlockStorage + TransactionStorage> Miner<S> {
+    pub fn new(config: MiningConfig, blockchain: Blockchain<S>, keypair: Keypair) -> Self {
+        Self { config, blockchain, keypair }
     }
 
-    pub fn mine(&mut self) -> Block {
-        let mut candidate_block = self.generate_candidate_block();
-        self.find_geometric_proof(&mut candidate_block);
-        candidate_block
+    pub fn config(&self) -> &MiningConfig {
+        &self.config
     }
 
-    fn generate_candidate_block(&self) -> Block {
-        let triangle_transactions: Vec<Transaction> = Vec::new(); 
-        let last_block = self.blockchain.latest_block();
-        let merkle_root = MerkleTree::new(&triangle_transactions).get_root();
-        let height = last_block.header.height + 1;
-        Block::new(
-            last_block.hash(),
-            merkle_root,
-            self.blockchain.get_difficulty(),
-            height,
-            triangle_transactions,
-        )
+    pub fn blockchain(&self) -> &Blockchain<S> {
+        &self.blockchain
     }
 
-    fn find_geometric_proof(&self, block: &mut Block) {
-        let difficulty = self.config.difficulty_target;
-        let mut nonce: u64 = 0;
+    /// Gives access to the chain, e.g. to import the blocks returned by `mine`.
+    pub fn blockchain_mut(&mut self) -> &mut Blockchain<S> {
+        &mut self.blockchain
+    }
 
-        loop {
-            let mut active_triangles = self.blockchain.get_active_triangles();
+    /// Builds a block on the current tip and searches for a proof-of-work.
+    /// The block is returned as-is; it is not added to the chain.
+    pub fn mine(&mut self) -> Result<Block, BlockchainError> {
+        self.mine_transactions(Vec::new())
+    }
 
-            for fractal_triangle in &mut active_triangles {
-                if let Ok(children) = fractal_triangle.subdivide() {
-                    for child in children {
-                        let mut block_header = block.header.clone();
-                        block_header.nonce = nonce;
-                        block_header.geometric_proof = child.address.clone();
+    /// Like `mine`, including the transactions of `pending` in the given
+    /// order. A transaction that pays less than the base fee of the next
+    /// block, or would push it over the maximum block weight, is skipped
+    /// together with the later transactions of its signer. The caller,
+    /// usually through the mempool, makes sure they are valid together and
+    /// each signer's come in nonce order.
+    pub fn mine_transactions(&mut self, pending: Vec<Transaction>) -> Result<Block, BlockchainError> {
+        let mut candidate_block = self.generate_candidate_block(pending);
+        self.find_geometric_proof(&mut candidate_block)?;
+        Ok(candidate_block)
+    }
+
+    fn generate_candidate_block(&self, pending: Vec<Transaction>) -> Block {
+        let last_header = self.blockchain.latest_header();
+        let height = last_header.height + 1;
+        let tip = self.blockchain.tip();
+        let base_fee = self.blockchain.next_base_fee(&tip);
+        // Placeholder coinbase, replaced once the geometric proof is known.
+        // The final coinbase only differs in its fixed-size amount, so it
+        // weighs the same.
+        let coinbase = Transaction::new_coinbase(Decimal::ZERO, height, &self.keypair);
+        let mut weight = transaction_weight(&coinbase);
+        let mut triangle_transactions = vec![coinbase];
+        // Skip what does not fit, along with the later transactions of its
+        // signer, whose nonces would no longer follow on.
+        let mut skipped = HashSet::new();
+        for tx in pending.into_iter().filter(|tx| !tx.is_coinbase()) {
+            if skipped.contains(&tx.public_key.to_bytes()) {
+                continue;
+            }
+            let tx_weight = transaction_weight(&tx);
+            if tx.fee < base_fee || weight + tx_weight > MAX_BLOCK_WEIGHT {
+                if tx.uses_nonce() {
+                    skipped.insert(tx.public_key.to_bytes());
+                }
+                continue;
+            }
+            weight += tx_weight;
+            triangle_transactions.push(tx);
+        }
+        let merkle_root = MerkleTree::new(&triangle_transactions).get_root();
+        // Consensus requires a timestamp after the median time past.
+        let timestamp = self.blockchain.clock().now().max(self.blockchain.median_time_past(&tip) + 1);
+        Block::new(tip, merkle_root, timestamp, self.blockchain.get_bits(), base_fee, height, triangle_transactions)
+    }
 
-                        let header_bytes = bincode::serialize(&block_header).unwrap();
-                        let hash = blake3::hash(&header_bytes).into();
+    fn find_geometric_proof(&self, block: &mut Block) -> Result<(), BlockchainError> {
+        let candidates = self.candidate_proofs(block)?;
+        let mut nonce: u64 = 0;
 
-                        if self.validate_geometric_work(&hash, difficulty) {
-                            block.header = block_header;
-                            return;
-                        }
-                        nonce += 1;
-                    }
+        loop {
+            for candidate in &candidates {
+                block.header.nonce = nonce;
+                block.header.geometric_proof = candidate.proof.clone();
+                block.header.merkle_root = candidate.merkle_root;
+                if is_valid_proof_of_work(&block.header) {
+                    block.triangle_transactions[0] = candidate.coinbase.clone();
+                    return Ok(());
                 }
+                nonce += 1;
             }
         }
     }
 
+    /// The shallowest unclaimed triangles below every active triangle on the
+    /// active chain, each with the coinbase it pays.
+    fn candidate_proofs(&self, block: &Block) -> Result<Vec<ProofCandidate>, BlockchainError> {
+        let state = self.blockchain.state();
+        let mut proofs: Vec<TriangleAddress> =
+            state.active_leaves().flat_map(|leaf| shallowest_unclaimed(state, leaf)).collect();
+        if proofs.is_empty() && !state.is_claimed(&TriangleAddress::root()) {
+            proofs.push(TriangleAddress::root());
+        }
+
+        let mut transactions = block.triangle_transactions.clone();
+        let fees: Vec<Decimal> = transactions[1..].iter().map(|tx| tx.fee).collect();
+        let candidates = proofs
+            .into_iter()
+            .map(|proof| {
+                let amount = BlockReward::new(proof.depth(), block.header.base_fee, &fees).coinbase_amount();
+                let coinbase = Transaction::new_coinbase(amount, block.header.height, &self.keypair);
+                transactions[0] = coinbase.clone();
+                let merkle_root = MerkleTree::new(&transactions).get_root();
+                ProofCandidate { proof, coinbase, merkle_root }
+            })
+            .collect();
+        Ok(candidates)
+    }
+}
 
-    fn validate_geometric_work(&self, hash: &H256, difficulty: u64) -> bool {
-        let hash_value = u64::from_le_bytes(hash.to_bytes()[..8].try_into().unwrap());
-        hash_value < u64::MAX / difficulty
+/// Up to three unclaimed triangles below `leaf`, from the shallowest level
+/// that still has any, as those pay the highest reward.
+fn shallowest_unclaimed(state: &WorldState, leaf: &TriangleAddress) -> Vec<TriangleAddress> {
+    let mut level = vec![leaf.clone()];
+    loop {
+        level = level.iter().flat_map(|address| (0..3).filter_map(move |i| address.child(i).ok())).collect();
+        let unclaimed: Vec<TriangleAddress> =
+            level.iter().filter(|address| !state.is_claimed(address)).take(3).cloned().collect();
+        if !unclaimed.is_empty() || level.is_empty() {
+            return unclaimed;
+        }
     }
 }
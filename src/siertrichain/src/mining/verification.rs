@@ -1,7 +1,16 @@
+use crate::consensus::rules::is_valid_proof_of_work;
 use crate::core::block::Block;
+use crate::core::errors::ValidationError;
 
-// This function will provide a fast way to verify geometric work without full recalculation.
-pub fn fast_verify(_block: &Block) -> bool {
-    // ... implement a fast verification algorithm
-    true // Placeholder
+/// Checks the parts of a block that need nothing but its header: the
+/// proof-of-work against the block's own target and that the mined
+/// triangle is not a void. `validate_block` covers the rest.
+pub fn fast_verify(block: &Block) -> Result<(), ValidationError> {
+    if !is_valid_proof_of_work(&block.header) {
+        return Err(ValidationError::InsufficientProofOfWork);
+    }
+    if block.header.geometric_proof.is_void() {
+        return Err(ValidationError::VoidProof(block.header.geometric_proof.clone()));
+    }
+    Ok(())
 }
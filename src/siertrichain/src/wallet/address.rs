@@ -3,6 +3,7 @@ use bs58;
 use thiserror::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryInto;
+use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Address {
@@ -27,6 +28,36 @@ impl Address {
         Self { data }
     }
 
+    /// The account of an on-chain module such as the staking pool. Nobody
+    /// holds a key for it; only the module's operations move its funds.
+    pub fn for_module(name: &str) -> Self {
+        let mut data = [0u8; 33];
+        data[0] = 1; // Version byte
+        data[1..].copy_from_slice(blake3::hash(name.as_bytes()).as_bytes());
+        Self { data }
+    }
+
+    /// Whether this is the account of an on-chain module.
+    pub fn is_module(&self) -> bool {
+        self.data[0] == 1
+    }
+
+    /// The key an account was derived from, `None` for module accounts.
+    pub fn public_key(&self) -> Option<PublicKey> {
+        if self.data[0] != 0 {
+            return None;
+        }
+        PublicKey::from_bytes(&self.data[1..]).ok()
+    }
+
+    pub fn from_bytes(data: [u8; 33]) -> Self {
+        Self { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 33] {
+        &self.data
+    }
+
     pub fn to_base58(&self) -> String {
         bs58::encode(&self.data).into_string()
     }
@@ -42,6 +73,12 @@ impl Address {
     }
 }
 
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_base58())
+    }
+}
+
 impl Serialize for Address {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
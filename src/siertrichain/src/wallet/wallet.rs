@@ -1,5 +1,7 @@
 use crate::core::transaction::{Transaction, TriangleOperation};
+use crate::wallet::address::Address;
 use ed25519_dalek::{Keypair, PublicKey};
+use rust_decimal::Decimal;
 
 
 pub struct Wallet {
@@ -21,11 +23,26 @@ impl Wallet {
         self.keypair.public
     }
 
-    pub fn create_transaction(&self, from: crate::core::address::TriangleAddress, to: PublicKey) -> Transaction {
+    /// Signs a transfer as this wallet's transaction number `nonce`,
+    /// offering `fee`. It must cover the base fee of the block it lands in,
+    /// see `Blockchain::next_base_fee`; anything above is the miner's tip.
+    pub fn create_transaction(
+        &self,
+        from: crate::core::address::TriangleAddress,
+        to: PublicKey,
+        nonce: u64,
+        fee: Decimal,
+    ) -> Transaction {
         let operation = TriangleOperation::Transfer {
             from,
             to,
         };
-        Transaction::new(operation, &self.keypair)
+        Transaction::with_fee(operation, nonce, fee, &self.keypair)
+    }
+
+    /// Signs a payment of `amount` native tokens to `to`. The wallet must
+    /// hold `amount` plus `fee` when it is mined.
+    pub fn create_token_transfer(&self, to: Address, amount: Decimal, nonce: u64, fee: Decimal) -> Transaction {
+        Transaction::with_fee(TriangleOperation::TokenTransfer { to, amount }, nonce, fee, &self.keypair)
     }
 }
@@ -1,21 +1,189 @@
+use siertrichain::consensus::weight::{block_weight, transaction_weight, MAX_BLOCK_WEIGHT};
 use siertrichain::mining::miner::Miner;
 use siertrichain::mining::config::MiningConfig;
 use siertrichain::core::blockchain::Blockchain;
+use siertrichain::core::clock::MockClock;
+use siertrichain::core::tokenomics::calculate_mining_reward;
+use siertrichain::core::transaction::TriangleOperation;
+use siertrichain::core::address::TriangleAddress;
+use siertrichain::core::barycentric::cartesian_children;
+use siertrichain::core::transaction::Transaction;
+use siertrichain::wallet::address::Address;
+use siertrichain::wallet::wallet::Wallet;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use rust_decimal_macros::dec;
 
-#[test]
-fn test_mining_reward() {
-    // This is a placeholder for a more comprehensive test.
-    let config = MiningConfig {
+fn test_config() -> MiningConfig {
+    MiningConfig {
         difficulty_target: 1,
         target_triangle_depth: 1,
         mining_reward: 50,
         hardware: siertrichain::mining::config::HardwareSelection::Cpu,
+    }
+}
+
+fn reward_keypair() -> Keypair {
+    Keypair::generate(&mut rand::thread_rng())
+}
+
+/// Owns the genesis triangle of the chains built by `owned_chain`.
+fn owner_keypair() -> Keypair {
+    let secret = SecretKey::from_bytes(&[7u8; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+fn owned_chain() -> Blockchain {
+    Blockchain::with_genesis_owner(1, owner_keypair().public)
+}
+
+#[test]
+fn test_mining_reward() {
+    let config = test_config();
+    let keypair = reward_keypair();
+    let public_key = keypair.public;
+
+    let blockchain = Blockchain::with_difficulty(config.difficulty_target);
+    let mut miner = Miner::new(config, blockchain, keypair);
+
+    let block = miner.mine().unwrap();
+    let expected = calculate_mining_reward(block.header.geometric_proof.depth());
+    match &block.triangle_transactions[0].operation {
+        TriangleOperation::Coinbase { recipient, amount, height } => {
+            assert_eq!(recipient, &public_key);
+            assert_eq!(*amount, expected);
+            assert_eq!(*height, 1);
+        }
+        other => panic!("expected a coinbase, got {:?}", other),
+    }
+
+    miner.blockchain_mut().add_block(block).unwrap();
+    assert_eq!(miner.blockchain().supply().issued, expected);
+}
+
+#[test]
+fn test_mined_block_is_imported_unchanged() {
+    let config = test_config();
+    let mut miner = Miner::new(config, Blockchain::with_difficulty(16), reward_keypair());
+
+    let block = miner.mine().unwrap();
+    let hash = block.hash();
+    let nonce = block.header.nonce;
+    let proof = block.header.geometric_proof.clone();
+
+    // Another node with the same genesis accepts the block as mined.
+    let mut peer = Blockchain::with_difficulty(16);
+    peer.add_block(block.clone()).unwrap();
+    assert_eq!(peer.tip(), hash);
+    assert_eq!(peer.latest_header().nonce, nonce);
+    assert_eq!(peer.latest_header().geometric_proof, proof);
+
+    miner.blockchain_mut().add_block(block).unwrap();
+    assert_eq!(miner.blockchain().tip(), hash);
+}
+
+#[test]
+fn test_miner_stamps_blocks_with_its_clock() {
+    let clock = MockClock::new(1_800_000_000);
+    let blockchain = Blockchain::with_difficulty(1).with_clock(clock.clone());
+    let mut miner = Miner::new(test_config(), blockchain, reward_keypair());
+
+    let first = miner.mine().unwrap();
+    assert_eq!(first.header.timestamp, 1_800_000_000);
+    miner.blockchain_mut().add_block(first).unwrap();
+
+    clock.advance(90);
+    let second = miner.mine().unwrap();
+    assert_eq!(second.header.timestamp, 1_800_000_090);
+    miner.blockchain_mut().add_block(second).unwrap();
+}
+
+#[test]
+fn test_miner_collects_tips_above_base_fee() {
+    let owner = Wallet::new(owner_keypair());
+    let keypair = reward_keypair();
+    let public_key = keypair.public;
+    // The owner pays the fees out of a first block reward.
+    let mut funder = Miner::new(test_config(), owned_chain(), owner_keypair());
+    let funding = funder.mine().unwrap();
+    let funds = calculate_mining_reward(funding.header.geometric_proof.depth());
+    let mut chain = owned_chain();
+    chain.add_block(funding).unwrap();
+    let mut miner = Miner::new(test_config(), chain, keypair);
+    let base_fee = miner.blockchain().next_base_fee(&miner.blockchain().tip());
+
+    let root = TriangleAddress::root();
+    let paid = owner.create_transaction(root.clone(), public_key, 0, base_fee + dec!(0.5));
+    let cheap = owner.create_transaction(root.append(1), public_key, 1, base_fee / dec!(2));
+    let block = miner.mine_transactions(vec![paid, cheap]).unwrap();
+    assert_eq!(block.triangle_transactions.len(), 2);
+    assert_eq!(block.header.base_fee, base_fee);
+
+    let subsidy = calculate_mining_reward(block.header.geometric_proof.depth());
+    match &block.triangle_transactions[0].operation {
+        TriangleOperation::Coinbase { amount, .. } => assert_eq!(*amount, subsidy + dec!(0.5)),
+        other => panic!("expected a coinbase, got {:?}", other),
+    }
+    miner.blockchain_mut().add_block(block).unwrap();
+    assert_eq!(miner.blockchain().supply().burned, base_fee);
+    assert_eq!(miner.blockchain().state().owner_of(&root), Some(&public_key));
+    let balances = miner.blockchain().state().balances();
+    assert_eq!(balances.balance(&Address::from_pubkey(&owner.address())), funds - base_fee - dec!(0.5));
+    assert_eq!(balances.balance(&Address::from_pubkey(&public_key)), subsidy + dec!(0.5));
+}
+
+#[test]
+fn test_miner_fills_blocks_up_to_max_weight() {
+    let owner = Wallet::new(owner_keypair());
+    let mut miner = Miner::new(test_config(), owned_chain(), reward_keypair());
+    let tx = owner.create_transaction(TriangleAddress::root(), owner.address(), 0, dec!(0.01));
+    let offered = (MAX_BLOCK_WEIGHT / transaction_weight(&tx)) as usize + 1;
+
+    let block = miner.mine_transactions(vec![tx.clone(); offered]).unwrap();
+    assert!(block_weight(&block) <= MAX_BLOCK_WEIGHT);
+    // Only as many as fit next to the coinbase are taken.
+    let room = MAX_BLOCK_WEIGHT - transaction_weight(&block.triangle_transactions[0]);
+    let fitting = room / transaction_weight(&tx);
+    assert_eq!(block.header.transaction_count as u64, fitting);
+}
+
+#[test]
+fn test_miner_skips_transactions_that_do_not_fit() {
+    let owner = owner_keypair();
+    let mut miner = Miner::new(test_config(), owned_chain(), reward_keypair());
+    let root = TriangleAddress::root();
+    let light = |keypair: &Keypair, nonce| {
+        let operation = TriangleOperation::Transfer { from: root.append(1), to: keypair.public };
+        Transaction::with_fee(operation, nonce, dec!(0.01), keypair)
     };
+    let filler = reward_keypair();
+    let heavy = Transaction::with_fee(
+        TriangleOperation::Subdivide { parent: root.clone(), children: cartesian_children(&root).unwrap() },
+        0,
+        dec!(5),
+        &owner,
+    );
+    let owner_next = light(&owner, 1);
+    let other = light(&reward_keypair(), 0);
 
-    let blockchain = Blockchain::new();
-    let mut miner = Miner::new(config, blockchain);
+    // Leave room for a light transaction, but not for the heavy one.
+    let coinbase_weight = transaction_weight(&Transaction::new_coinbase(dec!(0), 1, &owner));
+    let room = MAX_BLOCK_WEIGHT - coinbase_weight;
+    let fillers = (room - transaction_weight(&other)) / transaction_weight(&light(&filler, 0));
+    let mut pending: Vec<Transaction> = (0..fillers).map(|nonce| light(&filler, nonce)).collect();
+    assert!(room - block_weight_of(&pending) < transaction_weight(&heavy));
+    pending.extend([heavy.clone(), owner_next.clone(), other.clone()]);
+
+    let block = miner.mine_transactions(pending).unwrap();
+    let included: Vec<_> = block.triangle_transactions.iter().map(|tx| *tx.hash()).collect();
+    assert!(block_weight(&block) <= MAX_BLOCK_WEIGHT);
+    assert_eq!(included.len() as u64, fillers + 2);
+    assert!(included.contains(other.hash()));
+    assert!(!included.contains(heavy.hash()));
+    // The signer's next nonce would not follow on without the heavy one.
+    assert!(!included.contains(owner_next.hash()));
+}
 
-    let _block = miner.mine();
-    // In a real implementation, we would check the coinbase transaction amount.
-    assert!(true);
+fn block_weight_of(transactions: &[Transaction]) -> u64 {
+    transactions.iter().map(transaction_weight).sum()
 }